@@ -3,6 +3,32 @@ use std::env;
 use std::path::PathBuf;
 use embed_resource::CompilationResult;
 use fs_extra::dir::{copy, CopyOptions};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+// Files of the bundled module whose SHA-256 is embedded for `verify-module-integrity`
+const VERIFIED_MODULE_FILES: [&str; 2] = ["AudioDeviceCmdlets.psd1", "AudioDeviceCmdlets.dll"];
+
+// Writes `module_hashes.rs` to OUT_DIR with the expected hash of each verified module file.
+// Files missing at build time are left out, so verification reports them instead of failing the build.
+fn embed_module_hashes(src_modules_path: &Path) {
+    let mut entries = String::new();
+    for file in VERIFIED_MODULE_FILES {
+        let path = src_modules_path.join("AudioDeviceCmdlets").join(file);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let hash: String = Sha256::digest(&bytes).iter().map(|b| format!("{:02x}", b)).collect();
+                entries.push_str(&format!("    (\"{}\", \"{}\"),\n", file, hash));
+            }
+            Err(e) => eprintln!("Not embedding hash of {}: {}", path.display(), e),
+        }
+    }
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("module_hashes.rs");
+    let content = format!("pub const EXPECTED_MODULE_HASHES: &[(&str, &str)] = &[\n{}];\n", entries);
+    fs::write(&out_path, content).expect("Failed to write module_hashes.rs");
+}
 
 fn main() {
     let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
@@ -39,6 +65,8 @@ fn main() {
     let target_dir = PathBuf::from(&project_dir).join("target").join(profile);
     let dest_modules_path = target_dir.join("modules");
 
+    embed_module_hashes(&src_modules_path);
+
     if src_modules_path.exists() {
         println!("cargo:rerun-if-changed=modules"); // Rerun if modules content changes
         let mut options = CopyOptions::new();
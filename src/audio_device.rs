@@ -1,23 +1,46 @@
-use log::{error, info};
+use crate::config::{DeviceFormat, SwitchLimitPolicy};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::os::windows::process::CommandExt; // Import the extension trait
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 use std::process::Command; // Import logging macros
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 // use windows::core; // Keep commented unless needed elsewhere
 // use windows::core::{GUID, PCWSTR}; // Remove unused GUID, PCWSTR
-use windows::Win32::System::Com::StructuredStorage::PropVariantClear;
+use windows::Win32::System::Com::StructuredStorage::{PROPVARIANT, PropVariantClear};
 // Import PCWSTR for wide strings
 use windows::{
     Win32::{
-        Foundation::PROPERTYKEY,
+        Foundation::{PROPERTYKEY, RPC_E_CHANGED_MODE, S_OK},
         // Foundation::SysAllocStringLen, // Removed unused import
         Media::Audio::{
+            DEVICE_STATE, // Device state bit mask
             DEVICE_STATE_ACTIVE, // Filter for active devices
-            // ERole,               // Removed - No longer needed
+            DEVICE_STATE_DISABLED,
+            DEVICE_STATE_NOTPRESENT,
+            DEVICE_STATE_UNPLUGGED,
+            DEVICE_STATEMASK_ALL, // Every device regardless of state (diagnostics)
+            EDataFlow,
+            ERole,
+            AUDCLNT_E_UNSUPPORTED_FORMAT,
+            AUDCLNT_SHAREMODE_EXCLUSIVE,
+            AUDCLNT_SHAREMODE_SHARED,
+            Endpoints::{IAudioEndpointVolume, IAudioMeterInformation}, // Volume and mute control, peak meter
+            IAudioClient, // Capture stream that keeps the peak meter running, format checks
             IMMDevice, // Removed unused IMMEndpoint
             IMMDeviceCollection,
             IMMDeviceEnumerator,
             MMDeviceEnumerator, // Device enumerator
-            // eCommunications,    // Removed - No longer needed
-            // eConsole,           // Removed - No longer needed
+            WAVEFORMATEX,
+            WAVEFORMATEXTENSIBLE,
+            WAVEFORMATEXTENSIBLE_0,
+            eCommunications,
+            eConsole,
+            eMultimedia,
             eRender,
             eCapture, // Added for input devices
         },
@@ -25,20 +48,171 @@ use windows::{
             CLSCTX_ALL,
             COINIT_MULTITHREADED, // COM initialization flags
             // IUnknown, // Moved to windows::core
+            APTTYPE,
+            APTTYPEQUALIFIER,
             CoCreateInstance,
+            CoGetApartmentType,
             CoInitializeEx,
+            CoTaskMemFree,
             CoUninitialize,
         },
         UI::Shell::PropertiesSystem::IPropertyStore, // For device properties
     },
-    core::{PWSTR, Result}, // Keep Result for list_output_devices
+    core::{GUID, HSTRING, PWSTR, Result}, // Keep Result for list_output_devices
 }; // For converting &str to wide strings
 
 // Define a structure to hold device information
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct AudioDevice {
     pub id: String,
     pub name: String,
+    pub form_factor: FormFactor,
+    pub adapter_name: Option<String>, // The audio controller, e.g. "Realtek(R) Audio" (PKEY_DeviceInterface_FriendlyName)
+    pub container_id: Option<String>, // Shared by the render and capture endpoints of one physical device
+    pub state: DeviceState,
+}
+
+/// Devices are the same if their endpoint IDs are; the other fields are metadata read at some
+/// point in time (the state changes, a driver update may rename the device).
+impl PartialEq for AudioDevice {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for AudioDevice {}
+
+/// Endpoint state (`DEVICE_STATE_*`). Only active devices can be switched to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceState {
+    Active,
+    Disabled,
+    NotPresent,
+    Unplugged,
+    Unknown,
+}
+
+impl DeviceState {
+    fn from_raw(state: DEVICE_STATE) -> Self {
+        match state {
+            DEVICE_STATE_ACTIVE => DeviceState::Active,
+            DEVICE_STATE_DISABLED => DeviceState::Disabled,
+            DEVICE_STATE_NOTPRESENT => DeviceState::NotPresent,
+            DEVICE_STATE_UNPLUGGED => DeviceState::Unplugged,
+            _ => DeviceState::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `pad` so the state can be aligned in tables
+        f.pad(match self {
+            DeviceState::Active => "active",
+            DeviceState::Disabled => "disabled",
+            DeviceState::NotPresent => "not present",
+            DeviceState::Unplugged => "unplugged",
+            DeviceState::Unknown => "unknown",
+        })
+    }
+}
+
+/// Physical form factor of an endpoint (`PKEY_AudioEndpoint_FormFactor`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormFactor {
+    RemoteNetworkDevice,
+    Speakers,
+    LineLevel,
+    Headphones,
+    Microphone,
+    Headset,
+    Handset,
+    DigitalPassthrough,
+    Spdif,
+    DigitalAudioDisplay,
+    #[default]
+    Unknown,
+}
+
+impl FormFactor {
+    /// Maps the raw `EndpointFormFactor` value.
+    fn from_raw(value: u32) -> Self {
+        match value {
+            0 => FormFactor::RemoteNetworkDevice,
+            1 => FormFactor::Speakers,
+            2 => FormFactor::LineLevel,
+            3 => FormFactor::Headphones,
+            4 => FormFactor::Microphone,
+            5 => FormFactor::Headset,
+            6 => FormFactor::Handset,
+            7 => FormFactor::DigitalPassthrough,
+            8 => FormFactor::Spdif,
+            9 => FormFactor::DigitalAudioDisplay,
+            _ => FormFactor::Unknown,
+        }
+    }
+
+    /// Whether the endpoint is meant for voice communication (a headset or handset).
+    pub fn is_communications(self) -> bool {
+        matches!(self, FormFactor::Headset | FormFactor::Handset)
+    }
+}
+
+/// Direction of an audio endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceFlow {
+    Output,
+    Input,
+}
+
+impl DeviceFlow {
+    fn to_data_flow(self) -> EDataFlow {
+        match self {
+            DeviceFlow::Output => eRender,
+            DeviceFlow::Input => eCapture,
+        }
+    }
+
+    /// Converts a Core Audio data flow; `eAll` has no single-flow equivalent.
+    pub fn from_data_flow(data_flow: EDataFlow) -> Option<Self> {
+        if data_flow == eRender {
+            Some(DeviceFlow::Output)
+        } else if data_flow == eCapture {
+            Some(DeviceFlow::Input)
+        } else {
+            None
+        }
+    }
+}
+
+/// Windows default-device role. Each flow has a separate default per role.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioRole {
+    Console,
+    Multimedia,
+    Communications,
+}
+
+impl AudioRole {
+    pub const ALL: [AudioRole; 3] = [
+        AudioRole::Console,
+        AudioRole::Multimedia,
+        AudioRole::Communications,
+    ];
+
+    fn to_erole(self) -> ERole {
+        match self {
+            AudioRole::Console => eConsole,
+            AudioRole::Multimedia => eMultimedia,
+            AudioRole::Communications => eCommunications,
+        }
+    }
+
+    /// Converts a Core Audio role.
+    pub fn from_erole(role: ERole) -> Option<Self> {
+        AudioRole::ALL.into_iter().find(|r| r.to_erole() == role)
+    }
 }
 
 // PKEY_Device_FriendlyName
@@ -47,11 +221,249 @@ const PKEY_DEVICE_FRIENDLY_NAME: PROPERTYKEY = PROPERTYKEY {
     pid: 14,
 };
 
-/// Enumerates active audio output (rendering) devices.
-pub fn list_output_devices() -> Result<Vec<AudioDevice>> {
+// PKEY_Device_ContainerId
+const PKEY_DEVICE_CONTAINER_ID: PROPERTYKEY = PROPERTYKEY {
+    fmtid: windows::core::GUID::from_u128(0x8c7ed206_3f8a_4827_b3ab_ae9e1faefc6c),
+    pid: 2,
+};
+
+// PKEY_DeviceInterface_FriendlyName
+const PKEY_DEVICE_INTERFACE_FRIENDLY_NAME: PROPERTYKEY = PROPERTYKEY {
+    fmtid: windows::core::GUID::from_u128(0x026e516e_b814_414b_83cd_856d6fef4822),
+    pid: 2,
+};
+
+// PKEY_AudioEndpoint_FormFactor
+const PKEY_AUDIO_ENDPOINT_FORM_FACTOR: PROPERTYKEY = PROPERTYKEY {
+    fmtid: windows::core::GUID::from_u128(0x1da5d803_d492_4edd_8c23_e0c0ffee7f0e),
+    pid: 0,
+};
+
+/// Decodes a device ID from `GetId`, sanitizing invalid UTF-16 (then the flag is `false`).
+pub fn decode_device_id(wide: &[u16]) -> (String, bool) {
+    match String::from_utf16(wide) {
+        Ok(id) => (id, true),
+        Err(_) => (String::from_utf16_lossy(wide), false),
+    }
+}
+
+// Frees the value of an optional property. A failure only leaks the value, so it is logged
+// instead of failing the read, which would drop the whole device from the list
+unsafe fn clear_optional_property(prop_variant: &PROPVARIANT, property: &str) {
+    if let Err(e) = unsafe { PropVariantClear(prop_variant as *const _ as *mut _) } {
+        warn!("Failed to clear the {} property value: {}", property, e);
+    }
+}
+
+/// Reads the endpoint ID and friendly name of a device.
+/// Unreadable values are returned as an empty ID or the "Unknown Name"/"Invalid Name" placeholders.
+/// An ID that isn't valid UTF-16 is kept in sanitized form (invalid units replaced by U+FFFD) so
+/// the device doesn't vanish.
+unsafe fn read_device(device: &IMMDevice) -> Result<AudioDevice> {
     unsafe {
-        // Initialize COM for this thread
-        let _ = CoInitializeEx(None, COINIT_MULTITHREADED); // Use multithreaded apartment
+        let id_pwstr: PWSTR = device.GetId()?;
+        let (id, id_is_exact) = decode_device_id(id_pwstr.as_wide()); // Convert PWSTR to String
+        if !id_is_exact {
+            warn!(
+                "Device ID is not valid UTF-16 (raw: {:04X?}); using sanitized ID '{}'",
+                id_pwstr.as_wide(),
+                id
+            );
+        }
+        windows::Win32::System::Com::CoTaskMemFree(Some(id_pwstr.as_ptr() as *mut _)); // Free the memory allocated by GetId
+
+        // Get the property store for the device
+        let properties: IPropertyStore =
+            device.OpenPropertyStore(windows::Win32::System::Com::STGM_READ)?;
+
+        // Get the friendly name property
+        let prop_variant = properties.GetValue(&PKEY_DEVICE_FRIENDLY_NAME)?;
+
+        // Extract the string value (PWSTR) from the PROPVARIANT
+        // prop_variant.Anonymous.Anonymous.vt holds the type, should be VT_LPWSTR
+        // prop_variant.Anonymous.Anonymous.Anonymous holds the data
+        let name = if prop_variant.Anonymous.Anonymous.vt
+            == windows::Win32::System::Variant::VT_LPWSTR
+        {
+            prop_variant
+                .Anonymous
+                .Anonymous
+                .Anonymous
+                .pwszVal
+                .to_string()
+                .unwrap_or_else(|_| "Invalid Name".to_string())
+        } else {
+            "Unknown Name".to_string()
+        };
+
+        // Important: Need to free the PROPVARIANT memory
+        // PropVariantClear is often in Com::StructuredStorage or just Com
+        PropVariantClear((&prop_variant) as *const _ as *mut _)?;
+
+        // Get the form factor (a VT_UI4); missing or unexpected values map to Unknown
+        let form_factor = match properties.GetValue(&PKEY_AUDIO_ENDPOINT_FORM_FACTOR) {
+            Ok(prop_variant) => {
+                let form_factor = if prop_variant.Anonymous.Anonymous.vt
+                    == windows::Win32::System::Variant::VT_UI4
+                {
+                    FormFactor::from_raw(prop_variant.Anonymous.Anonymous.Anonymous.ulVal)
+                } else {
+                    FormFactor::Unknown
+                };
+                clear_optional_property(&prop_variant, "form factor");
+                form_factor
+            }
+            Err(_) => FormFactor::Unknown,
+        };
+
+        // Get the adapter name (a VT_LPWSTR); missing for some virtual endpoints
+        let adapter_name = match properties.GetValue(&PKEY_DEVICE_INTERFACE_FRIENDLY_NAME) {
+            Ok(prop_variant) => {
+                let adapter_name = if prop_variant.Anonymous.Anonymous.vt
+                    == windows::Win32::System::Variant::VT_LPWSTR
+                {
+                    prop_variant.Anonymous.Anonymous.Anonymous.pwszVal.to_string().ok()
+                } else {
+                    None
+                };
+                clear_optional_property(&prop_variant, "adapter name");
+                adapter_name
+            }
+            Err(_) => None,
+        };
+
+        // Get the container ID (a VT_CLSID); missing for some virtual endpoints
+        let container_id = match properties.GetValue(&PKEY_DEVICE_CONTAINER_ID) {
+            Ok(prop_variant) => {
+                let guid = prop_variant.Anonymous.Anonymous.Anonymous.puuid;
+                let container_id = if prop_variant.Anonymous.Anonymous.vt
+                    == windows::Win32::System::Variant::VT_CLSID
+                    && !guid.is_null()
+                {
+                    Some(format!("{:?}", *guid))
+                } else {
+                    None
+                };
+                clear_optional_property(&prop_variant, "container ID");
+                container_id
+            }
+            Err(_) => None,
+        };
+
+        let state = device.GetState().map_or(DeviceState::Unknown, DeviceState::from_raw);
+
+        Ok(AudioDevice { id, name, form_factor, adapter_name, container_id, state })
+    }
+}
+
+/// Keeps COM initialized (multithreaded apartment) on the current thread while alive.
+///
+/// `CoInitializeEx` returns `S_OK` when it initialized COM and `S_FALSE` when COM was already
+/// initialized with the same apartment. Both increment the thread's COM reference count and must be
+/// balanced by `CoUninitialize`, so the guard uninitializes in both cases. If the thread already
+/// joined a different apartment (`RPC_E_CHANGED_MODE`), COM is usable but this call did not
+/// initialize anything, so the guard must not uninitialize.
+pub struct ComGuard {
+    initialized: bool,
+}
+
+impl ComGuard {
+    /// Initializes COM for the current thread. Fails only if COM is unusable on this thread.
+    pub fn new() -> Result<ComGuard> {
+        let hr = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) };
+        if hr == RPC_E_CHANGED_MODE {
+            return Ok(ComGuard { initialized: false });
+        }
+        hr.ok()?;
+        Ok(ComGuard { initialized: true })
+    }
+}
+
+impl Drop for ComGuard {
+    fn drop(&mut self) {
+        if self.initialized {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+thread_local! {
+    // Set by ensure_com_initialized; keeps COM initialized until the thread exits
+    static THREAD_COM: RefCell<Option<ComGuard>> = const { RefCell::new(None) };
+}
+
+/// Initializes COM for the calling thread once and keeps it initialized until the thread exits.
+/// For threads that don't own a `ComGuard`, such as the main thread running the tray or a
+/// command-line query. Later calls on the same thread do nothing.
+pub fn ensure_com_initialized() -> Result<()> {
+    THREAD_COM.with(|com| {
+        let mut com = com.borrow_mut();
+        if com.is_none() {
+            *com = Some(ComGuard::new()?);
+        }
+        Ok(())
+    })
+}
+
+/// Fails with `CO_E_NOTINITIALIZED` unless COM is initialized on the calling thread. The device
+/// functions in this module don't initialize COM themselves: the thread that calls them owns
+/// the initialization (a `ComGuard` or `ensure_com_initialized`), so a nested init/uninit pair
+/// can't leave the owner with COM torn down underneath it.
+pub fn require_com() -> Result<()> {
+    let mut apartment = APTTYPE::default();
+    let mut qualifier = APTTYPEQUALIFIER::default();
+    // Also succeeds on a thread in the implicit MTA, where COM is usable without initializing
+    unsafe { CoGetApartmentType(&mut apartment, &mut qualifier) }
+}
+
+// Enumeration can fail for a moment while a driver resets, so it is retried this often
+const ENUMERATION_ATTEMPTS: u32 = 3;
+const ENUMERATION_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Calls `operation` up to `attempts` times, sleeping `delay` between attempts, and returns the
+/// first success or the last error. Each failed attempt that is retried is logged.
+pub fn retry_transient<T, E: std::fmt::Display>(
+    description: &str,
+    attempts: u32,
+    delay: Duration,
+    mut operation: impl FnMut() -> std::result::Result<T, E>,
+) -> std::result::Result<T, E> {
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(e) if attempt < attempts => {
+                warn!(
+                    "{} failed (attempt {} of {}): {}; retrying in {} ms",
+                    description,
+                    attempt,
+                    attempts,
+                    e,
+                    delay.as_millis()
+                ); // Log warning
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Enumerates the devices for the given data flow whose state is in the `states` mask,
+/// retrying transient COM failures.
+fn list_devices(data_flow: EDataFlow, states: DEVICE_STATE) -> Result<Vec<AudioDevice>> {
+    let description = match DeviceFlow::from_data_flow(data_flow) {
+        Some(flow) => format!("Enumerating {:?} devices", flow),
+        None => "Enumerating devices".to_string(),
+    };
+    retry_transient(&description, ENUMERATION_ATTEMPTS, ENUMERATION_RETRY_DELAY, || {
+        enumerate_devices(data_flow, states)
+    })
+}
+
+// One enumeration attempt for list_devices
+fn enumerate_devices(data_flow: EDataFlow, states: DEVICE_STATE) -> Result<Vec<AudioDevice>> {
+    unsafe {
+        require_com()?;
 
         let mut devices = Vec::new();
 
@@ -59,269 +471,610 @@ pub fn list_output_devices() -> Result<Vec<AudioDevice>> {
         let enumerator: IMMDeviceEnumerator =
             CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
 
-        // Get the collection of active rendering devices
-        let collection: IMMDeviceCollection =
-            enumerator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)?;
+        // Get the collection of devices in the requested states
+        let collection: IMMDeviceCollection = enumerator.EnumAudioEndpoints(data_flow, states)?;
 
         let count = collection.GetCount()?;
 
         for i in 0..count {
             let device: IMMDevice = collection.Item(i)?;
-            let id_pwstr: PWSTR = device.GetId()?;
-            let id = id_pwstr.to_string().unwrap_or_default(); // Convert PWSTR to String
-            windows::Win32::System::Com::CoTaskMemFree(Some(id_pwstr.as_ptr() as *mut _)); // Free the memory allocated by GetId
-
-            // Get the property store for the device
-            let properties: IPropertyStore =
-                device.OpenPropertyStore(windows::Win32::System::Com::STGM_READ)?;
-
-            // Get the friendly name property
-            let prop_variant = properties.GetValue(&PKEY_DEVICE_FRIENDLY_NAME)?;
-
-            // Extract the string value (PWSTR) from the PROPVARIANT
-            // prop_variant.Anonymous.Anonymous.vt holds the type, should be VT_LPWSTR
-            // prop_variant.Anonymous.Anonymous.Anonymous holds the data
-            let name = if prop_variant.Anonymous.Anonymous.vt
-                == windows::Win32::System::Variant::VT_LPWSTR
-            {
-                prop_variant
-                    .Anonymous
-                    .Anonymous
-                    .Anonymous
-                    .pwszVal
-                    .to_string()
-                    .unwrap_or_else(|_| "Invalid Name".to_string())
-            } else {
-                "Unknown Name".to_string()
-            };
-
-            // Important: Need to free the PROPVARIANT memory
-            // PropVariantClear is often in Com::StructuredStorage or just Com
-            PropVariantClear((&prop_variant) as *const _ as *mut _)?;
-
-            if !id.is_empty() && name != "Unknown Name" && name != "Invalid Name" {
-                devices.push(AudioDevice { id, name });
+            let audio_device = read_device(&device)?;
+
+            if audio_device.id.is_empty() {
+                warn!("Skipping device #{} ('{}'): empty device ID", i, audio_device.name);
+            } else if audio_device.name != "Unknown Name" && audio_device.name != "Invalid Name" {
+                if audio_device.id.contains(char::REPLACEMENT_CHARACTER) {
+                    warn!(
+                        "Device #{} ('{}') has a sanitized ID and may not be switchable",
+                        i, audio_device.name
+                    );
+                }
+                devices.push(audio_device);
             }
         }
 
-        // Uninitialize COM
-        CoUninitialize();
-
         Ok(devices)
     }
 }
 
+/// Enumerates active audio output (rendering) devices.
+pub fn list_output_devices() -> Result<Vec<AudioDevice>> {
+    list_devices(eRender, DEVICE_STATE_ACTIVE)
+}
+
 /// Enumerates active audio input (capture) devices.
 pub fn list_input_devices() -> Result<Vec<AudioDevice>> {
+    list_devices(eCapture, DEVICE_STATE_ACTIVE)
+}
+
+/// Enumerates the devices for the given flow that are active, unplugged or disabled, i.e. every
+/// device that can be configured ahead of time. Devices that were removed are left out.
+pub fn list_devices_including_inactive(flow: DeviceFlow) -> Result<Vec<AudioDevice>> {
+    let states = DEVICE_STATE(DEVICE_STATE_ACTIVE.0 | DEVICE_STATE_UNPLUGGED.0 | DEVICE_STATE_DISABLED.0);
+    list_devices(flow.to_data_flow(), states)
+}
+
+/// Enumerates every device for the given flow, including disabled, unplugged and missing ones.
+pub fn list_devices_with_state(flow: DeviceFlow) -> Result<Vec<AudioDevice>> {
     unsafe {
-        // Initialize COM for this thread
-        let _ = CoInitializeEx(None, COINIT_MULTITHREADED); // Use multithreaded apartment
+        require_com()?;
+
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let collection: IMMDeviceCollection =
+            enumerator.EnumAudioEndpoints(flow.to_data_flow(), DEVICE_STATE(DEVICE_STATEMASK_ALL))?;
 
         let mut devices = Vec::new();
+        for i in 0..collection.GetCount()? {
+            let device: IMMDevice = collection.Item(i)?;
+            // Devices that are no longer present may not have readable properties
+            match read_device(&device) {
+                Ok(audio_device) => devices.push(audio_device),
+                Err(e) => warn!("Skipping {:?} device #{}: {}", flow, i, e),
+            }
+        }
+        Ok(devices)
+    }
+}
+
+/// Returns the current default device for the given flow and role.
+pub fn get_default_device(flow: DeviceFlow, role: AudioRole) -> Result<AudioDevice> {
+    unsafe {
+        require_com()?;
 
-        // Create an instance of the device enumerator
         let enumerator: IMMDeviceEnumerator =
             CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDefaultAudioEndpoint(flow.to_data_flow(), role.to_erole())?;
+        let audio_device = read_device(&device)?;
 
-        // Get the collection of active capture devices
-        let collection: IMMDeviceCollection =
-            enumerator.EnumAudioEndpoints(eCapture, DEVICE_STATE_ACTIVE)?;
+        Ok(audio_device)
+    }
+}
 
-        let count = collection.GetCount()?;
+/// Returns the current default playback device for `role`. The console role is the one shown
+/// as "Default Device" in Sound settings.
+pub fn get_default_output_device(role: AudioRole) -> Result<AudioDevice> {
+    get_default_device(DeviceFlow::Output, role)
+}
 
-        for i in 0..count {
-            let device: IMMDevice = collection.Item(i)?;
-            let id_pwstr: PWSTR = device.GetId()?;
-            let id = id_pwstr.to_string().unwrap_or_default(); // Convert PWSTR to String
-            windows::Win32::System::Com::CoTaskMemFree(Some(id_pwstr.as_ptr() as *mut _)); // Free the memory allocated by GetId
-
-            // Get the property store for the device
-            let properties: IPropertyStore =
-                device.OpenPropertyStore(windows::Win32::System::Com::STGM_READ)?;
-
-            // Get the friendly name property
-            let prop_variant = properties.GetValue(&PKEY_DEVICE_FRIENDLY_NAME)?;
-
-            // Extract the string value (PWSTR) from the PROPVARIANT
-            let name = if prop_variant.Anonymous.Anonymous.vt
-                == windows::Win32::System::Variant::VT_LPWSTR
-            {
-                prop_variant
-                    .Anonymous
-                    .Anonymous
-                    .Anonymous
-                    .pwszVal
-                    .to_string()
-                    .unwrap_or_else(|_| "Invalid Name".to_string())
-            } else {
-                "Unknown Name".to_string()
-            };
-
-            // Important: Need to free the PROPVARIANT memory
-            PropVariantClear((&prop_variant) as *const _ as *mut _)?;
-
-            if !id.is_empty() && name != "Unknown Name" && name != "Invalid Name" {
-                devices.push(AudioDevice { id, name });
+/// Returns the current default recording device for `role`.
+pub fn get_default_input_device(role: AudioRole) -> Result<AudioDevice> {
+    get_default_device(DeviceFlow::Input, role)
+}
+
+/// Looks up a device (in any state) by its endpoint ID.
+pub fn get_device_by_id(device_id: &str) -> Result<AudioDevice> {
+    unsafe {
+        require_com()?;
+
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDevice(&HSTRING::from(device_id))?;
+        let audio_device = read_device(&device)?;
+
+        Ok(audio_device)
+    }
+}
+
+/// Activates the endpoint volume interface of the device with the given ID.
+/// COM must be initialized on the calling thread.
+unsafe fn endpoint_volume(device_id: &str) -> Result<IAudioEndpointVolume> {
+    unsafe {
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDevice(&HSTRING::from(device_id))?;
+        device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None)
+    }
+}
+
+/// Returns the master volume (0.0 to 1.0) of the device with the given ID.
+pub fn get_volume(device_id: &str) -> Result<f32> {
+    unsafe {
+        require_com()?;
+        let level = endpoint_volume(device_id)?.GetMasterVolumeLevelScalar()?;
+        Ok(level)
+    }
+}
+
+/// Sets the master volume of the device with the given ID. `level` is clamped to 0.0 to 1.0.
+pub fn set_volume(device_id: &str, level: f32) -> Result<()> {
+    unsafe {
+        require_com()?;
+        endpoint_volume(device_id)?
+            .SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), std::ptr::null())?;
+        Ok(())
+    }
+}
+
+/// Changes the volume of the current default output device (console role) by `delta` and returns the new level.
+pub fn adjust_output_volume(delta: f32) -> Result<f32> {
+    let device = get_default_output_device(AudioRole::Console)?;
+    let level = (get_volume(&device.id)? + delta).clamp(0.0, 1.0);
+    set_volume(&device.id, level)?;
+    Ok(level)
+}
+
+/// Toggles mute on the current default output device (console role) and returns its name and the new mute state.
+pub fn toggle_output_mute() -> Result<(String, bool)> {
+    let device = get_default_output_device(AudioRole::Console)?;
+    let muted = !get_mute(&device.id)?;
+    set_mute(&device.id, muted)?;
+    Ok((device.name, muted))
+}
+
+/// Plays a short test tone on the current default output device (blocks until it has finished).
+pub fn play_test_tone() -> Result<()> {
+    // Since Windows 7, Beep is rendered through the default output device
+    unsafe { windows::Win32::System::Diagnostics::Debug::Beep(440, 400) }
+}
+
+/// Returns whether the device with the given ID is muted.
+pub fn get_mute(device_id: &str) -> Result<bool> {
+    unsafe {
+        require_com()?;
+        let muted = endpoint_volume(device_id)?.GetMute()?.as_bool();
+        Ok(muted)
+    }
+}
+
+/// Mutes or unmutes the device with the given ID.
+pub fn set_mute(device_id: &str, muted: bool) -> Result<()> {
+    unsafe {
+        require_com()?;
+        endpoint_volume(device_id)?.SetMute(muted, std::ptr::null())?;
+        Ok(())
+    }
+}
+
+/// How often `peak_input_level` reads the meter.
+const METER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Samples the highest peak level (0.0 to 1.0) the input device with the given ID picks up
+/// within `duration_ms`. A capture endpoint's meter only moves while something records from
+/// it, so a shared-mode capture stream runs for the duration; its data is discarded.
+pub fn peak_input_level(device_id: &str, duration_ms: u32) -> Result<f32> {
+    unsafe {
+        require_com()?;
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDevice(&HSTRING::from(device_id))?;
+        let meter = device.Activate::<IAudioMeterInformation>(CLSCTX_ALL, None)?;
+        let client = device.Activate::<IAudioClient>(CLSCTX_ALL, None)?;
+
+        let format = client.GetMixFormat()?;
+        // 100 ms buffer (in 100-nanosecond units); nothing reads it, so overruns don't matter
+        let initialized = client.Initialize(AUDCLNT_SHAREMODE_SHARED, 0, 1_000_000, 0, format, None);
+        CoTaskMemFree(Some(format as *const _));
+        initialized?;
+
+        client.Start()?;
+        let deadline = Instant::now() + Duration::from_millis(duration_ms.into());
+        let mut peak = 0.0f32;
+        let sampled = loop {
+            std::thread::sleep(METER_POLL_INTERVAL);
+            match meter.GetPeakValue() {
+                Ok(value) => peak = peak.max(value),
+                Err(e) => break Err(e),
+            }
+            if Instant::now() >= deadline {
+                break Ok(peak);
             }
+        };
+        if let Err(e) = client.Stop() {
+            warn!("Failed to stop the capture stream of {}: {}", device_id, e);
         }
+        sampled
+    }
+}
 
-        // Uninitialize COM
-        CoUninitialize();
+// WAVE_FORMAT_EXTENSIBLE and KSDATAFORMAT_SUBTYPE_PCM, which live in the kernel streaming bindings
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+const SUBTYPE_PCM: GUID = GUID::from_u128(0x00000001_0000_0010_8000_00aa00389b71);
+// Front left and right, for a mix format that doesn't state its channel layout
+const SPEAKER_STEREO: u32 = 0x3;
 
-        Ok(devices)
+/// The integer PCM format with `format`'s sample rate and bit depth for the given channel layout.
+/// 24-bit samples are packed in 3 bytes, as the Sound properties list them.
+pub fn pcm_format(format: DeviceFormat, channels: u16, channel_mask: u32) -> WAVEFORMATEXTENSIBLE {
+    let block_align = channels * format.bits / 8;
+    WAVEFORMATEXTENSIBLE {
+        Format: WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+            nChannels: channels,
+            nSamplesPerSec: format.sample_rate,
+            nAvgBytesPerSec: format.sample_rate * u32::from(block_align),
+            nBlockAlign: block_align,
+            wBitsPerSample: format.bits,
+            cbSize: (size_of::<WAVEFORMATEXTENSIBLE>() - size_of::<WAVEFORMATEX>()) as u16,
+        },
+        Samples: WAVEFORMATEXTENSIBLE_0 { wValidBitsPerSample: format.bits },
+        dwChannelMask: channel_mask,
+        SubFormat: SUBTYPE_PCM,
+    }
+}
+
+/// Sets the shared-mode format (sample rate and bit depth) of the device with the given ID, like
+/// choosing a "Default Format" in its Sound properties. The channel layout is kept. Fails if the
+/// device doesn't support the format or an application holds it in exclusive mode.
+pub fn set_device_format(device_id: &str, format: DeviceFormat) -> Result<()> {
+    unsafe {
+        require_com()?;
+        let enumerator: IMMDeviceEnumerator =
+            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDevice(&HSTRING::from(device_id))?;
+        let client = device.Activate::<IAudioClient>(CLSCTX_ALL, None)?;
+
+        // Keep the channels of the current format; the structs are packed, so read them unaligned
+        let mix = client.GetMixFormat()?;
+        let current = std::ptr::read_unaligned(mix);
+        let channel_mask = if current.wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+            std::ptr::read_unaligned(mix as *const WAVEFORMATEXTENSIBLE).dwChannelMask
+        } else {
+            SPEAKER_STEREO
+        };
+        CoTaskMemFree(Some(mix as *const _));
+
+        let wanted = pcm_format(format, current.nChannels, channel_mask);
+        // The Sound properties only offer formats the device accepts in exclusive mode
+        if client.IsFormatSupported(AUDCLNT_SHAREMODE_EXCLUSIVE, &wanted.Format, None) != S_OK {
+            return Err(windows::core::Error::new(
+                AUDCLNT_E_UNSUPPORTED_FORMAT,
+                format!("{} Hz / {} bit is not supported by the device", format.sample_rate, format.bits),
+            ));
+        }
+        policy_config::set_device_format_native(device_id, &wanted)
     }
 }
 
 // --- Undocumented COM Interface Definitions Removed ---
 
-/// Sets the default audio output device using PowerShell's Set-AudioDevice cmdlet.
-///
-/// # Arguments
-/// * `device_id` - The unique ID string of the device to set as default.
-///
-/// # Notes
-/// - Requires PowerShell 5.1 or later.
-/// - May require the user to install the `AudioDeviceCmdlets` module:
-///   `Install-Module -Name AudioDeviceCmdlets -Scope CurrentUser`
-/// - Hides the PowerShell window during execution.
-// Use standard library Result and Box<dyn Error> for flexibility
-pub fn set_default_output_device(
-    device_id: &str,
-) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let escaped_device_id = device_id.replace('\'', "''");
+// Limits how many PowerShell switch invocations run at the same time
+struct SwitchLimiter {
+    state: Mutex<SwitchLimiterState>,
+    slot_freed: Condvar,
+}
+
+struct SwitchLimiterState {
+    running: usize,
+    max_concurrent: usize,
+    policy: SwitchLimitPolicy,
+}
 
-    // --- Get path to bundled module manifest ---
-    let mut module_manifest_path = std::env::current_exe()
-        .map_err(|e| format!("Failed to get executable path: {}", e))?;
-    module_manifest_path.pop(); // Remove executable name
-    module_manifest_path.push("modules");
-    module_manifest_path.push("AudioDeviceCmdlets");
-    module_manifest_path.push("AudioDeviceCmdlets.psd1"); // Directly point to the manifest
+static SWITCH_LIMITER: SwitchLimiter = SwitchLimiter {
+    state: Mutex::new(SwitchLimiterState {
+        running: 0,
+        max_concurrent: 1,
+        policy: SwitchLimitPolicy::Queue,
+    }),
+    slot_freed: Condvar::new(),
+};
+
+// Releases its slot in the switch limiter when dropped
+struct SwitchSlot;
 
-    // Check if the constructed path actually exists before proceeding
-    if !module_manifest_path.exists() {
-        return Err(format!("Bundled module manifest not found at expected path: {}", module_manifest_path.display()).into());
+impl Drop for SwitchSlot {
+    fn drop(&mut self) {
+        let mut state = SWITCH_LIMITER.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.running -= 1;
+        SWITCH_LIMITER.slot_freed.notify_one();
     }
+}
 
-    let module_path_str = module_manifest_path.to_str()
-        .ok_or("Failed to convert module path to string")?;
-    // Escape path for PowerShell command
-    let escaped_module_path = module_path_str.replace('\'', "''");
-    // --- End get path ---
+/// Sets how many PowerShell switch invocations may run concurrently (at least 1) and what
+/// happens to a switch that arrives while all slots are busy.
+pub fn configure_switch_limit(max_concurrent: usize, policy: SwitchLimitPolicy) {
+    let mut state = SWITCH_LIMITER.state.lock().unwrap_or_else(|e| e.into_inner());
+    state.max_concurrent = max_concurrent.max(1);
+    state.policy = policy;
+    SWITCH_LIMITER.slot_freed.notify_all();
+}
 
+// Waits for (or, with the drop policy, refuses) a free PowerShell slot
+fn acquire_switch_slot(description: &str) -> std::result::Result<SwitchSlot, Box<dyn std::error::Error>> {
+    let mut state = SWITCH_LIMITER.state.lock().unwrap_or_else(|e| e.into_inner());
+    if state.running >= state.max_concurrent {
+        match state.policy {
+            SwitchLimitPolicy::Drop => {
+                warn!(
+                    "Dropping switch of {}: {} PowerShell switch(es) already running (limit {})",
+                    description, state.running, state.max_concurrent
+                ); // Log warning
+                return Err(format!("Switch of {} dropped: too many switches in progress", description).into());
+            }
+            SwitchLimitPolicy::Queue => {
+                info!(
+                    "Queueing switch of {}: {} PowerShell switch(es) already running (limit {})",
+                    description, state.running, state.max_concurrent
+                ); // Log info
+                while state.running >= state.max_concurrent {
+                    state = SWITCH_LIMITER.slot_freed.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        }
+    }
+    state.running += 1;
+    Ok(SwitchSlot)
+}
 
-    // Construct the PowerShell command: Import using full path, then run Set-AudioDevice
-    let command_str = format!(
-        // Use single quotes around the path in PowerShell
-        "Import-Module -Name '{}' -ErrorAction Stop; Set-AudioDevice -ID '{}'",
-        escaped_module_path,
-        escaped_device_id
-    );
+// Whether a blocked module import may be retried with `-ExecutionPolicy Bypass`
+static EXECUTION_POLICY_BYPASS: AtomicBool = AtomicBool::new(true);
+
+/// Sets whether switches retry with `-ExecutionPolicy Bypass` (scoped to the spawned PowerShell
+/// process) when the execution policy blocks the module import.
+pub fn configure_execution_policy_bypass(enabled: bool) {
+    EXECUTION_POLICY_BYPASS.store(enabled, Ordering::Relaxed);
+}
+
+// How often a failed PowerShell switch is retried (switch-retries)
+static SWITCH_RETRIES: AtomicU32 = AtomicU32::new(2);
+// Wait before the first retry; doubled for each further one
+const SWITCH_RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// Sets how many times a PowerShell switch that exits with a failure status is retried.
+pub fn configure_switch_retries(retries: u32) {
+    SWITCH_RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// The wait before retry number `retry` (1 for the first): 250 ms, doubling each time, at most 2 s.
+fn switch_retry_delay(retry: u32) -> Duration {
+    SWITCH_RETRY_BASE_DELAY.saturating_mul(1 << retry.saturating_sub(1).min(3))
+}
+
+/// Whether PowerShell's stderr shows the module import was blocked by the execution policy.
+pub fn is_execution_policy_error(stderr: &str) -> bool {
+    stderr.contains("about_Execution_Policies")
+        || stderr.contains("PSSecurityException")
+        || stderr.contains("running scripts is disabled")
+}
+
+/// Whether PowerShell's stderr shows the module couldn't be found on the module path.
+pub fn is_module_not_found_error(stderr: &str) -> bool {
+    stderr.contains("no valid module file was found")
+}
+
+/// Where the AudioDeviceCmdlets module used by the PowerShell fallback is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioDeviceModule {
+    /// The `modules/AudioDeviceCmdlets` folder shipped next to the executable
+    Bundled(PathBuf),
+    /// A copy installed with `Install-Module AudioDeviceCmdlets`, found on the PowerShell module path
+    Installed,
+}
+
+impl AudioDeviceModule {
+    // The `-Name` argument for Import-Module, escaped for a single-quoted string
+    fn import_name(&self) -> String {
+        match self {
+            AudioDeviceModule::Bundled(manifest) => manifest.display().to_string().replace('\'', "''"),
+            AudioDeviceModule::Installed => "AudioDeviceCmdlets".to_string(),
+        }
+    }
+}
+
+// The manifest of the bundled module, whether or not it exists
+fn bundled_module_manifest() -> std::result::Result<PathBuf, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path: {}", e))?;
+    Ok(exe.with_file_name("modules").join("AudioDeviceCmdlets").join("AudioDeviceCmdlets.psd1"))
+}
 
-    info!("Executing PowerShell: {}", command_str); // Log info
+/// Explains how to restore the module when neither the bundled nor an installed copy exists.
+pub fn missing_module_message(bundled_manifest: &Path) -> String {
+    format!(
+        "The AudioDeviceCmdlets PowerShell module was not found. SoundSwitch looks for it at\n{}\n\
+        and then on the PowerShell module path.\n\n\
+        To fix this, either:\n\
+        • copy the 'modules' folder from the SoundSwitch release next to sound_switch.exe, or\n\
+        • install it by running in PowerShell:\n  Install-Module -Name AudioDeviceCmdlets -Scope CurrentUser",
+        bundled_manifest.display()
+    )
+}
+
+/// Finds the module for the PowerShell fallback: the bundled copy if present, otherwise an
+/// installed one (this asks PowerShell, so it takes a moment). The error explains how to fix it.
+pub fn locate_audio_device_module() -> std::result::Result<AudioDeviceModule, String> {
+    let manifest = bundled_module_manifest()?;
+    if manifest.exists() {
+        return Ok(AudioDeviceModule::Bundled(manifest));
+    }
+    warn!(
+        "Bundled module manifest not found at {}; looking for an installed AudioDeviceCmdlets module",
+        manifest.display()
+    ); // Log warning
+    match run_powershell("if (Get-Module -ListAvailable -Name AudioDeviceCmdlets) { exit 0 } else { exit 1 }", false) {
+        Ok(output) if output.status.success() => Ok(AudioDeviceModule::Installed),
+        Ok(_) => Err(missing_module_message(&manifest)),
+        Err(e) => Err(format!("Failed to run PowerShell to look for the AudioDeviceCmdlets module: {}", e)),
+    }
+}
 
-    // Execute the command using powershell.exe
+// Runs a command in a hidden, non-interactive PowerShell and captures its output
+fn run_powershell(command_str: &str, bypass_execution_policy: bool) -> std::io::Result<std::process::Output> {
     const CREATE_NO_WINDOW: u32 = 0x08000000; // Define flag to hide window
-    let output = Command::new("powershell.exe")
-        .creation_flags(CREATE_NO_WINDOW) // Set the flag to prevent window creation
+    let mut command = Command::new("powershell.exe");
+    command.creation_flags(CREATE_NO_WINDOW); // Set the flag to prevent window creation
+    if bypass_execution_policy {
+        command.args(["-ExecutionPolicy", "Bypass"]); // Applies to this process only
+    }
+    command
         // Arguments to hide window and run command
-        .args(&[
+        .args([
             "-NoProfile",      // Don't load user profile
             "-NonInteractive", // Don't require user interaction
             "-WindowStyle", "Hidden", // Hide the window
-            "-Command", &command_str, // Use the new command string
+            "-Command", command_str, // Use the new command string
         ])
         .output() // Capture stdout/stderr/status
-        .map_err(|e| format!("Failed to execute PowerShell command: {}", e))?; // This ? now works with Box<dyn Error>
+}
 
-    // Check the exit status
-    if output.status.success() {
-        info!("PowerShell command succeeded."); // Log info
+// SHA-256 of the bundled module files at build time (generated by build.rs)
+include!(concat!(env!("OUT_DIR"), "/module_hashes.rs"));
+
+/// Checks the bundled `modules/AudioDeviceCmdlets` files next to the executable against the
+/// hashes embedded at build time. Returns a description of every missing or modified file.
+pub fn verify_module_integrity() -> std::result::Result<(), Vec<String>> {
+    let module_dir = std::env::current_exe()
+        .map_err(|e| vec![format!("Failed to get executable path: {}", e)])?
+        .with_file_name("modules")
+        .join("AudioDeviceCmdlets");
+    if EXPECTED_MODULE_HASHES.is_empty() {
+        return Err(vec!["No expected module hashes were embedded at build time".to_string()]);
+    }
+
+    let problems: Vec<String> = EXPECTED_MODULE_HASHES
+        .iter()
+        .filter_map(|(file, expected)| {
+            let path = module_dir.join(file);
+            match std::fs::read(&path) {
+                Ok(bytes) => {
+                    let actual: String = Sha256::digest(&bytes).iter().map(|b| format!("{:02x}", b)).collect();
+                    (actual != *expected).then(|| format!("{} has been modified or is incomplete", path.display()))
+                }
+                Err(e) => Some(format!("{} could not be read: {}", path.display(), e)),
+            }
+        })
+        .collect();
+
+    if problems.is_empty() {
+        info!("Bundled module matches the expected hashes ({} files)", EXPECTED_MODULE_HASHES.len()); // Log info
         Ok(())
     } else {
-        // Combine stdout and stderr for error message
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let err_msg = format!(
-            "PowerShell command failed with status: {}. Stdout: '{}'. Stderr: '{}'",
-            output.status,
-            stdout.trim(),
-            stderr.trim()
-        );
-        error!("{}", err_msg); // Log error
-        Err(err_msg.into()) // This .into() correctly converts String to Box<dyn Error>
+        Err(problems)
     }
 }
 
-/// Sets the default audio input device using PowerShell's Set-AudioDevice cmdlet.
+/// Runs `Set-AudioDevice` from the bundled AudioDeviceCmdlets module for the given device.
 ///
 /// # Arguments
-/// * `device_id` - The unique ID string of the device to set as default input.
+/// * `device_id` - The unique ID string of the device to set as default.
+/// * `switches` - Extra cmdlet switches (e.g. `-DefaultOnly`), or an empty string.
+/// * `description` - Short label for log and error messages (e.g. "input device").
 ///
 /// # Notes
 /// - Requires PowerShell 5.1 or later.
 /// - May require the user to install the `AudioDeviceCmdlets` module:
 ///   `Install-Module -Name AudioDeviceCmdlets -Scope CurrentUser`
 /// - Hides the PowerShell window during execution.
-pub fn set_default_input_device(
+fn run_set_audio_device(
     device_id: &str,
+    switches: &str,
+    description: &str,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
     let escaped_device_id = device_id.replace('\'', "''");
 
-    // --- Get path to bundled module manifest ---
-    let mut module_manifest_path = std::env::current_exe()
-        .map_err(|e| format!("Failed to get executable path: {}", e))?;
-    module_manifest_path.pop(); // Remove executable name
-    module_manifest_path.push("modules");
-    module_manifest_path.push("AudioDeviceCmdlets");
-    module_manifest_path.push("AudioDeviceCmdlets.psd1"); // Directly point to the manifest
-
-    // Check if the constructed path actually exists before proceeding
-    if !module_manifest_path.exists() {
-        return Err(format!("Bundled module manifest not found at expected path: {}", module_manifest_path.display()).into());
-    }
-
-    let module_path_str = module_manifest_path.to_str()
-        .ok_or("Failed to convert module path to string")?;
-    // Escape path for PowerShell command
-    let escaped_module_path = module_path_str.replace('\'', "''");
-    // --- End get path ---
+    // Prefer the bundled module; without it, try an installed copy from the module path
+    let module_manifest_path = bundled_module_manifest()?;
+    let module = if module_manifest_path.exists() {
+        AudioDeviceModule::Bundled(module_manifest_path.clone())
+    } else {
+        warn!(
+            "Bundled module manifest not found at {}; importing AudioDeviceCmdlets from the PowerShell module path",
+            module_manifest_path.display()
+        ); // Log warning
+        AudioDeviceModule::Installed
+    };
 
-    // Construct the PowerShell command: Import using full path, then run Set-AudioDevice
+    // Construct the PowerShell command: Import the module, then run Set-AudioDevice
     // Note: AudioDeviceCmdlets automatically detects device type from ID, no -RecordingDevice flag needed
     let command_str = format!(
         // Use single quotes around the path in PowerShell
-        "Import-Module -Name '{}' -ErrorAction Stop; Set-AudioDevice -ID '{}'",
-        escaped_module_path,
-        escaped_device_id
+        "Import-Module -Name '{}' -ErrorAction Stop; Set-AudioDevice -ID '{}' {}",
+        module.import_name(),
+        escaped_device_id,
+        switches
     );
 
-    info!("Executing PowerShell for input device: {}", command_str); // Log info
+    // Held until PowerShell exits so at most `max-concurrent-switches` run at once
+    let _slot = acquire_switch_slot(description)?;
+    info!("Executing PowerShell for {}: {}", description, command_str); // Log info
 
-    // Execute the command using powershell.exe
-    const CREATE_NO_WINDOW: u32 = 0x08000000; // Define flag to hide window
-    let output = Command::new("powershell.exe")
-        .creation_flags(CREATE_NO_WINDOW) // Set the flag to prevent window creation
-        // Arguments to hide window and run command
-        .args(&[
-            "-NoProfile",      // Don't load user profile
-            "-NonInteractive", // Don't require user interaction
-            "-WindowStyle", "Hidden", // Hide the window
-            "-Command", &command_str, // Use the new command string
-        ])
-        .output() // Capture stdout/stderr/status
-        .map_err(|e| format!("Failed to execute PowerShell command for input device: {}", e))?;
+    // A failure exit status is retried (e.g. the module import racing a device that just appeared);
+    // failing to start PowerShell at all is not
+    let attempts = SWITCH_RETRIES.load(Ordering::Relaxed).saturating_add(1);
+    let mut attempt = 1;
+    let mut bypass_execution_policy = false;
+    let output = loop {
+        info!("PowerShell attempt {} of {} for {}", attempt, attempts, description); // Log info
+        let output = run_powershell(&command_str, bypass_execution_policy)
+            .map_err(|e| format!("Failed to execute PowerShell command for {}: {}", description, e))?;
+        if output.status.success() {
+            break output;
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        // A locked-down execution policy blocks the module import even though the module exists.
+        // The bypass is a different command, not a retry, so it doesn't use up an attempt
+        if !bypass_execution_policy && is_execution_policy_error(&stderr) {
+            if !EXECUTION_POLICY_BYPASS.load(Ordering::Relaxed) {
+                let err_msg = format!(
+                    "PowerShell's execution policy blocked loading the AudioDeviceCmdlets module for {}. \
+                    Allow it with 'Set-ExecutionPolicy -Scope CurrentUser RemoteSigned', or set \
+                    powershell-execution-policy-bypass = true to bypass the policy for SoundSwitch's own PowerShell process.",
+                    description
+                );
+                error!("{}", err_msg); // Log error
+                return Err(err_msg.into());
+            }
+            warn!(
+                "Execution policy blocked the module import for {}; retrying with -ExecutionPolicy Bypass (process scope)",
+                description
+            ); // Log warning
+            bypass_execution_policy = true;
+            continue;
+        }
+
+        // A missing module won't appear by waiting
+        if attempt >= attempts || (module == AudioDeviceModule::Installed && is_module_not_found_error(&stderr)) {
+            break output;
+        }
+        let delay = switch_retry_delay(attempt);
+        warn!(
+            "PowerShell command for {} failed with status {} (attempt {} of {}); retrying in {} ms",
+            description,
+            output.status,
+            attempt,
+            attempts,
+            delay.as_millis()
+        ); // Log warning
+        std::thread::sleep(delay);
+        attempt += 1;
+    };
 
     // Check the exit status
     if output.status.success() {
-        info!("PowerShell command for input device succeeded."); // Log info
+        info!("PowerShell command for {} succeeded.", description); // Log info
         Ok(())
     } else {
         // Combine stdout and stderr for error message
         let stdout = String::from_utf8_lossy(&output.stdout);
         let stderr = String::from_utf8_lossy(&output.stderr);
+        if module == AudioDeviceModule::Installed && is_module_not_found_error(&stderr) {
+            let err_msg = format!("Cannot set {}. {}", description, missing_module_message(&module_manifest_path));
+            error!("{}", err_msg); // Log error
+            return Err(err_msg.into());
+        }
         let err_msg = format!(
-            "PowerShell command for input device failed with status: {}. Stdout: '{}'. Stderr: '{}'",
+            "PowerShell command for {} failed with status: {}. Stdout: '{}'. Stderr: '{}'",
+            description,
             output.status,
             stdout.trim(),
             stderr.trim()
@@ -331,4 +1084,272 @@ pub fn set_default_input_device(
     }
 }
 
+mod policy_config {
+    #![allow(non_snake_case)] // COM method names as declared by Windows
+    use std::ffi::c_void;
+    use super::AudioRole;
+    use windows::Win32::{
+        Foundation::PROPERTYKEY,
+        Media::Audio::{ERole, WAVEFORMATEX, WAVEFORMATEXTENSIBLE},
+        System::Com::{CLSCTX_ALL, CoCreateInstance, CoTaskMemFree},
+    };
+    use windows::core::{GUID, HRESULT, HSTRING, IUnknown, IUnknown_Vtbl, PCWSTR, Result, interface};
+
+    /// `IPolicyConfig`: the undocumented interface the Windows Sound settings use to change default
+    /// endpoints and formats. Only `GetMixFormat`, `SetDeviceFormat` and `SetDefaultEndpoint` are
+    /// called; the other methods keep the vtable layout.
+    #[interface("f8679f50-850a-41cf-9c72-430f290290c8")]
+    unsafe trait IPolicyConfig: IUnknown {
+        fn GetMixFormat(&self, device_id: PCWSTR, format: *mut *mut c_void) -> HRESULT;
+        fn GetDeviceFormat(&self, device_id: PCWSTR, default: i32, format: *mut *mut c_void) -> HRESULT;
+        fn ResetDeviceFormat(&self, device_id: PCWSTR) -> HRESULT;
+        fn SetDeviceFormat(&self, device_id: PCWSTR, endpoint_format: *mut c_void, mix_format: *mut c_void) -> HRESULT;
+        fn GetProcessingPeriod(&self, device_id: PCWSTR, default: i32, default_period: *mut i64, min_period: *mut i64) -> HRESULT;
+        fn SetProcessingPeriod(&self, device_id: PCWSTR, period: *mut i64) -> HRESULT;
+        fn GetShareMode(&self, device_id: PCWSTR, mode: *mut c_void) -> HRESULT;
+        fn SetShareMode(&self, device_id: PCWSTR, mode: *mut c_void) -> HRESULT;
+        fn GetPropertyValue(&self, device_id: PCWSTR, fx_store: i32, key: *const PROPERTYKEY, value: *mut c_void) -> HRESULT;
+        fn SetPropertyValue(&self, device_id: PCWSTR, fx_store: i32, key: *const PROPERTYKEY, value: *mut c_void) -> HRESULT;
+        fn SetDefaultEndpoint(&self, device_id: PCWSTR, role: ERole) -> HRESULT;
+        fn SetEndpointVisibility(&self, device_id: PCWSTR, visible: i32) -> HRESULT;
+    }
+
+    // CPolicyConfigClient, the coclass implementing IPolicyConfig
+    const CLSID_POLICY_CONFIG_CLIENT: GUID = GUID::from_u128(0x870af99c_171d_4f9e_af0d_e63df40c2bc9);
+
+    /// Sets the device's endpoint format through `IPolicyConfig`. The shared-mode mix format keeps
+    /// the device's own sample type (usually 32-bit float) and only follows the new sample rate,
+    /// as it does when the format is picked in the Sound properties.
+    pub fn set_device_format_native(device_id: &str, format: &WAVEFORMATEXTENSIBLE) -> Result<()> {
+        unsafe {
+            super::require_com()?;
+
+            let policy_config: IPolicyConfig =
+                CoCreateInstance(&CLSID_POLICY_CONFIG_CLIENT, None, CLSCTX_ALL)?;
+            let device_id = HSTRING::from(device_id);
+            let device_id = PCWSTR(device_id.as_ptr());
+
+            let mut mix: *mut c_void = std::ptr::null_mut();
+            policy_config.GetMixFormat(device_id, &mut mix).ok()?;
+            let mix = mix as *mut WAVEFORMATEX;
+            // The struct is packed, so copy it out and back unaligned
+            let mut mix_format = std::ptr::read_unaligned(mix);
+            let sample_rate = format.Format.nSamplesPerSec;
+            mix_format.nSamplesPerSec = sample_rate;
+            mix_format.nAvgBytesPerSec = sample_rate * u32::from(mix_format.nBlockAlign);
+            std::ptr::write_unaligned(mix, mix_format);
+
+            let endpoint = format as *const WAVEFORMATEXTENSIBLE as *mut c_void;
+            let result = policy_config.SetDeviceFormat(device_id, endpoint, mix as *mut c_void).ok();
+            CoTaskMemFree(Some(mix as *const _));
+            result
+        }
+    }
+
+    /// Sets the device as default for the given roles through `IPolicyConfig`.
+    pub fn set_default_endpoint_native(device_id: &str, roles: &[AudioRole]) -> Result<()> {
+        unsafe {
+            super::require_com()?;
+
+            let policy_config: IPolicyConfig =
+                CoCreateInstance(&CLSID_POLICY_CONFIG_CLIENT, None, CLSCTX_ALL)?;
+            let device_id = HSTRING::from(device_id);
+            for role in roles {
+                policy_config
+                    .SetDefaultEndpoint(PCWSTR(device_id.as_ptr()), role.to_erole())
+                    .ok()?;
+            }
+            Ok(())
+        }
+    }
+}
+
+use policy_config::set_default_endpoint_native;
+
+// Set from the config's `use-powershell-fallback`; on by default so existing setups keep working
+static POWERSHELL_FALLBACK: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables falling back to PowerShell when a native switch fails.
+pub fn configure_powershell_fallback(enabled: bool) {
+    POWERSHELL_FALLBACK.store(enabled, Ordering::Relaxed);
+}
+
+/// Switches natively, falling back to `Set-AudioDevice` with `switches` if that fails and the
+/// fallback is enabled. Logs how long the switch took.
+fn switch_default_device(
+    device_id: &str,
+    roles: &[AudioRole],
+    switches: &str,
+    description: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    match set_default_endpoint_native(device_id, roles) {
+        Ok(()) => {
+            info!("Set {} natively in {} ms", description, start.elapsed().as_millis()); // Log info
+            return Ok(());
+        }
+        Err(e) if POWERSHELL_FALLBACK.load(Ordering::Relaxed) => {
+            warn!("Native switch of {} failed ({}); falling back to PowerShell", description, e); // Log warning
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to set {} natively: {}", description, e);
+            error!("{}", err_msg); // Log error
+            return Err(err_msg.into());
+        }
+    }
+
+    run_set_audio_device(device_id, switches, description)?;
+    info!("Set {} via PowerShell in {} ms", description, start.elapsed().as_millis()); // Log info
+    Ok(())
+}
+
+/// Sets the default audio output device for all roles.
+///
+/// # Arguments
+/// * `device_id` - The unique ID string of the device to set as default.
+// Use standard library Result and Box<dyn Error> for flexibility
+pub fn set_default_output_device(
+    device_id: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    switch_default_device(device_id, &AudioRole::ALL, "", "output device")
+}
+
+/// Sets the default audio input device for all roles.
+///
+/// # Arguments
+/// * `device_id` - The unique ID string of the device to set as default input.
+pub fn set_default_input_device(
+    device_id: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    switch_default_device(device_id, &AudioRole::ALL, "", "input device")
+}
+
+/// Sets the device as default for a single role.
+///
+/// # Notes
+/// - `Console` and `Multimedia` are always set together, matching the PowerShell fallback:
+///   `Set-AudioDevice` cannot address them separately (both map to `-DefaultOnly`).
+pub fn set_default_device_for_role(
+    device_id: &str,
+    role: AudioRole,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    match role {
+        AudioRole::Console | AudioRole::Multimedia => switch_default_device(
+            device_id,
+            &[AudioRole::Console, AudioRole::Multimedia],
+            "-DefaultOnly",
+            "default role",
+        ),
+        AudioRole::Communications => switch_default_device(
+            device_id,
+            &[AudioRole::Communications],
+            "-CommunicationOnly",
+            "communications role",
+        ),
+    }
+}
+
+/// Abstraction over the system audio API used by the hotkey handling code, so the
+/// resolve-and-switch pipeline can be exercised against a mock backend in tests.
+pub trait AudioBackend {
+    fn list_output_devices(&self) -> std::result::Result<Vec<AudioDevice>, Box<dyn std::error::Error>>;
+    fn list_input_devices(&self) -> std::result::Result<Vec<AudioDevice>, Box<dyn std::error::Error>>;
+    fn set_default_output_device(&self, device_id: &str) -> std::result::Result<(), Box<dyn std::error::Error>>;
+    fn set_default_input_device(&self, device_id: &str) -> std::result::Result<(), Box<dyn std::error::Error>>;
+    fn set_default_device_for_role(&self, device_id: &str, role: AudioRole) -> std::result::Result<(), Box<dyn std::error::Error>>;
+    /// The default output device for the console role.
+    fn default_output_device(&self) -> std::result::Result<AudioDevice, Box<dyn std::error::Error>>;
+    fn default_device(&self, flow: DeviceFlow, role: AudioRole) -> std::result::Result<AudioDevice, Box<dyn std::error::Error>>;
+    fn set_volume(&self, device_id: &str, level: f32) -> std::result::Result<(), Box<dyn std::error::Error>>;
+    fn set_device_format(&self, device_id: &str, format: DeviceFormat) -> std::result::Result<(), Box<dyn std::error::Error>>;
+}
+
+/// The default devices of all three roles for one flow, read together. A role whose default
+/// can't be read (e.g. no device of that flow is present) is `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoleDefaults {
+    pub console: Option<AudioDevice>,
+    pub multimedia: Option<AudioDevice>,
+    pub communications: Option<AudioDevice>,
+}
+
+impl RoleDefaults {
+    /// Reads the defaults of every role for `flow` from `backend`.
+    pub fn read(backend: &dyn AudioBackend, flow: DeviceFlow) -> RoleDefaults {
+        let read = |role| backend.default_device(flow, role).ok();
+        RoleDefaults {
+            console: read(AudioRole::Console),
+            multimedia: read(AudioRole::Multimedia),
+            communications: read(AudioRole::Communications),
+        }
+    }
+
+    /// The default device of `role`.
+    pub fn get(&self, role: AudioRole) -> Option<&AudioDevice> {
+        match role {
+            AudioRole::Console => self.console.as_ref(),
+            AudioRole::Multimedia => self.multimedia.as_ref(),
+            AudioRole::Communications => self.communications.as_ref(),
+        }
+    }
+
+    /// Whether the roles have different defaults, so "the current device" depends on the role asked.
+    pub fn differ(&self) -> bool {
+        self.console != self.multimedia || self.console != self.communications
+    }
+}
+
+/// The real backend: Core Audio enumeration plus native `IPolicyConfig` switching, with an optional
+/// PowerShell fallback (`use-powershell-fallback`).
+pub struct SystemBackend;
+
+impl AudioBackend for SystemBackend {
+    fn list_output_devices(&self) -> std::result::Result<Vec<AudioDevice>, Box<dyn std::error::Error>> {
+        Ok(list_output_devices()?)
+    }
+
+    fn list_input_devices(&self) -> std::result::Result<Vec<AudioDevice>, Box<dyn std::error::Error>> {
+        Ok(list_input_devices()?)
+    }
+
+    fn set_default_output_device(&self, device_id: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        set_default_output_device(device_id)
+    }
+
+    fn set_default_input_device(&self, device_id: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        set_default_input_device(device_id)
+    }
+
+    fn set_default_device_for_role(&self, device_id: &str, role: AudioRole) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        set_default_device_for_role(device_id, role)
+    }
+
+    fn default_output_device(&self) -> std::result::Result<AudioDevice, Box<dyn std::error::Error>> {
+        Ok(get_default_output_device(AudioRole::Console)?)
+    }
+
+    fn default_device(&self, flow: DeviceFlow, role: AudioRole) -> std::result::Result<AudioDevice, Box<dyn std::error::Error>> {
+        Ok(get_default_device(flow, role)?)
+    }
+
+    fn set_volume(&self, device_id: &str, level: f32) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        Ok(set_volume(device_id, level)?)
+    }
+
+    fn set_device_format(&self, device_id: &str, format: DeviceFormat) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        Ok(set_device_format(device_id, format)?)
+    }
+}
+
 // Removed unused helper function find_module_manifest
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_retries_back_off_up_to_two_seconds() {
+        let delays: Vec<u128> = (1..=5).map(|retry| switch_retry_delay(retry).as_millis()).collect();
+        assert_eq!(delays, vec![250, 500, 1000, 2000, 2000]);
+    }
+}
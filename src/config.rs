@@ -1,6 +1,14 @@
-use log::info;
-use serde::Deserialize;
-use std::{error::Error, fs};
+use crate::audio_device::{AudioRole, DeviceFlow};
+use crate::hotkey_manager::{normalize_keys, parse_hotkey_string};
+use crate::log_dedup::DEFAULT_LOG_DEDUP_WINDOW_MS;
+use log::{LevelFilter, info, warn};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
 // Assuming global_hotkey uses these types. Adjust if necessary based on the actual crate API.
 // If global_hotkey doesn't expose Modifiers/Code directly for config,
 // or a simpler string representation initially. For now, let's assume direct use is possible or we define placeholders.
@@ -8,24 +16,279 @@ use std::{error::Error, fs};
 // Update: Using Code and Modifiers from global_hotkey::hotkey
 // use global_hotkey::hotkey::{Code, Modifiers}; // Removed unused imports
 
+/// A mapping's `keys`: one key combination (`"Ctrl+Alt+1"`) or a list of combinations that all
+/// trigger it (`["Ctrl+Alt+1", "F13"]`). Displays as the combinations joined with ", ".
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "KeysField")]
+pub struct HotkeyKeys {
+    combos: Vec<String>,
+    joined: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeysField {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<KeysField> for HotkeyKeys {
+    fn from(field: KeysField) -> Self {
+        let combos = match field {
+            KeysField::One(keys) => vec![keys],
+            KeysField::Many(combos) => combos,
+        };
+        HotkeyKeys { joined: combos.join(", "), combos }
+    }
+}
+
+impl HotkeyKeys {
+    /// Each key combination, as written in the config.
+    pub fn combos(&self) -> impl Iterator<Item = &str> {
+        self.combos.iter().map(String::as_str)
+    }
+
+    /// The combinations joined with ", ", for logs and messages.
+    pub fn as_str(&self) -> &str {
+        &self.joined
+    }
+}
+
+impl std::fmt::Display for HotkeyKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.joined)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct HotkeyMapping {
-    // Deserialize the hotkey combination as a single string first
-    pub keys: String,
+    // Deserialize the hotkey combination(s) as strings first
+    pub keys: HotkeyKeys,
     // Modifiers and Code will be parsed later in hotkey_manager
     // pub modifiers: Modifiers, // Removed
     // pub key: Code, // Removed
-    pub device_name: String,
+    // Output device to switch to (required unless an action is given)
+    pub device_name: Option<String>,
+    // Endpoint ID of the output device; takes precedence over device-name, which is then just a label
+    #[serde(alias = "id")]
+    pub device_id: Option<String>,
+    // Output devices to cycle through, one step per press (instead of device-name)
+    pub device_names: Option<Vec<String>>,
+    // Output devices to try in order when device-name doesn't resolve to a connected device
+    pub fallback_devices: Option<Vec<String>>,
     // Optional input device to switch to when switching output
     pub input_device_name: Option<String>,
+    // Endpoint ID of the input device; takes precedence over input-device-name
+    pub input_device_id: Option<String>,
+    // Optional output device for the communications role only; device-name then only sets console/multimedia
+    pub comms_device_name: Option<String>,
+    // Which default role(s) the devices are set for
+    #[serde(default)] // Defaults to All if not present
+    pub role: MappingRole,
+    // Which role's default counts as "the current device" for cycling, toggle-back and toggle-recent
+    pub current_role: Option<AudioRole>,
+    // Optional action to run instead of switching devices
+    pub action: Option<HotkeyAction>,
+    // Snapshot name used by the snapshot actions (defaults to "default")
+    pub snapshot: Option<String>,
+    // Volume change per press (and per key repeat) for the volume actions (defaults to 0.05)
+    pub volume_step: Option<f32>,
+    // Volume (0.0 to 1.0) to set on the output device after switching to it
+    pub volume: Option<f32>,
+    // Sample rate and bit depth to set on the output device after switching to it
+    pub format: Option<DeviceFormat>,
+    // Run this hotkey even inside the disable-between window
+    #[serde(default)]
+    pub ignore_dnd: bool,
+    // Name of a [[profiles]] entry to switch to instead of switching devices
+    pub activate_profile: Option<String>,
+    // Keep re-applying these devices if something else changes the default (until the next switch)
+    #[serde(default)]
+    pub sticky: bool,
+    // Every second press returns to the output that was default before the first press
+    #[serde(default)]
+    pub toggle_back: bool,
+    // Program to start after a successful switch (a full path or a program on PATH)
+    pub launch: Option<String>,
+    // Arguments passed to the launch program
+    pub launch_args: Option<Vec<String>>,
+    // Name for this hotkey in logs and notifications (defaults to the keys)
+    pub label: Option<String>,
+    // Steps to run in order, instead of the device fields and 'action' above
+    #[serde(default)] // Defaults to an empty vec (the fields above are used) if not present
+    pub actions: Vec<Action>,
+}
+
+impl HotkeyMapping {
+    /// How the hotkey is referred to in logs and notifications: its label, else its keys.
+    pub fn display_name(&self) -> &str {
+        self.label.as_deref().unwrap_or(self.keys.as_str())
+    }
+
+    /// What the output device is resolved from: the endpoint ID if given, else the name.
+    pub fn output_target(&self) -> Option<&str> {
+        self.device_id.as_deref().or(self.device_name.as_deref())
+    }
+
+    /// The output devices in order of preference: the primary target, then each fallback.
+    pub fn output_priority(&self) -> impl Iterator<Item = &str> {
+        self.output_target()
+            .into_iter()
+            .chain(self.fallback_devices.iter().flatten().map(String::as_str))
+    }
+
+    /// What the input device is resolved from: the endpoint ID if given, else the name.
+    pub fn input_target(&self) -> Option<&str> {
+        self.input_device_id.as_deref().or(self.input_device_name.as_deref())
+    }
+
+    /// The role whose default cycling, toggle-back and toggle-recent treat as the current output:
+    /// `current-role` if given, else communications for a `role = "communications"` mapping (the
+    /// only role it changes), else console.
+    pub fn current_role(&self) -> AudioRole {
+        self.current_role.unwrap_or(match self.role {
+            MappingRole::Communications => AudioRole::Communications,
+            MappingRole::All | MappingRole::Console => AudioRole::Console,
+        })
+    }
+
+    /// Name of the snapshot the snapshot actions read or write.
+    pub fn snapshot_name(&self) -> &str {
+        self.snapshot.as_deref().unwrap_or(DEFAULT_SNAPSHOT_NAME)
+    }
+
+    /// Volume change applied by the volume actions.
+    pub fn volume_step(&self) -> f32 {
+        self.volume_step.unwrap_or(DEFAULT_VOLUME_STEP)
+    }
+
+    /// Whether holding the hotkey should keep triggering it.
+    pub fn is_repeatable(&self) -> bool {
+        self.action.as_ref().is_some_and(HotkeyAction::is_repeatable)
+    }
+
+    /// The steps a press runs: the `actions` list, or a single [`Action::Fields`] step for a
+    /// mapping written with the flat fields.
+    pub fn steps(&self) -> &[Action] {
+        if self.actions.is_empty() { FIELDS_STEP } else { &self.actions }
+    }
+
+    /// Devices named by the `set-output` (or, for `Input`, `set-input`) steps of `actions`.
+    pub fn step_devices(&self, flow: DeviceFlow) -> impl Iterator<Item = &str> {
+        self.actions.iter().filter_map(move |action| match (action, flow) {
+            (Action::SetOutput { device }, DeviceFlow::Output) | (Action::SetInput { device }, DeviceFlow::Input) => {
+                Some(device.as_str())
+            }
+            _ => None,
+        })
+    }
+}
+
+const FIELDS_STEP: &[Action] = &[Action::Fields];
+
+/// One step of a hotkey's `actions` list, written as an inline table with a `type`, e.g.
+/// `{ type = "set-volume", level = 0.4 }`. The steps run in order; a failed step is reported and
+/// the remaining steps still run.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Action {
+    /// Make a device (name or endpoint ID) the default output, for the mapping's `role`
+    SetOutput { device: String },
+    /// Make a device (name or endpoint ID) the default input, for the mapping's `role`
+    SetInput { device: String },
+    /// Set the volume (0.0 to 1.0) of the current default output device
+    SetVolume { level: f32 },
+    /// Mute or unmute the current default output device
+    ToggleMute,
+    /// Start a program (a full path or a program on PATH) without waiting for it
+    Run {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// The mapping's flat fields (`device-name`, `action`, `activate-profile`, ...). A mapping
+    /// without `actions` runs just this step; it can't be written in the list.
+    #[serde(skip)]
+    Fields,
+}
+
+impl Action {
+    /// The `type` the step is written with, for logs and error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::SetOutput { .. } => "set-output",
+            Action::SetInput { .. } => "set-input",
+            Action::SetVolume { .. } => "set-volume",
+            Action::ToggleMute => "toggle-mute",
+            Action::Run { .. } => "run",
+            Action::Fields => "fields",
+        }
+    }
+}
+
+pub const DEFAULT_SNAPSHOT_NAME: &str = "default";
+pub const DEFAULT_VOLUME_STEP: f32 = 0.05;
+
+/// Actions a hotkey can trigger instead of a device switch.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HotkeyAction {
+    /// Capture the current defaults, volumes and mute states into a snapshot file
+    SaveSnapshot,
+    /// Re-apply a previously saved snapshot
+    RestoreSnapshot,
+    /// Raise the default output device's volume by `volume-step`
+    VolumeUp,
+    /// Lower the default output device's volume by `volume-step`
+    VolumeDown,
+    /// Mute or unmute the default output device
+    ToggleMute,
+    /// Switch between the two most recently activated output devices
+    ToggleRecent,
+    /// Make the mapping's devices the default for every role, then verify each role took
+    SetBothRoles,
+    /// Open the tray context menu at the mouse cursor
+    ShowMenu,
+    /// Switch to the output device after the current default, in enumeration order
+    NextOutput,
+    /// Switch to the output device before the current default, in enumeration order
+    PrevOutput,
+    /// Switch to the input device after the current default, in enumeration order
+    NextInput,
+    /// Switch to the input device before the current default, in enumeration order
+    PrevInput,
+    /// Open the config file in the default editor
+    EditConfig,
+}
+
+impl HotkeyAction {
+    /// Whether the action keeps firing while its hotkey is held down (key repeat).
+    pub fn is_repeatable(&self) -> bool {
+        matches!(self, HotkeyAction::VolumeUp | HotkeyAction::VolumeDown)
+    }
+
+    /// Whether the action changes a default device (and can be followed by `launch`).
+    pub fn switches_devices(&self) -> bool {
+        matches!(
+            self,
+            HotkeyAction::ToggleRecent
+                | HotkeyAction::SetBothRoles
+                | HotkeyAction::NextOutput
+                | HotkeyAction::PrevOutput
+                | HotkeyAction::NextInput
+                | HotkeyAction::PrevInput
+        )
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "kebab-case")]
 pub enum FuzzyMatchAlgorithm {
     Skim,
     Levenshtein,
+    Regex, // The configured name is a regular expression; every matching device scores 1.0
+    Glob,  // The configured name is a wildcard pattern (`*`, `?`); every matching device scores 1.0
 }
 
 impl Default for FuzzyMatchAlgorithm {
@@ -34,78 +297,1051 @@ impl Default for FuzzyMatchAlgorithm {
     }
 }
 
+/// What to do when the top fuzzy candidates score within the ambiguity margin.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AmbiguityPolicy {
+    /// Switch to the best-scoring candidate anyway
+    #[default]
+    Pick,
+    /// Don't switch and notify the user about the ambiguous match
+    Refuse,
+}
+
+/// What to do when the current default device of a flow disappears.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceRemovedPolicy {
+    /// Keep whatever Windows picks as the new default
+    #[default]
+    Ignore,
+    /// Switch back to the most recent earlier default that is still present
+    FallbackToPrevious,
+    /// Switch to the first present device named by a hotkey mapping, in config order
+    SwitchToConfigured,
+}
+
+/// What to do when two hotkey mappings bind the same key combination.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateHotkeyPolicy {
+    /// Log a warning; only the first mapping gets the hotkey
+    #[default]
+    Warn,
+    /// Refuse to load the config
+    Error,
+}
+
+/// Which default role(s) a mapping switches its devices for.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MappingRole {
+    /// Default device and default communications device
+    #[default]
+    All,
+    /// Default device only (console and multimedia roles)
+    Console,
+    /// Default communications device only
+    Communications,
+}
+
+impl MappingRole {
+    /// The single role to set, or `None` for all roles.
+    pub fn audio_role(self) -> Option<AudioRole> {
+        match self {
+            MappingRole::All => None,
+            MappingRole::Console => Some(AudioRole::Console),
+            MappingRole::Communications => Some(AudioRole::Communications),
+        }
+    }
+}
+
+/// Which device a mapping with both an output and an input device switches first.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwitchOrder {
+    #[default]
+    OutputFirst,
+    InputFirst,
+}
+
+impl SwitchOrder {
+    /// The device flows in the order they are switched.
+    pub fn flows(self) -> [DeviceFlow; 2] {
+        match self {
+            SwitchOrder::OutputFirst => [DeviceFlow::Output, DeviceFlow::Input],
+            SwitchOrder::InputFirst => [DeviceFlow::Input, DeviceFlow::Output],
+        }
+    }
+}
+
+/// The shared-mode format (the "Default Format" in the device's Sound properties) a mapping
+/// applies to its output device after switching to it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeviceFormat {
+    // Samples per second, e.g. 48000 or 96000
+    #[serde(alias = "sample_rate")]
+    pub sample_rate: u32,
+    // Bits per sample: 16, 24 or 32
+    pub bits: u16,
+}
+
+/// Bit depths `format` accepts.
+pub const SUPPORTED_FORMAT_BITS: [u16; 3] = [16, 24, 32];
+
+/// A named set of devices switched to at once by an `activate-profile` hotkey or the tray menu.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    pub name: String,
+    // Output device to switch to
+    pub device_name: Option<String>,
+    // Input device to switch to
+    pub input_device_name: Option<String>,
+    // Keep re-applying the devices after a hotkey activates the profile (see HotkeyMapping::sticky)
+    #[serde(default)] // Defaults to false if not present
+    pub sticky: bool,
+}
+
+/// A sound confirming a switch: `"none"`, `"beep"` (the Windows default sound) or the path of a .wav file.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(from = "String")]
+pub enum SwitchSound {
+    #[default]
+    None,
+    Beep,
+    File(PathBuf),
+}
+
+impl From<String> for SwitchSound {
+    fn from(value: String) -> Self {
+        match value.to_lowercase().as_str() {
+            "" | "none" => SwitchSound::None,
+            "beep" => SwitchSound::Beep,
+            _ => SwitchSound::File(PathBuf::from(value)),
+        }
+    }
+}
+
+/// A daily local-time window during which hotkeys are ignored, e.g. `["22:00", "07:00"]`.
+/// The window may cross midnight; the end time itself is outside the window.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "Vec<String>")]
+pub struct DndWindow {
+    start_minute: u32, // Minutes since midnight
+    end_minute: u32,
+}
+
+impl TryFrom<Vec<String>> for DndWindow {
+    type Error = String;
+
+    fn try_from(times: Vec<String>) -> Result<Self, Self::Error> {
+        match times.as_slice() {
+            [start, end] => Ok(DndWindow {
+                start_minute: parse_time_of_day(start)?,
+                end_minute: parse_time_of_day(end)?,
+            }),
+            _ => Err(format!(
+                "disable-between needs exactly two times (start and end), got {}",
+                times.len()
+            )),
+        }
+    }
+}
+
+impl DndWindow {
+    /// Whether the given time (minutes since local midnight) falls inside the window.
+    pub fn contains(&self, minute_of_day: u32) -> bool {
+        if self.start_minute <= self.end_minute {
+            (self.start_minute..self.end_minute).contains(&minute_of_day)
+        } else {
+            // Crosses midnight, e.g. 22:00-07:00
+            minute_of_day >= self.start_minute || minute_of_day < self.end_minute
+        }
+    }
+}
+
+/// The `[quiet-hours]` section: a daily local-time window (`start = "22:00"`, `end = "07:00"`) in
+/// which switches happen without a switch sound or toast. Like `disable-between`, the window may
+/// cross midnight and the end time itself is outside it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "QuietHoursTimes")]
+pub struct QuietHours {
+    window: DndWindow,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct QuietHoursTimes {
+    start: String,
+    end: String,
+}
+
+impl TryFrom<QuietHoursTimes> for QuietHours {
+    type Error = String;
+
+    fn try_from(times: QuietHoursTimes) -> Result<Self, Self::Error> {
+        let window = DndWindow {
+            start_minute: parse_time_of_day(&times.start)?,
+            end_minute: parse_time_of_day(&times.end)?,
+        };
+        Ok(QuietHours { window })
+    }
+}
+
+impl QuietHours {
+    /// Whether the given time (minutes since local midnight) falls inside quiet hours.
+    pub fn contains(&self, minute_of_day: u32) -> bool {
+        self.window.contains(minute_of_day)
+    }
+}
+
+// Parses "HH:MM" (24-hour) into minutes since midnight
+fn parse_time_of_day(time: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time '{}', expected HH:MM (24-hour)", time);
+    let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// What to do with a device switch while `max-concurrent-switches` PowerShell switches are running.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwitchLimitPolicy {
+    /// Wait for a running switch to finish
+    #[default]
+    Queue,
+    /// Skip the switch
+    Drop,
+}
+
+/// How configured devices that are missing at startup are reported.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingDevicesNotice {
+    /// A message box that stays until dismissed
+    #[default]
+    Modal,
+    /// A toast notification that doesn't block
+    Toast,
+    /// Only the log
+    Off,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    #[serde(default = "default_config_version")] // Defaults to 1 if not present
+    pub version: u32,
     #[serde(default)] // Defaults to false if not present
     pub fuzzy_match: bool,
     #[serde(default)] // Defaults to Skim if not present
     pub fuzzy_match_algorithm: FuzzyMatchAlgorithm,
-    #[serde(default = "default_fuzzy_match_threshold")] // Defaults to 0.3
+    #[serde(default = "default_fuzzy_match_threshold")] // Defaults to 0.6
     pub fuzzy_match_threshold: f64,
+    #[serde(default)] // Defaults to false if not present
+    pub ignore_parentheticals: bool,
+    #[serde(default = "default_true")] // Defaults to true if not present
+    pub warn_dynamic_names: bool,
+    #[serde(default)] // Defaults to Pick if not present
+    pub on_ambiguous: AmbiguityPolicy,
+    #[serde(default = "default_ambiguity_margin")] // Defaults to 0.02
+    pub ambiguity_margin: f64,
+    #[serde(default)] // Defaults to false if not present
+    pub exact_first_on_ambiguous: bool,
+    #[serde(default = "default_true")] // Defaults to true if not present
+    pub show_switch_notifications: bool,
+    #[serde(default)] // Defaults to None if not present
+    pub switch_sound: SwitchSound,
+    #[serde(default)] // Defaults to None if not present
+    pub failure_sound: SwitchSound,
+    #[serde(default = "default_true")] // Defaults to true if not present
+    pub notification_glyphs: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub sticky_default: bool,
+    #[serde(default = "default_sticky_interval_ms")] // Defaults to 2000
+    pub sticky_interval_ms: u64,
+    #[serde(default = "default_sticky_cooldown_ms")] // Defaults to 2000
+    pub sticky_cooldown_ms: u64,
+    #[serde(default = "default_debounce_ms")] // Defaults to 250, 0 disables
+    pub debounce_ms: u64,
+    #[serde(default)] // Defaults to false if not present
+    pub prefer_communications_input: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub auto_pair_input: bool,
+    #[serde(default)] // Defaults to OutputFirst if not present
+    pub switch_order: SwitchOrder,
+    #[serde(default)] // No do-not-disturb window if not present
+    pub disable_between: Option<DndWindow>,
+    #[serde(default, alias = "quiet_hours")] // No quiet hours if not present
+    pub quiet_hours: Option<QuietHours>,
+    #[serde(default = "default_true")] // Defaults to true if not present
+    pub exit_on_channel_disconnect: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub require_tray: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub no_tray: bool,
+    #[serde(default = "default_max_concurrent_switches")] // Defaults to 1
+    pub max_concurrent_switches: usize,
+    #[serde(default = "default_switch_retries")] // Defaults to 2, 0 disables retries
+    pub switch_retries: u32,
+    #[serde(default)] // Defaults to Queue if not present
+    pub on_switch_limit: SwitchLimitPolicy,
+    #[serde(default = "default_true")] // Defaults to true if not present
+    pub use_powershell_fallback: bool,
+    #[serde(default = "default_true")] // Defaults to true if not present
+    pub powershell_execution_policy_bypass: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub verify_module_integrity: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub enable_ipc: bool,
+    #[serde(default)] // Defaults to false if not present
+    pub restore_on_start: bool,
+    #[serde(default)] // Defaults to 0 (no wait) if not present
+    pub startup_delay_ms: u64,
+    #[serde(default)] // Defaults to Modal if not present
+    pub notify_missing_devices: MissingDevicesNotice,
+    #[serde(default)] // Defaults to Ignore if not present
+    pub on_device_removed: DeviceRemovedPolicy,
+    #[serde(default)] // Defaults to Warn if not present
+    pub on_duplicate_hotkey: DuplicateHotkeyPolicy,
+    #[serde(default = "default_log_dedup_window_ms")] // Defaults to 5000, 0 disables
+    pub log_dedup_window_ms: u64,
+    #[serde(default)] // Defaults to level "info" and 10 MB if not present
+    pub logging: LoggingConfig,
+    #[serde(default)] // Defaults to the built-in icons if not present
+    pub tray_icons: TrayIcons,
+    #[serde(default)] // Defaults to an empty vec if not present
+    pub profiles: Vec<Profile>,
     #[serde(default)] // Defaults to an empty vec if not present
     pub hotkeys: Vec<HotkeyMapping>,
+    #[serde(default)] // Extra files (relative to this one) whose hotkeys are appended to `hotkeys`
+    pub include: Vec<String>,
+    #[serde(skip)] // The file this config was loaded from; set by load_config_from
+    pub source_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// The `[[profiles]]` entry with the given name.
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+}
+
+/// The `[logging]` section. Read once at startup; changes apply after a restart.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct LoggingConfig {
+    #[serde(default = "default_log_level")] // Defaults to "info"
+    pub level: String,
+    #[serde(default = "default_log_max_size_mb")] // Defaults to 10, 0 disables rotation
+    pub max_size_mb: u64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: default_log_level(), max_size_mb: default_log_max_size_mb() }
+    }
+}
+
+impl LoggingConfig {
+    /// The configured level (case-insensitive), or None if it isn't a level name.
+    pub fn level_filter(&self) -> Option<LevelFilter> {
+        self.level.parse().ok()
+    }
+
+    /// Size at which the log file is rotated, in bytes (0 = never).
+    pub fn max_size_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// The `[tray-icons]` section: the tray icon for each category of default output device. A value
+/// is either the name of an icon built into the executable (`headphones-icon`, `speakers-icon`,
+/// `default-icon`) or the path of an `.ico` file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct TrayIcons {
+    #[serde(default = "default_headphones_icon")] // Defaults to "headphones-icon"
+    pub headphones: String,
+    #[serde(default = "default_speakers_icon")] // Defaults to "speakers-icon"
+    pub speakers: String,
+    #[serde(default = "default_tray_icon")] // Defaults to "default-icon"
+    pub default: String,
+}
+
+impl Default for TrayIcons {
+    fn default() -> Self {
+        TrayIcons {
+            headphones: default_headphones_icon(),
+            speakers: default_speakers_icon(),
+            default: default_tray_icon(),
+        }
+    }
+}
+
+fn default_headphones_icon() -> String {
+    "headphones-icon".to_string()
+}
+
+fn default_speakers_icon() -> String {
+    "speakers-icon".to_string()
+}
+
+fn default_tray_icon() -> String {
+    "default-icon".to_string()
+}
+
+fn default_config_version() -> u32 {
+    1 // Configs written before the field existed
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_max_size_mb() -> u64 {
+    10 // Rotate to sound_switch.log.1 past 10 MB
+}
+
+fn default_max_concurrent_switches() -> usize {
+    1 // One PowerShell switch at a time
+}
+
+fn default_switch_retries() -> u32 {
+    2 // A failed PowerShell switch is tried up to three times
+}
+
+fn default_sticky_interval_ms() -> u64 {
+    2000 // How often sticky mappings re-check the current default
+}
+
+fn default_sticky_cooldown_ms() -> u64 {
+    2000 // At most one sticky re-assert every 2 seconds
+}
+
+fn default_debounce_ms() -> u64 {
+    250 // Repeated presses of the same hotkey within 250 ms only switch once
+}
+
+fn default_log_dedup_window_ms() -> u64 {
+    DEFAULT_LOG_DEDUP_WINDOW_MS // Identical log lines within 5 seconds are collapsed
+}
+
+fn default_ambiguity_margin() -> f64 {
+    0.02 // Top two scores within 2% of the best score are considered ambiguous
+}
+
+fn default_true() -> bool {
+    true
 }
 
 fn default_fuzzy_match_threshold() -> f64 {
-    0.8 // More strict default threshold - requires 80% similarity
+    0.6 // Requires 60% similarity
 }
 
-/// Loads configuration from `config.toml`.
+/// Config file names in search precedence. The extension selects the format.
+const CONFIG_FILE_NAMES: [&str; 4] = ["config.json", "config.yaml", "config.yml", "config.toml"];
+
+/// Loads configuration from `config.json`, `config.yaml`/`config.yml` or `config.toml`.
 /// It first looks next to the executable, then falls back to the current working directory.
 pub fn load_config() -> Result<Config, Box<dyn Error>> {
+    load_config_from(&find_config_path()?)
+}
+
+/// Returns the path of the config file that `load_config` uses.
+pub fn find_config_path() -> Result<PathBuf, Box<dyn Error>> {
     let exe_dir = std::env::current_exe()?
         .parent()
         .ok_or("Failed to get parent directory of executable")?
         .to_path_buf();
+    // Fallback for running with `cargo run` where cwd is project root
+    let cwd = std::env::current_dir()?;
 
-    let mut config_path_exe = exe_dir.clone();
-    config_path_exe.push("config.toml");
+    let candidates: Vec<PathBuf> = [&exe_dir, &cwd]
+        .into_iter()
+        .flat_map(|dir| CONFIG_FILE_NAMES.iter().map(move |name| dir.join(name)))
+        .collect();
+    if let Some(path) = candidates.iter().find(|path| path.exists()) {
+        return Ok(path.clone());
+    }
 
-    let mut config_path_cwd = std::env::current_dir()?;
-    config_path_cwd.push("config.toml");
+    // None exists, return error with helpful guidance
+    Err(format!(
+        "Config file not found!\n\n\
+        Searched for {} in:\n\
+        1. Next to executable: {}\n\
+        2. Current working directory: {}\n\n\
+        Please create a config.toml (or config.json/config.yaml) file in one of these locations.\n\
+        Use config.toml.example as a template if available.",
+        CONFIG_FILE_NAMES.join(", "),
+        exe_dir.display(),
+        cwd.display()
+    )
+    .into())
+}
 
-    let config_path_to_use = if config_path_exe.exists() {
-        config_path_exe
-    } else if config_path_cwd.exists() {
-        // Fallback for running with `cargo run` where cwd is project root
-        config_path_cwd
-    } else {
-        // Neither exists, return error with helpful guidance
+/// Parses config file content in the format given by the file's extension (TOML unless
+/// `.json`, `.yaml` or `.yml`). Errors name the format.
+fn parse_config_content<T: DeserializeOwned>(path: &Path, content: &str) -> Result<T, String> {
+    let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("json") => serde_json::from_str(content).map_err(|e| format!("Failed to parse JSON config: {}", e)),
+        Some("yaml" | "yml") => serde_yaml::from_str(content).map_err(|e| format!("Failed to parse YAML config: {}", e)),
+        _ => toml::from_str(content).map_err(|e| format!("Failed to parse TOML config: {}", e)),
+    }
+}
+
+/// The config schema version this build reads. Bump it together with a new entry in
+/// `MIGRATIONS` when a field is renamed or changes meaning.
+pub const CURRENT_CONFIG_VERSION: u32 = 1;
+
+/// Upgrades a raw config by one version and describes each change it made.
+pub type ConfigMigration = fn(&mut serde_json::Map<String, serde_json::Value>) -> Vec<String>;
+
+/// `MIGRATIONS[i]` upgrades a config from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[ConfigMigration] = &[];
+
+// Just the version, read before the full parse so a migration can run first
+#[derive(Deserialize)]
+struct VersionProbe {
+    #[serde(default = "default_config_version")]
+    version: u32,
+}
+
+/// Applies `migrations` to bring the raw config `value` from version `from` to `to`, and sets its
+/// `version` to `to`. Returns the description of every change, prefixed with the versions.
+pub fn migrate_config(
+    value: &mut serde_json::Value,
+    from: u32,
+    to: u32,
+    migrations: &[ConfigMigration],
+) -> Result<Vec<String>, String> {
+    let table = value.as_object_mut().ok_or("The config must be a table of settings")?;
+    let mut changes = Vec::new();
+    for version in from.max(1)..to {
+        let migration = migrations
+            .get(version as usize - 1)
+            .ok_or_else(|| format!("No migration from config version {} to {}", version, version + 1))?;
+        changes.extend(
+            migration(table)
+                .into_iter()
+                .map(|change| format!("version {} -> {}: {}", version, version + 1, change)),
+        );
+    }
+    table.insert("version".to_string(), to.into());
+    Ok(changes)
+}
+
+// Parses the main config file, migrating it first if it was written for an older version
+fn parse_versioned_config(path: &Path, content: &str) -> Result<Config, String> {
+    // A file that doesn't parse at all is reported by the full parse below
+    let version = parse_config_content::<VersionProbe>(path, content).map_or(CURRENT_CONFIG_VERSION, |p| p.version);
+    if version > CURRENT_CONFIG_VERSION {
         return Err(format!(
-            "Config file 'config.toml' not found!\n\n\
-            Searched in:\n\
-            1. Next to executable: {}\n\
-            2. Current working directory: {}\n\n\
-            Please create a config.toml file in one of these locations.\n\
-            Use config.toml.example as a template if available.",
-            config_path_exe.display(),
-            config_path_cwd.display()
-        )
-        .into());
-    };
+            "Config version {} is newer than this build supports (version {}). Update SoundSwitch or use a config written for this version.",
+            version, CURRENT_CONFIG_VERSION
+        ));
+    }
+    if version == CURRENT_CONFIG_VERSION {
+        return parse_config_content(path, content);
+    }
 
+    let mut value: serde_json::Value = parse_config_content(path, content)?;
+    for change in migrate_config(&mut value, version, CURRENT_CONFIG_VERSION, MIGRATIONS)? {
+        info!("Migrated config: {}", change); // Log info
+    }
+    warn!(
+        "Config is version {}; it was migrated in memory. Apply the changes above and set 'version = {}' to skip this.",
+        version, CURRENT_CONFIG_VERSION
+    ); // Log warning
+    serde_json::from_value(value)
+        .map_err(|e| format!("Failed to parse config after migrating from version {}: {}", version, e))
+}
+
+/// Loads the config at `config_path`, appending the hotkeys of any included files.
+pub fn load_config_from(config_path: &Path) -> Result<Config, Box<dyn Error>> {
     info!(
         "Attempting to load config from: {}",
-        config_path_to_use.display()
+        config_path.display()
     );
 
-    let config_content = fs::read_to_string(&config_path_to_use) // Use the correct variable here
+    let config_content = fs::read_to_string(config_path)
         .map_err(|e| {
             format!(
                 "Failed to read config file at {}: {}",
-                config_path_to_use.display(),
+                config_path.display(),
                 e
             )
         })?;
 
-    let config: Config = toml::from_str(&config_content)
-        .map_err(|e| format!("Failed to parse TOML config: {}", e))?;
+    let mut config = parse_versioned_config(config_path, &config_content)?;
+
+    // Append hotkeys from included files (in order, depth-first)
+    let mut include_chain = vec![canonical_path(config_path)];
+    let includes = std::mem::take(&mut config.include);
+    append_included_hotkeys(config_path, &includes, &mut include_chain, &mut config.hotkeys)?;
+    config.include = includes;
+
+    expand_config_paths(&mut config);
+
+    // Sound files are relative to the config file, like includes
+    let config_dir = config_path.parent().unwrap_or_else(|| Path::new("."));
+    for sound in [&mut config.switch_sound, &mut config.failure_sound] {
+        if let SwitchSound::File(path) = sound {
+            if path.is_relative() {
+                *path = config_dir.join(&path);
+            }
+            if !path.is_file() {
+                warn!("Switch sound file {} does not exist", path.display()); // Log warning
+            }
+        }
+    }
+
+    if !(0.0..=1.0).contains(&config.fuzzy_match_threshold) {
+        return Err(format!(
+            "'fuzzy-match-threshold' must be between 0.0 and 1.0, got {}",
+            config.fuzzy_match_threshold
+        )
+        .into());
+    }
+
+    for (i, profile) in config.profiles.iter().enumerate() {
+        if profile.device_name.is_none() && profile.input_device_name.is_none() {
+            return Err(format!(
+                "Profile '{}' must specify 'device-name' and/or 'input-device-name'",
+                profile.name
+            )
+            .into());
+        }
+        if config.profiles[..i].iter().any(|p| p.name == profile.name) {
+            return Err(format!("Profile '{}' is defined more than once", profile.name).into());
+        }
+    }
+
+    for mapping in &config.hotkeys {
+        if mapping.keys.combos().next().is_none() {
+            return Err("A hotkey has an empty 'keys' list".into());
+        }
+        if !mapping.actions.is_empty() {
+            validate_actions(mapping)?;
+            continue;
+        }
+        if mapping.action.is_none()
+            && mapping.activate_profile.is_none()
+            && mapping.output_target().is_none()
+            && mapping.device_names.is_none()
+            && mapping.comms_device_name.is_none()
+            && mapping.input_target().is_none()
+        {
+            return Err(format!(
+                "Hotkey '{}' must specify a device ('device-name', 'device-names', 'comms-device-name', 'input-device-name' or an ID), an 'activate-profile' or an 'action'",
+                mapping.keys
+            )
+            .into());
+        }
+        if let Some(profile_name) = &mapping.activate_profile {
+            if config.profile(profile_name).is_none() {
+                return Err(format!(
+                    "Hotkey '{}' activates profile '{}', which is not defined in [[profiles]]",
+                    mapping.keys, profile_name
+                )
+                .into());
+            }
+            if mapping.action.is_some()
+                || mapping.output_target().is_some()
+                || mapping.device_names.is_some()
+                || mapping.comms_device_name.is_some()
+                || mapping.input_target().is_some()
+            {
+                return Err(format!(
+                    "Hotkey '{}' uses 'activate-profile', which can't be combined with device names or an 'action'",
+                    mapping.keys
+                )
+                .into());
+            }
+        }
+        if mapping.role != MappingRole::All && mapping.comms_device_name.is_some() {
+            return Err(format!(
+                "Hotkey '{}' can't combine 'role' with 'comms-device-name', which already routes the roles separately",
+                mapping.keys
+            )
+            .into());
+        }
+        if let Some(names) = &mapping.device_names {
+            if mapping.output_target().is_some() {
+                return Err(format!(
+                    "Hotkey '{}' can't specify both 'device-name' (or 'device-id') and 'device-names'",
+                    mapping.keys
+                )
+                .into());
+            }
+            if names.is_empty() {
+                return Err(format!("Hotkey '{}' has an empty 'device-names' list", mapping.keys).into());
+            }
+        }
+        if let Some(fallbacks) = &mapping.fallback_devices {
+            if mapping.output_target().is_none() || mapping.device_names.is_some() {
+                return Err(format!(
+                    "Hotkey '{}' uses 'fallback-devices', which needs 'device-name' (or 'device-id') and can't be combined with 'device-names'",
+                    mapping.keys
+                )
+                .into());
+            }
+            if fallbacks.is_empty() {
+                return Err(format!("Hotkey '{}' has an empty 'fallback-devices' list", mapping.keys).into());
+            }
+        }
+        if mapping.toggle_back
+            && (mapping.output_target().is_none() || mapping.device_names.is_some() || mapping.action.is_some())
+        {
+            return Err(format!(
+                "Hotkey '{}' uses 'toggle-back', which needs 'device-name' and can't be combined with 'device-names' or an 'action'",
+                mapping.keys
+            )
+            .into());
+        }
+        if mapping.label.as_deref().is_some_and(|label| label.trim().is_empty()) {
+            return Err(format!("Hotkey '{}' has an empty 'label'", mapping.keys).into());
+        }
+        if mapping.launch.as_deref().is_some_and(|program| program.trim().is_empty()) {
+            return Err(format!("Hotkey '{}' has an empty 'launch' path", mapping.keys).into());
+        }
+        if mapping.launch_args.is_some() && mapping.launch.is_none() {
+            return Err(format!("Hotkey '{}' sets 'launch-args' without 'launch'", mapping.keys).into());
+        }
+        if let Some(format) = &mapping.format {
+            if mapping.output_target().is_none() {
+                return Err(format!("Hotkey '{}' sets 'format' without a 'device-name' or 'device-id'", mapping.keys).into());
+            }
+            if !SUPPORTED_FORMAT_BITS.contains(&format.bits) {
+                return Err(format!("Hotkey '{}': format bits must be 16, 24 or 32, not {}", mapping.keys, format.bits).into());
+            }
+            if !(8_000..=768_000).contains(&format.sample_rate) {
+                return Err(format!(
+                    "Hotkey '{}': format sample-rate {} is outside 8000-768000",
+                    mapping.keys, format.sample_rate
+                )
+                .into());
+            }
+        }
+        // Only switches launch anything, so a launch on e.g. a volume action would never run
+        if mapping.launch.is_some()
+            && mapping
+                .action
+                .as_ref()
+                .is_some_and(|action| !action.switches_devices())
+        {
+            return Err(format!(
+                "Hotkey '{}' uses 'launch' with an action that doesn't switch devices",
+                mapping.keys
+            )
+            .into());
+        }
+        if mapping.action == Some(HotkeyAction::SetBothRoles) {
+            if mapping.output_target().is_none() && mapping.input_target().is_none() {
+                return Err(format!(
+                    "Hotkey '{}' uses action 'set-both-roles' but has no 'device-name' or 'input-device-name'",
+                    mapping.keys
+                )
+                .into());
+            }
+            if mapping.comms_device_name.is_some() || mapping.role != MappingRole::All {
+                return Err(format!(
+                    "Hotkey '{}' uses action 'set-both-roles', which can't be combined with 'comms-device-name' or 'role'",
+                    mapping.keys
+                )
+                .into());
+            }
+        }
+    }
+
+    check_duplicate_keys(&config)?;
 
+    config.source_path = Some(config_path.to_path_buf());
     Ok(config)
 }
 
+// Finds key combinations that are the same once normalized, across all mappings and within a
+// mapping's list. Each later duplicate is reported against the first mapping with that
+// combination, as a warning or an error per config.
+fn check_duplicate_keys(config: &Config) -> Result<(), Box<dyn Error>> {
+    // Combos that parse compare by hotkey ID, so key aliases (`A`/`KeyA`, `Esc`/`Escape`) and
+    // scancodes match too; one that doesn't parse compares by its normalized text
+    let mut first_by_keys: HashMap<Result<u32, String>, (&str, &HotkeyMapping)> = HashMap::new();
+    for (mapping, combo) in config.hotkeys.iter().flat_map(|m| m.keys.combos().map(move |combo| (m, combo))) {
+        let key = parse_hotkey_string(combo).map(|hotkey| hotkey.id()).map_err(|_| normalize_keys(combo));
+        let Some((first_combo, first)) = first_by_keys.get(&key) else {
+            first_by_keys.insert(key, (combo, mapping));
+            continue;
+        };
+        let message = format!(
+            "Hotkeys '{}' ({}) and '{}' ({}) bind the same key combination; only the first one can be registered",
+            first_combo,
+            mapping_target(first),
+            combo,
+            mapping_target(mapping)
+        );
+        match config.on_duplicate_hotkey {
+            DuplicateHotkeyPolicy::Warn => warn!("{}", message),
+            DuplicateHotkeyPolicy::Error => return Err(message.into()),
+        }
+    }
+    Ok(())
+}
+
+// What a mapping switches to or does, to tell duplicates apart in messages
+fn mapping_target(mapping: &HotkeyMapping) -> String {
+    if let Some(device) = mapping.output_target().or(mapping.input_target()) {
+        format!("device '{}'", device)
+    } else if let Some(names) = &mapping.device_names {
+        format!("devices '{}'", names.join("', '"))
+    } else if let Some(profile) = &mapping.activate_profile {
+        format!("profile '{}'", profile)
+    } else if let Some(action) = &mapping.action {
+        format!("action {:?}", action)
+    } else if let Some(comms) = &mapping.comms_device_name {
+        format!("communications device '{}'", comms)
+    } else if let Some(label) = &mapping.label {
+        format!("'{}'", label)
+    } else {
+        format!("{} action steps", mapping.actions.len())
+    }
+}
+
+// Checks a mapping with an `actions` list: the flat fields it replaces must not be set, and
+// every step needs sensible values
+fn validate_actions(mapping: &HotkeyMapping) -> Result<(), Box<dyn Error>> {
+    if mapping.action.is_some()
+        || mapping.activate_profile.is_some()
+        || mapping.output_target().is_some()
+        || mapping.device_names.is_some()
+        || mapping.fallback_devices.is_some()
+        || mapping.comms_device_name.is_some()
+        || mapping.input_target().is_some()
+        || mapping.volume.is_some()
+        || mapping.format.is_some()
+        || mapping.toggle_back
+        || mapping.launch.is_some()
+        || mapping.launch_args.is_some()
+    {
+        return Err(format!(
+            "Hotkey '{}' uses 'actions', which can't be combined with device names, 'action', 'activate-profile', 'volume', 'format', 'toggle-back' or 'launch'",
+            mapping.keys
+        )
+        .into());
+    }
+    if mapping.label.as_deref().is_some_and(|label| label.trim().is_empty()) {
+        return Err(format!("Hotkey '{}' has an empty 'label'", mapping.keys).into());
+    }
+    for (i, action) in mapping.actions.iter().enumerate() {
+        let invalid = match action {
+            Action::SetOutput { device } | Action::SetInput { device } if device.trim().is_empty() => {
+                Some("has an empty 'device'".to_string())
+            }
+            Action::SetVolume { level } if !(0.0..=1.0).contains(level) => {
+                Some(format!("has level {}, which must be between 0.0 and 1.0", level))
+            }
+            Action::Run { program, .. } if program.trim().is_empty() => Some("has an empty 'program'".to_string()),
+            _ => None,
+        };
+        if let Some(problem) = invalid {
+            return Err(format!("Hotkey '{}': action {} ('{}') {}", mapping.keys, i + 1, action.kind(), problem).into());
+        }
+    }
+    Ok(())
+}
+
+/// Expands `~` at the start (the user's profile folder), `%NAME%` and `${NAME}` in a path from the
+/// config. A variable that isn't defined is left as written, with a warning, so a later "file not
+/// found" error shows the path as configured.
+pub fn expand_path(value: &str) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    if let Some(after) = rest.strip_prefix('~')
+        && (after.is_empty() || after.starts_with(['/', '\\']))
+        && let Ok(home) = std::env::var("USERPROFILE").or_else(|_| std::env::var("HOME"))
+    {
+        expanded.push_str(&home);
+        rest = after;
+    }
+    while let Some(start) = rest.find(['%', '$']) {
+        expanded.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        // The variable name and the length of the whole reference
+        let reference = if let Some(inner) = tail.strip_prefix("${") {
+            inner.find('}').map(|end| (&inner[..end], end + 3))
+        } else if let Some(inner) = tail.strip_prefix('%') {
+            inner.find('%').map(|end| (&inner[..end], end + 2))
+        } else {
+            None
+        };
+        match reference {
+            Some((name, len)) if !name.is_empty() => {
+                match std::env::var(name) {
+                    Ok(value) => expanded.push_str(&value),
+                    Err(_) => {
+                        warn!("Environment variable '{}' in '{}' is not defined; leaving it as written", name, value);
+                        expanded.push_str(&tail[..len]);
+                    }
+                }
+                rest = &tail[len..];
+            }
+            _ => {
+                // A lone '%' or '$' is kept
+                expanded.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// Expands environment variables and `~` in every field that holds a path. Device names are left
+// alone: they are matched against what Windows reports.
+fn expand_config_paths(config: &mut Config) {
+    for sound in [&mut config.switch_sound, &mut config.failure_sound] {
+        if let SwitchSound::File(path) = sound {
+            *path = PathBuf::from(expand_path(&path.to_string_lossy()));
+        }
+    }
+    for mapping in &mut config.hotkeys {
+        if let Some(program) = &mut mapping.launch {
+            *program = expand_path(program);
+        }
+        for action in &mut mapping.actions {
+            if let Action::Run { program, .. } = action {
+                *program = expand_path(program);
+            }
+        }
+    }
+    let icons = &mut config.tray_icons;
+    for icon in [&mut icons.headphones, &mut icons.speakers, &mut icons.default] {
+        *icon = expand_path(icon);
+    }
+}
+
+// The part of an included file that is merged into the main config
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct IncludedConfig {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    hotkeys: Vec<HotkeyMapping>,
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Resolves `includes` relative to the including file and appends their hotkeys.
+// `include_chain` holds the files currently being included, to detect cycles.
+fn append_included_hotkeys(
+    including_file: &Path,
+    includes: &[String],
+    include_chain: &mut Vec<PathBuf>,
+    hotkeys: &mut Vec<HotkeyMapping>,
+) -> Result<(), Box<dyn Error>> {
+    let base_dir = including_file.parent().unwrap_or_else(|| Path::new("."));
+
+    for include in includes {
+        let include_path = base_dir.join(expand_path(include));
+        let content = fs::read_to_string(&include_path).map_err(|e| {
+            format!(
+                "Failed to read included file '{}' (included from {}): {}",
+                include_path.display(),
+                including_file.display(),
+                e
+            )
+        })?;
+
+        let canonical = canonical_path(&include_path);
+        if include_chain.contains(&canonical) {
+            return Err(format!(
+                "Include cycle detected: '{}' (included from {}) is already being included",
+                include_path.display(),
+                including_file.display()
+            )
+            .into());
+        }
+
+        let included: IncludedConfig = parse_config_content(&include_path, &content).map_err(|e| {
+            format!(
+                "Failed to parse included file '{}' (included from {}): {}",
+                include_path.display(),
+                including_file.display(),
+                e
+            )
+        })?;
+        info!(
+            "Including {} hotkeys from {}",
+            included.hotkeys.len(),
+            include_path.display()
+        );
+        hotkeys.extend(included.hotkeys);
+
+        include_chain.push(canonical);
+        append_included_hotkeys(&include_path, &included.include, include_chain, hotkeys)?;
+        include_chain.pop();
+    }
+
+    Ok(())
+}
+
 // Removed unused function get_executable_dir
 
 // --- Removed serde helpers and FromStr implementations ---
 // Parsing logic moved to hotkey_manager.rs
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_path_replaces_defined_variables() {
+        let profile = std::env::var("USERPROFILE").expect("USERPROFILE is set on Windows");
+        assert_eq!(expand_path("%USERPROFILE%\\ding.wav"), format!("{}\\ding.wav", profile));
+        assert_eq!(expand_path("${USERPROFILE}\\ding.wav"), format!("{}\\ding.wav", profile));
+        assert_eq!(expand_path("~\\ding.wav"), format!("{}\\ding.wav", profile));
+    }
+
+    #[test]
+    fn expand_path_leaves_undefined_variables_and_plain_paths() {
+        assert_eq!(expand_path("%SOUNDSWITCH_UNDEFINED_VAR%\\ding.wav"), "%SOUNDSWITCH_UNDEFINED_VAR%\\ding.wav");
+        assert_eq!(expand_path("${SOUNDSWITCH_UNDEFINED_VAR}/x"), "${SOUNDSWITCH_UNDEFINED_VAR}/x");
+        assert_eq!(expand_path("C:\\Sounds\\100% ding.wav"), "C:\\Sounds\\100% ding.wav");
+        assert_eq!(expand_path("~other\\ding.wav"), "~other\\ding.wav");
+    }
+
+    #[test]
+    fn config_migrations_run_in_order_from_the_file_version() {
+        fn rename_old_fuzzy(table: &mut serde_json::Map<String, serde_json::Value>) -> Vec<String> {
+            match table.remove("old-fuzzy") {
+                Some(value) => {
+                    table.insert("fuzzy-match".to_string(), value);
+                    vec!["renamed 'old-fuzzy' to 'fuzzy-match'".to_string()]
+                }
+                None => Vec::new(),
+            }
+        }
+        fn nothing(_: &mut serde_json::Map<String, serde_json::Value>) -> Vec<String> {
+            Vec::new()
+        }
+
+        let mut value = serde_json::json!({ "old-fuzzy": true });
+        let changes = migrate_config(&mut value, 1, 3, &[rename_old_fuzzy, nothing]).unwrap();
+
+        assert_eq!(changes, vec!["version 1 -> 2: renamed 'old-fuzzy' to 'fuzzy-match'".to_string()]);
+        let config: Config = serde_json::from_value(value).unwrap();
+        assert!(config.fuzzy_match);
+        assert_eq!(config.version, 3);
+        assert!(migrate_config(&mut serde_json::json!({}), 1, 3, &[nothing]).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, error::Error, fs, path::PathBuf};
+use windows::Win32::Media::Audio::{
+    DEVICE_STATE_ACTIVE, DEVICE_STATE_DISABLED, DEVICE_STATE_NOTPRESENT, DEVICE_STATE_UNPLUGGED,
+};
+
+/// Maximum number of events kept in memory and in the history file.
+pub const MAX_HISTORY_EVENTS: usize = 200;
+
+const HISTORY_FILE_NAME: &str = "device_history.toml";
+
+/// What happened to a device.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceChange {
+    Added,
+    Removed,
+    Active,
+    Disabled,
+    NotPresent,
+    Unplugged,
+}
+
+impl DeviceChange {
+    /// Maps a `DEVICE_STATE_*` value reported by a state-change notification.
+    pub fn from_state(state: u32) -> Option<Self> {
+        match state {
+            s if s == DEVICE_STATE_ACTIVE.0 => Some(DeviceChange::Active),
+            s if s == DEVICE_STATE_DISABLED.0 => Some(DeviceChange::Disabled),
+            s if s == DEVICE_STATE_NOTPRESENT.0 => Some(DeviceChange::NotPresent),
+            s if s == DEVICE_STATE_UNPLUGGED.0 => Some(DeviceChange::Unplugged),
+            _ => None,
+        }
+    }
+}
+
+/// A single timestamped device change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeviceHistoryEvent {
+    pub timestamp: String,
+    pub change: DeviceChange,
+    pub device_name: String,
+    pub device_id: String,
+}
+
+/// Bounded history of device add/remove/state events, persisted next to the executable
+/// so it survives restarts.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DeviceHistory {
+    #[serde(default)]
+    events: VecDeque<DeviceHistoryEvent>,
+}
+
+fn history_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut path = std::env::current_exe()?
+        .parent()
+        .ok_or("Failed to get parent directory of executable")?
+        .to_path_buf();
+    path.push(HISTORY_FILE_NAME);
+    Ok(path)
+}
+
+/// Current local time formatted as `YYYY-MM-DD HH:MM:SS`.
+pub fn local_timestamp() -> String {
+    let now = unsafe { windows::Win32::System::SystemInformation::GetLocalTime() };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        now.wYear, now.wMonth, now.wDay, now.wHour, now.wMinute, now.wSecond
+    )
+}
+
+impl DeviceHistory {
+    /// Loads the persisted history. A missing or unreadable file yields an empty history.
+    pub fn load() -> Self {
+        let path = match history_path() {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Could not determine device history path: {}", e);
+                return DeviceHistory::default();
+            }
+        };
+        if !path.exists() {
+            return DeviceHistory::default();
+        }
+
+        match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|content| {
+            toml::from_str::<DeviceHistory>(&content).map_err(|e| e.to_string())
+        }) {
+            Ok(mut history) => {
+                history.truncate();
+                history
+            }
+            Err(e) => {
+                warn!("Ignoring unreadable device history file {}: {}", path.display(), e);
+                DeviceHistory::default()
+            }
+        }
+    }
+
+    /// Writes the history to disk.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = history_path()?;
+        let content = toml::to_string_pretty(self)?;
+        fs::write(&path, content)
+            .map_err(|e| format!("Failed to write device history {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    /// Appends an event stamped with the current local time, dropping the oldest beyond the limit.
+    pub fn record(&mut self, change: DeviceChange, device_id: &str, device_name: &str) {
+        let event = DeviceHistoryEvent {
+            timestamp: local_timestamp(),
+            change,
+            device_name: device_name.to_string(),
+            device_id: device_id.to_string(),
+        };
+        info!(
+            "Device history: {} {:?} '{}' ({})",
+            event.timestamp, event.change, event.device_name, event.device_id
+        );
+        self.events.push_back(event);
+        self.truncate();
+    }
+
+    /// Events from oldest to newest.
+    pub fn events(&self) -> impl Iterator<Item = &DeviceHistoryEvent> {
+        self.events.iter()
+    }
+
+    fn truncate(&mut self) {
+        while self.events.len() > MAX_HISTORY_EVENTS {
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Prints the persisted device history to stdout (used by `--device-history`).
+pub fn print_device_history() {
+    let history = DeviceHistory::load();
+    if history.events.is_empty() {
+        println!("No device events recorded yet.");
+        return;
+    }
+
+    println!("{:<19}  {:<10}  {:<40}  ID", "Time", "Change", "Device");
+    for event in history.events() {
+        println!(
+            "{:<19}  {:<10}  {:<40}  {}",
+            event.timestamp,
+            format!("{:?}", event.change),
+            event.device_name,
+            event.device_id
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use crate::audio_device::{AudioRole, DeviceFlow};
+use log::{error, info};
+use windows::{
+    Win32::{
+        Foundation::PROPERTYKEY,
+        Media::Audio::{
+            DEVICE_STATE, EDataFlow, ERole, IMMDeviceEnumerator, IMMNotificationClient,
+            IMMNotificationClient_Impl, MMDeviceEnumerator,
+        },
+        System::Com::{CLSCTX_ALL, CoCreateInstance},
+    },
+    core::{PCWSTR, Result, implement},
+};
+
+/// Endpoint changes reported by Windows through `IMMNotificationClient`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added { device_id: String },
+    Removed { device_id: String },
+    StateChanged { device_id: String, state: u32 },
+    DefaultChanged {
+        flow: DeviceFlow,
+        role: AudioRole,
+        device_id: Option<String>,
+    },
+}
+
+// Receives endpoint notifications on a Windows-owned thread and forwards them over a channel.
+// The callbacks must return quickly and must not call back into Core Audio, so all real
+// handling happens on the listener thread that drains the channel.
+#[implement(IMMNotificationClient)]
+struct NotificationClient {
+    sender: crossbeam_channel::Sender<DeviceEvent>,
+}
+
+fn pcwstr_to_string(value: &PCWSTR) -> Option<String> {
+    if value.is_null() {
+        None
+    } else {
+        unsafe { value.to_string().ok() }
+    }
+}
+
+impl IMMNotificationClient_Impl for NotificationClient_Impl {
+    fn OnDeviceStateChanged(&self, pwstrdeviceid: &PCWSTR, dwnewstate: DEVICE_STATE) -> Result<()> {
+        if let Some(device_id) = pcwstr_to_string(pwstrdeviceid) {
+            let _ = self.sender.send(DeviceEvent::StateChanged {
+                device_id,
+                state: dwnewstate.0,
+            });
+        }
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, pwstrdeviceid: &PCWSTR) -> Result<()> {
+        if let Some(device_id) = pcwstr_to_string(pwstrdeviceid) {
+            let _ = self.sender.send(DeviceEvent::Added { device_id });
+        }
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, pwstrdeviceid: &PCWSTR) -> Result<()> {
+        if let Some(device_id) = pcwstr_to_string(pwstrdeviceid) {
+            let _ = self.sender.send(DeviceEvent::Removed { device_id });
+        }
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(&self, flow: EDataFlow, role: ERole, pwstrdefaultdeviceid: &PCWSTR) -> Result<()> {
+        if let (Some(flow), Some(role)) = (DeviceFlow::from_data_flow(flow), AudioRole::from_erole(role)) {
+            let _ = self.sender.send(DeviceEvent::DefaultChanged {
+                flow,
+                role,
+                device_id: pcwstr_to_string(pwstrdefaultdeviceid),
+            });
+        }
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _pwstrdeviceid: &PCWSTR, _key: &PROPERTYKEY) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps an endpoint notification callback registered for as long as it is alive.
+/// Must be created and dropped on a thread with COM initialized.
+pub struct DeviceNotifier {
+    enumerator: IMMDeviceEnumerator,
+    client: IMMNotificationClient,
+}
+
+impl DeviceNotifier {
+    /// Registers for endpoint notifications, forwarding them to `sender`.
+    pub fn register(sender: crossbeam_channel::Sender<DeviceEvent>) -> Result<Self> {
+        unsafe {
+            let enumerator: IMMDeviceEnumerator =
+                CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            let client: IMMNotificationClient = NotificationClient { sender }.into();
+            enumerator.RegisterEndpointNotificationCallback(&client)?;
+            info!("Registered audio endpoint notification callback."); // Log info
+            Ok(DeviceNotifier { enumerator, client })
+        }
+    }
+}
+
+impl Drop for DeviceNotifier {
+    fn drop(&mut self) {
+        match unsafe { self.enumerator.UnregisterEndpointNotificationCallback(&self.client) } {
+            Ok(()) => info!("Unregistered audio endpoint notification callback."), // Log info
+            Err(e) => error!("Failed to unregister endpoint notification callback: {}", e), // Log error
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::audio_device::{AudioRole, DeviceFlow, get_default_device, list_devices_with_state};
+use crate::config::{find_config_path, load_config_from};
+use crate::log_file::LOG_FILE_NAME;
+use std::fmt::Write as _;
+use std::{error::Error, fs, path::PathBuf};
+
+const DIAGNOSTICS_FILE_NAME: &str = "soundswitch-diagnostics.txt";
+const LOG_TAIL_LINES: usize = 200;
+
+/// Collects everything useful for a bug report into `soundswitch-diagnostics.txt` in the
+/// current directory and returns its path. Nothing is redacted: the file contains device
+/// names and IDs as well as the full config.
+pub fn write_diagnostics() -> Result<PathBuf, Box<dyn Error>> {
+    let mut report = String::new();
+    writeln!(report, "SoundSwitch diagnostics")?;
+    writeln!(report, "Version: {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(report, "Executable: {}", std::env::current_exe()?.display())?;
+
+    write_config_section(&mut report)?;
+    for flow in [DeviceFlow::Output, DeviceFlow::Input] {
+        write_devices_section(&mut report, flow)?;
+    }
+    write_log_section(&mut report)?;
+
+    let path = std::env::current_dir()?.join(DIAGNOSTICS_FILE_NAME);
+    fs::write(&path, report)
+        .map_err(|e| format!("Failed to write diagnostics file {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// The resolved config path, whether it loads, and its raw contents
+fn write_config_section(report: &mut String) -> Result<(), Box<dyn Error>> {
+    writeln!(report, "\n== Config ==")?;
+    let path = match find_config_path() {
+        Ok(path) => path,
+        Err(e) => {
+            writeln!(report, "{}", e)?;
+            return Ok(());
+        }
+    };
+    writeln!(report, "Path: {}", path.display())?;
+    match load_config_from(&path) {
+        Ok(config) => writeln!(
+            report,
+            "Loads OK (version {}, {} hotkeys including includes)",
+            config.version,
+            config.hotkeys.len()
+        )?,
+        Err(e) => writeln!(report, "Fails to load: {}", e)?,
+    }
+    match fs::read_to_string(&path) {
+        Ok(content) => writeln!(report, "\n{}", content.trim_end())?,
+        Err(e) => writeln!(report, "Failed to read: {}", e)?,
+    }
+    Ok(())
+}
+
+// Every device of one flow with its state, followed by the current default per role
+fn write_devices_section(report: &mut String, flow: DeviceFlow) -> Result<(), Box<dyn Error>> {
+    writeln!(report, "\n== {:?} devices ==", flow)?;
+    match list_devices_with_state(flow) {
+        Ok(devices) => {
+            for device in devices {
+                writeln!(
+                    report,
+                    "[{}] {} ({:?}, adapter: {})\n    {}",
+                    device.state,
+                    device.name,
+                    device.form_factor,
+                    device.adapter_name.as_deref().unwrap_or("unknown"),
+                    device.id
+                )?;
+            }
+        }
+        Err(e) => writeln!(report, "Failed to enumerate: {}", e)?,
+    }
+
+    writeln!(report, "\nDefaults:")?;
+    for role in AudioRole::ALL {
+        match get_default_device(flow, role) {
+            Ok(device) => writeln!(report, "  {:?}: {} ({})", role, device.name, device.id)?,
+            Err(e) => writeln!(report, "  {:?}: none ({})", role, e)?,
+        }
+    }
+    Ok(())
+}
+
+// The last lines of the log file
+fn write_log_section(report: &mut String) -> Result<(), Box<dyn Error>> {
+    writeln!(report, "\n== Log (last {} lines of {}) ==", LOG_TAIL_LINES, LOG_FILE_NAME)?;
+    match fs::read(LOG_FILE_NAME) {
+        Ok(bytes) => {
+            let log = String::from_utf8_lossy(&bytes);
+            let lines: Vec<&str> = log.lines().collect();
+            for line in &lines[lines.len().saturating_sub(LOG_TAIL_LINES)..] {
+                writeln!(report, "{}", line)?;
+            }
+        }
+        Err(e) => writeln!(report, "Failed to read: {}", e)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use log::warn;
+use windows::{
+    Win32::{
+        Foundation::{ERROR_CLASS_ALREADY_EXISTS, GetLastError, HWND},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::WindowsAndMessaging::{
+            CreateWindowExW, DestroyWindow, RegisterClassW, WINDOW_EX_STYLE, WNDCLASSW, WNDPROC, WS_OVERLAPPED,
+        },
+    },
+    core::{PCWSTR, Result, w},
+};
+
+/// A hidden top-level window for system broadcasts (power, session end) that only reach
+/// top-level windows, not thread message queues or message-only windows. The thread that creates
+/// it must keep pumping messages for them to arrive. The window is destroyed on drop.
+pub struct HiddenWindow {
+    hwnd: HWND,
+}
+
+impl HiddenWindow {
+    /// Registers the window class with `window_proc`, unless it already is, and creates a window
+    /// of it for the calling thread.
+    pub fn create(class_name: PCWSTR, window_proc: WNDPROC) -> Result<HiddenWindow> {
+        unsafe {
+            let instance = GetModuleHandleW(None)?;
+            let class = WNDCLASSW {
+                lpfnWndProc: window_proc,
+                hInstance: instance.into(),
+                lpszClassName: class_name,
+                ..Default::default()
+            };
+            // A restarted listener thread registers the class again
+            if RegisterClassW(&class) == 0 && GetLastError() != ERROR_CLASS_ALREADY_EXISTS {
+                return Err(windows::core::Error::from_win32());
+            }
+            // Never shown: WS_VISIBLE isn't set
+            let hwnd = CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                class_name,
+                w!("SoundSwitch"),
+                WS_OVERLAPPED,
+                0,
+                0,
+                0,
+                0,
+                None,
+                None,
+                Some(instance.into()),
+                None,
+            )?;
+            Ok(HiddenWindow { hwnd })
+        }
+    }
+}
+
+impl Drop for HiddenWindow {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { DestroyWindow(self.hwnd) } {
+            warn!("Failed to destroy hidden window: {}", e);
+        }
+    }
+}
@@ -1,12 +1,94 @@
-use crate::config::{Config, HotkeyMapping};
+use crate::config::{Config, HotkeyAction, HotkeyMapping};
 use global_hotkey::{
     GlobalHotKeyManager, // Removed unused HotKeyState
     hotkey::{Code, HotKey, Modifiers},
 };
-use std::{collections::HashMap, error::Error}; // Removed unused str::FromStr
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap; // Removed unused str::FromStr
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    MAPVK_VSC_TO_VK_EX, MapVirtualKeyW, VK_ADD, VK_BACK, VK_CAPITAL, VK_DECIMAL, VK_DELETE, VK_DIVIDE, VK_DOWN,
+    VK_END, VK_ESCAPE, VK_HOME, VK_INSERT, VK_LEFT, VK_MEDIA_NEXT_TRACK, VK_MEDIA_PLAY_PAUSE,
+    VK_MEDIA_PREV_TRACK, VK_MEDIA_STOP, VK_MULTIPLY, VK_NEXT, VK_NUMLOCK, VK_OEM_1, VK_OEM_2, VK_OEM_3,
+    VK_OEM_4, VK_OEM_5, VK_OEM_6, VK_OEM_7, VK_OEM_COMMA, VK_OEM_MINUS, VK_OEM_PERIOD, VK_OEM_PLUS, VK_PAUSE,
+    VK_PRIOR, VK_RETURN, VK_RIGHT, VK_SCROLL, VK_SNAPSHOT, VK_SPACE, VK_SUBTRACT, VK_TAB, VK_UP,
+    VK_VOLUME_DOWN, VK_VOLUME_MUTE, VK_VOLUME_UP, VIRTUAL_KEY,
+};
 
-/// Represents the mapping from a registered hotkey ID to the complete hotkey configuration.
-pub type HotkeyDeviceMap = HashMap<u32, HotkeyMapping>;
+/// Represents the mapping from registered hotkey IDs to the complete hotkey configuration.
+/// A mapping with several key combinations is stored once and shared by all of their IDs.
+#[derive(Debug, Default, Clone)]
+pub struct HotkeyDeviceMap {
+    // Each mapping with the ID of its first registered combination
+    mappings: Vec<(u32, HotkeyMapping)>,
+    // Hotkey ID to index into `mappings`
+    ids: HashMap<u32, usize>,
+}
+
+impl HotkeyDeviceMap {
+    /// Points every hotkey ID in `ids` (the registered combinations of one entry) at `mapping`.
+    pub fn insert(&mut self, ids: &[u32], mapping: HotkeyMapping) {
+        let Some(&primary) = ids.first() else {
+            return;
+        };
+        let index = self.mappings.len();
+        self.mappings.push((primary, mapping));
+        self.ids.extend(ids.iter().map(|&id| (id, index)));
+    }
+
+    /// The mapping a hotkey ID triggers.
+    pub fn get(&self, id: u32) -> Option<&HotkeyMapping> {
+        self.ids.get(&id).map(|&index| &self.mappings[index].1)
+    }
+
+    /// The ID of the first registered combination of the mapping `id` triggers. It is the same
+    /// for every combination of a mapping, so per-mapping state can be keyed by it.
+    pub fn primary_id(&self, id: u32) -> Option<u32> {
+        self.ids.get(&id).map(|&index| self.mappings[index].0)
+    }
+
+    /// The number of registered hotkey IDs.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Result of `register_hotkeys`: the ID map, the registered hotkeys, a report per mapping
+/// and the `(keys, error)` pairs of the mappings that couldn't be registered.
+pub type RegisteredHotkeys = (HotkeyDeviceMap, Vec<HotKey>, Vec<HotkeyRegistration>, Vec<(String, String)>);
+
+/// How a configured mapping was interpreted and whether its hotkey was registered.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct HotkeyRegistration {
+    /// The key combination as written in the config (one entry of a `keys` list)
+    pub keys: String,
+    /// The parsed combination (e.g. "shift+control+Digit1"), if it could be parsed
+    pub parsed: Option<String>,
+    pub device_name: Option<String>,
+    pub input_device_name: Option<String>,
+    pub action: Option<HotkeyAction>,
+    pub registered: bool,
+    pub error: Option<String>,
+}
+
+impl HotkeyRegistration {
+    fn new(mapping: &HotkeyMapping, combo: &str, hotkey: Option<&HotKey>, result: Result<(), String>) -> Self {
+        HotkeyRegistration {
+            keys: combo.to_string(),
+            parsed: hotkey.map(HotKey::to_string),
+            device_name: mapping.device_name.clone(),
+            input_device_name: mapping.input_device_name.clone(),
+            action: mapping.action.clone(),
+            registered: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
 
 /// Registers hotkeys defined in the configuration.
 ///
@@ -14,35 +96,54 @@ pub type HotkeyDeviceMap = HashMap<u32, HotkeyMapping>;
 /// registering each hotkey specified in the config.
 ///
 /// Returns a `HotkeyDeviceMap` mapping the registered hotkey IDs to their corresponding
-/// device configurations, and a `Vec<HotKey>` containing the registered hotkeys for later unregistration.
+/// device configurations, a `Vec<HotKey>` containing the registered hotkeys for later unregistration,
+/// a `HotkeyRegistration` per key combination describing how it was interpreted, and the failures.
+/// Each combination is attempted independently: one that fails to parse or register is skipped
+/// and reported, so one bad line (or one bad entry of a `keys` list) doesn't disable the others.
 /// Requires a reference to the `GlobalHotKeyManager` created in the appropriate thread.
 pub fn register_hotkeys(
     manager: &GlobalHotKeyManager,
     config: &Config,
-) -> Result<(HotkeyDeviceMap, Vec<HotKey>), Box<dyn Error>> {
-    let mut hotkey_device_map: HotkeyDeviceMap = HashMap::new();
+) -> RegisteredHotkeys {
+    let mut hotkey_device_map = HotkeyDeviceMap::default();
+    let mut registrations: Vec<HotkeyRegistration> = Vec::new();
 
     println!("Registering hotkeys..."); // Debugging output
 
     let mut hotkeys: Vec<HotKey> = Vec::new(); // Store hotkeys for later use
     for mapping in &config.hotkeys {
-        // Parse the combined keys string (e.g., "Ctrl+Alt+1")
-        let hotkey = parse_hotkey_string(&mapping.keys)
-            .map_err(|e| format!("Failed to parse hotkey string '{}': {}", mapping.keys, e))?;
-
-        hotkeys.push(hotkey.clone()); // Store the hotkey for later use
+        let mut ids: Vec<u32> = Vec::new();
+        for combo in mapping.keys.combos() {
+            // Parse the combined keys string (e.g., "Ctrl+Alt+1")
+            let hotkey = match parse_hotkey_string(combo) {
+                Ok(hotkey) => hotkey,
+                Err(e) => {
+                    warn!("Failed to parse hotkey string '{}': {}", combo, e); // Log warning
+                    registrations.push(HotkeyRegistration::new(mapping, combo, None, Err(e)));
+                    continue;
+                }
+            };
 
-        let id = hotkey.id(); // Get the unique ID generated by the HotKey struct
+            let id = hotkey.id(); // Get the unique ID generated by the HotKey struct
 
-        println!(
-            "  Registering: Keys='{}' -> Modifiers={:?}, Key={:?}, ID={}, Device='{}', Input Device='{:?}'",
-            mapping.keys, hotkey.mods, hotkey.key, id, mapping.device_name, mapping.input_device_name
-        ); // More detailed debug
+            println!(
+                "  Registering: Keys='{}' -> Modifiers={:?}, Key={:?}, ID={}, Device='{:?}', Input Device='{:?}', Action={:?}",
+                combo, hotkey.mods, hotkey.key, id, mapping.device_name, mapping.input_device_name, mapping.action
+            ); // More detailed debug
 
-        manager.register(hotkey)?;
+            // Fails e.g. if another application (or an earlier line) already uses the combination
+            if let Err(e) = manager.register(hotkey) {
+                warn!("Failed to register hotkey '{}': {}", combo, e); // Log warning
+                registrations.push(HotkeyRegistration::new(mapping, combo, Some(&hotkey), Err(e.to_string())));
+                continue;
+            }
+            hotkeys.push(hotkey); // Store the hotkey for later unregistration
+            registrations.push(HotkeyRegistration::new(mapping, combo, Some(&hotkey), Ok(())));
+            ids.push(id);
+        }
 
-        // Store the mapping from the hotkey's ID to the complete mapping configuration
-        hotkey_device_map.insert(id, mapping.clone());
+        // Point every registered combination's ID at the complete mapping configuration
+        hotkey_device_map.insert(&ids, mapping.clone());
     }
 
     if hotkey_device_map.is_empty() {
@@ -54,11 +155,40 @@ pub fn register_hotkeys(
         );
     }
 
-    Ok((hotkey_device_map, hotkeys))
+    let failures = registrations
+        .iter()
+        .filter(|r| !r.registered)
+        .map(|r| (r.keys.clone(), r.error.clone().unwrap_or_default()))
+        .collect();
+    (hotkey_device_map, hotkeys, registrations, failures)
+}
+
+/// Tries to register every key combination independently (then unregisters them again) and
+/// reports how each one was interpreted, including parse errors and conflicts with other
+/// registrations.
+pub fn probe_hotkeys(manager: &GlobalHotKeyManager, config: &Config) -> Vec<HotkeyRegistration> {
+    let mut registered: Vec<HotKey> = Vec::new();
+    let registrations = config
+        .hotkeys
+        .iter()
+        .flat_map(|mapping| mapping.keys.combos().map(move |combo| (mapping, combo)))
+        .map(|(mapping, combo)| match parse_hotkey_string(combo) {
+            Ok(hotkey) => {
+                let result = manager.register(hotkey).map_err(|e| e.to_string());
+                if result.is_ok() {
+                    registered.push(hotkey);
+                }
+                HotkeyRegistration::new(mapping, combo, Some(&hotkey), result)
+            }
+            Err(e) => HotkeyRegistration::new(mapping, combo, None, Err(e)),
+        })
+        .collect();
+    let _ = manager.unregister_all(&registered);
+    registrations
 }
 
 /// Parses a string like "Ctrl+Alt+F1" into a `HotKey`.
-fn parse_hotkey_string(s: &str) -> Result<HotKey, String> {
+pub fn parse_hotkey_string(s: &str) -> Result<HotKey, String> {
     let parts: Vec<&str> = s
         .split('+')
         .map(str::trim)
@@ -88,11 +218,111 @@ fn parse_hotkey_string(s: &str) -> Result<HotKey, String> {
     }
 
     let key_str = key_part.ok_or("No key part found in hotkey string")?;
-    let key_code = parse_key_code(key_str)?;
+    let key_code = match key_str.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("sc:") => code_for_scancode(parse_scancode(key_str)?)?,
+        _ => parse_key_code(key_str)?,
+    };
+    if modifiers.is_empty() && takes_over_typing(key_code) {
+        return Err(format!(
+            "'{}' needs a modifier: without one it would be swallowed everywhere. \
+            Letters, digits, punctuation, Space, Enter, Tab and Backspace can't be used alone",
+            key_str
+        ));
+    }
 
     Ok(HotKey::new(Some(modifiers), key_code))
 }
 
+/// Canonical form of a `keys` string for comparing mappings: lowercase, modifier aliases unified
+/// (`control` is `ctrl`, `option` is `alt`, `super`/`meta` are `win`) and modifiers in a fixed
+/// order, so `Ctrl+Shift+A` and `shift+ctrl+a` normalize to the same `ctrl+shift+a`. The string
+/// doesn't have to be a valid hotkey.
+pub fn normalize_keys(keys: &str) -> String {
+    let mut parts: Vec<String> = keys
+        .split('+')
+        .map(|part| part.trim().to_lowercase())
+        .filter(|part| !part.is_empty())
+        .collect();
+    let Some(key) = parts.pop() else {
+        return String::new();
+    };
+    let rank = |modifier: &str| ["ctrl", "alt", "shift", "win"].iter().position(|m| *m == modifier);
+    let mut modifiers: Vec<String> = parts
+        .into_iter()
+        .map(|part| match part.as_str() {
+            "control" => "ctrl".to_string(),
+            "option" => "alt".to_string(),
+            "super" | "meta" => "win".to_string(),
+            _ => part,
+        })
+        .collect();
+    // Unknown modifiers sort after the known ones; parse_hotkey_string reports them
+    modifiers.sort_by(|a, b| rank(a).unwrap_or(usize::MAX).cmp(&rank(b).unwrap_or(usize::MAX)).then(a.cmp(b)));
+    modifiers.dedup();
+    modifiers.push(key);
+    modifiers.join("+")
+}
+
+/// Keys that can't be bound without a modifier: letters, digits, punctuation, Space, Enter, Tab
+/// and Backspace. A hotkey swallows its key system-wide, so a bare one would break normal typing.
+fn takes_over_typing(code: Code) -> bool {
+    matches!(
+        code,
+        Code::KeyA
+            | Code::KeyB
+            | Code::KeyC
+            | Code::KeyD
+            | Code::KeyE
+            | Code::KeyF
+            | Code::KeyG
+            | Code::KeyH
+            | Code::KeyI
+            | Code::KeyJ
+            | Code::KeyK
+            | Code::KeyL
+            | Code::KeyM
+            | Code::KeyN
+            | Code::KeyO
+            | Code::KeyP
+            | Code::KeyQ
+            | Code::KeyR
+            | Code::KeyS
+            | Code::KeyT
+            | Code::KeyU
+            | Code::KeyV
+            | Code::KeyW
+            | Code::KeyX
+            | Code::KeyY
+            | Code::KeyZ
+            | Code::Digit0
+            | Code::Digit1
+            | Code::Digit2
+            | Code::Digit3
+            | Code::Digit4
+            | Code::Digit5
+            | Code::Digit6
+            | Code::Digit7
+            | Code::Digit8
+            | Code::Digit9
+            | Code::Backquote
+            | Code::Minus
+            | Code::Equal
+            | Code::BracketLeft
+            | Code::BracketRight
+            | Code::Backslash
+            | Code::IntlBackslash
+            | Code::Semicolon
+            | Code::Quote
+            | Code::Comma
+            | Code::Period
+            | Code::Slash
+            | Code::Space
+            | Code::Enter
+            | Code::Tab
+            | Code::Backspace
+    )
+}
+
 /// Parses the key code string (e.g., "F1", "KeyA", "1") into a `Code`.
 /// This is essentially the `FromStr` logic moved here.
 fn parse_key_code(s: &str) -> Result<Code, String> {
@@ -146,6 +376,18 @@ fn parse_key_code(s: &str) -> Result<Code, String> {
         "F10" => Ok(Code::F10),
         "F11" => Ok(Code::F11),
         "F12" => Ok(Code::F12),
+        "F13" => Ok(Code::F13),
+        "F14" => Ok(Code::F14),
+        "F15" => Ok(Code::F15),
+        "F16" => Ok(Code::F16),
+        "F17" => Ok(Code::F17),
+        "F18" => Ok(Code::F18),
+        "F19" => Ok(Code::F19),
+        "F20" => Ok(Code::F20),
+        "F21" => Ok(Code::F21),
+        "F22" => Ok(Code::F22),
+        "F23" => Ok(Code::F23),
+        "F24" => Ok(Code::F24),
         "SPACE" => Ok(Code::Space),
         "ENTER" | "RETURN" => Ok(Code::Enter), // Added Return alias
         "ESCAPE" | "ESC" => Ok(Code::Escape),  // Added Esc alias
@@ -194,11 +436,114 @@ fn parse_key_code(s: &str) -> Result<Code, String> {
         "NUMPADMULTIPLY" | "NUMMULTIPLY" => Ok(Code::NumpadMultiply),
         "NUMPADDIVIDE" | "NUMDIVIDE" => Ok(Code::NumpadDivide),
         "NUMPADENTER" | "NUMENTER" => Ok(Code::NumpadEnter),
+        // Media and volume keys
+        "VOLUMEUP" | "AUDIOVOLUMEUP" => Ok(Code::AudioVolumeUp),
+        "VOLUMEDOWN" | "AUDIOVOLUMEDOWN" => Ok(Code::AudioVolumeDown),
+        "VOLUMEMUTE" | "AUDIOVOLUMEMUTE" | "MUTE" => Ok(Code::AudioVolumeMute),
+        "MEDIAPLAYPAUSE" | "PLAYPAUSE" => Ok(Code::MediaPlayPause),
+        "MEDIASTOP" => Ok(Code::MediaStop),
+        "MEDIANEXTTRACK" | "MEDIATRACKNEXT" | "MEDIANEXT" => Ok(Code::MediaTrackNext),
+        "MEDIAPREVIOUSTRACK" | "MEDIATRACKPREVIOUS" | "MEDIAPREVIOUS" => Ok(Code::MediaTrackPrevious),
         // ... consult global_hotkey docs for any missing codes ...
         _ => Err(format!("Unknown or unsupported key code: {}", s)),
     }
 }
 
+/// Parses a physical key given as `sc:<scancode>`, e.g. `sc:0x1E` (hex) or `sc:30` (decimal).
+/// Accepts set 1 make codes 0x01-0x7F, and 0xE001-0xE07F for extended keys.
+pub fn parse_scancode(s: &str) -> Result<u32, String> {
+    let number = s.get(3..).unwrap_or_default().trim();
+    let parsed = match number.strip_prefix("0x").or_else(|| number.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+    };
+    let scancode = parsed.map_err(|_| format!("Invalid scancode '{}': expected a number such as sc:0x1E", s))?;
+    if matches!(scancode, 0x01..=0x7F | 0xE001..=0xE07F) {
+        Ok(scancode)
+    } else {
+        Err(format!(
+            "Scancode 0x{:X} in '{}' is out of range (0x01-0x7F, or 0xE001-0xE07F for extended keys)",
+            scancode, s
+        ))
+    }
+}
+
+/// Finds the key that the current keyboard layout puts on a physical scancode. Hotkeys are
+/// registered by virtual key, so this picks whichever virtual key the layout maps there: on
+/// AZERTY, `sc:0x1E` becomes Q rather than A.
+fn code_for_scancode(scancode: u32) -> Result<Code, String> {
+    let vk = unsafe { MapVirtualKeyW(scancode, MAPVK_VSC_TO_VK_EX) } as u16;
+    if vk == 0 {
+        return Err(format!("Scancode 0x{:X} has no key on the current keyboard layout", scancode));
+    }
+    code_for_virtual_key(VIRTUAL_KEY(vk)).ok_or_else(|| {
+        format!(
+            "Scancode 0x{:X} is virtual key 0x{:02X} on the current keyboard layout, which can't be used as a hotkey",
+            scancode, vk
+        )
+    })
+}
+
+/// The `Code` that global-hotkey registers as the given virtual key (the reverse of its own
+/// table), or None for keys it can't register.
+pub fn code_for_virtual_key(vk: VIRTUAL_KEY) -> Option<Code> {
+    let name = match vk.0 {
+        0x30..=0x39 | 0x41..=0x5A => char::from(vk.0 as u8).to_string(), // Digits and letters
+        0x60..=0x69 => format!("NUMPAD{}", vk.0 - 0x60),
+        0x70..=0x87 => format!("F{}", vk.0 - 0x6F),
+        _ => String::new(),
+    };
+    if !name.is_empty() {
+        return parse_key_code(&name).ok();
+    }
+    Some(match vk {
+        VK_OEM_PLUS => Code::Equal,
+        VK_OEM_COMMA => Code::Comma,
+        VK_OEM_MINUS => Code::Minus,
+        VK_OEM_PERIOD => Code::Period,
+        VK_OEM_1 => Code::Semicolon,
+        VK_OEM_2 => Code::Slash,
+        VK_OEM_3 => Code::Backquote,
+        VK_OEM_4 => Code::BracketLeft,
+        VK_OEM_5 => Code::Backslash,
+        VK_OEM_6 => Code::BracketRight,
+        VK_OEM_7 => Code::Quote,
+        VK_BACK => Code::Backspace,
+        VK_TAB => Code::Tab,
+        VK_SPACE => Code::Space,
+        VK_RETURN => Code::Enter,
+        VK_CAPITAL => Code::CapsLock,
+        VK_ESCAPE => Code::Escape,
+        VK_PRIOR => Code::PageUp,
+        VK_NEXT => Code::PageDown,
+        VK_END => Code::End,
+        VK_HOME => Code::Home,
+        VK_LEFT => Code::ArrowLeft,
+        VK_UP => Code::ArrowUp,
+        VK_RIGHT => Code::ArrowRight,
+        VK_DOWN => Code::ArrowDown,
+        VK_SNAPSHOT => Code::PrintScreen,
+        VK_INSERT => Code::Insert,
+        VK_DELETE => Code::Delete,
+        VK_NUMLOCK => Code::NumLock,
+        VK_ADD => Code::NumpadAdd,
+        VK_DECIMAL => Code::NumpadDecimal,
+        VK_DIVIDE => Code::NumpadDivide,
+        VK_MULTIPLY => Code::NumpadMultiply,
+        VK_SUBTRACT => Code::NumpadSubtract,
+        VK_SCROLL => Code::ScrollLock,
+        VK_PAUSE => Code::Pause,
+        VK_VOLUME_UP => Code::AudioVolumeUp,
+        VK_VOLUME_DOWN => Code::AudioVolumeDown,
+        VK_VOLUME_MUTE => Code::AudioVolumeMute,
+        VK_MEDIA_PLAY_PAUSE => Code::MediaPlayPause,
+        VK_MEDIA_STOP => Code::MediaStop,
+        VK_MEDIA_NEXT_TRACK => Code::MediaTrackNext,
+        VK_MEDIA_PREV_TRACK => Code::MediaTrackPrevious,
+        _ => return None,
+    })
+}
+
 // Optional: Add a function to handle cleanup if needed, though the manager might handle it on drop.
 // pub fn unregister_hotkeys(manager: &GlobalHotKeyManager, hotkeys: &[HotKey]) -> Result<(), Box<dyn Error>> {
 //     println!("Unregistering hotkeys...");
@@ -208,3 +553,73 @@ fn parse_key_code(s: &str) -> Result<Code, String> {
 //     println!("Hotkeys unregistered.");
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scancodes_are_parsed_and_range_checked() {
+        assert_eq!(parse_scancode("sc:0x1E"), Ok(0x1E));
+        assert_eq!(parse_scancode("SC:0X1e"), Ok(0x1E));
+        assert_eq!(parse_scancode("sc:30"), Ok(30));
+        assert_eq!(parse_scancode("sc:0xE04B"), Ok(0xE04B));
+
+        for malformed in ["sc:", "sc:0x", "sc:abc", "sc:-1", "sc:0x1G"] {
+            let err = parse_scancode(malformed).unwrap_err();
+            assert!(err.contains("expected a number"), "{}: {}", malformed, err);
+        }
+        for out_of_range in ["sc:0", "sc:0x80", "sc:0xE000", "sc:0xE080", "sc:0x1FF"] {
+            let err = parse_scancode(out_of_range).unwrap_err();
+            assert!(err.contains("out of range"), "{}: {}", out_of_range, err);
+        }
+        assert!(parse_hotkey_string("Ctrl+Alt+sc:0x200").unwrap_err().contains("out of range"));
+    }
+
+    #[test]
+    fn normalize_keys_ignores_modifier_order_case_and_modifier_aliases() {
+        assert_eq!(normalize_keys("Ctrl+Shift+A"), "ctrl+shift+a");
+        assert_eq!(normalize_keys("shift+ctrl+a"), normalize_keys("Ctrl+Shift+A"));
+        assert_eq!(normalize_keys(" Control + Alt + F1 "), normalize_keys("alt+ctrl+f1"));
+        assert_eq!(normalize_keys("Meta+Option+1"), normalize_keys("Alt+Win+1"));
+        assert_ne!(normalize_keys("Ctrl+Alt+1"), normalize_keys("Ctrl+Shift+1"));
+        assert_ne!(normalize_keys("Ctrl+Alt+1"), normalize_keys("Ctrl+1+Alt"));
+    }
+
+    #[test]
+    fn bare_keys_are_refused_only_if_they_take_over_typing() {
+        let f13 = parse_hotkey_string("F13").unwrap();
+        assert_eq!(f13.key, Code::F13);
+        assert!(f13.mods.is_empty());
+        assert_eq!(parse_hotkey_string("VolumeUp").unwrap().key, Code::AudioVolumeUp);
+        assert_eq!(parse_hotkey_string("MediaPlayPause").unwrap().key, Code::MediaPlayPause);
+
+        // Keys that don't type anything stay usable alone, as before
+        for keys in ["F9", "Insert", "PageUp", "PrintScreen", "Numpad1"] {
+            assert!(parse_hotkey_string(keys).is_ok(), "{}", keys);
+        }
+
+        let err = parse_hotkey_string("A").unwrap_err();
+        assert!(err.contains("needs a modifier"), "{}", err);
+        for keys in ["1", "Space", "Enter", "Tab", "Backspace", "Comma"] {
+            assert!(parse_hotkey_string(keys).is_err(), "{}", keys);
+        }
+
+        let ctrl_a = parse_hotkey_string("Ctrl+A").unwrap();
+        assert_eq!(ctrl_a.key, Code::KeyA);
+        assert_eq!(ctrl_a.mods, Modifiers::CONTROL);
+    }
+
+    #[test]
+    fn virtual_keys_map_back_to_registrable_codes() {
+        use windows::Win32::UI::Input::KeyboardAndMouse::{VK_LSHIFT, VK_OEM_102};
+        assert_eq!(code_for_virtual_key(VIRTUAL_KEY(b'Q' as u16)), Some(Code::KeyQ));
+        assert_eq!(code_for_virtual_key(VIRTUAL_KEY(b'7' as u16)), Some(Code::Digit7));
+        assert_eq!(code_for_virtual_key(VIRTUAL_KEY(0x63)), Some(Code::Numpad3));
+        assert_eq!(code_for_virtual_key(VIRTUAL_KEY(0x87)), Some(Code::F24));
+        assert_eq!(code_for_virtual_key(VK_OEM_1), Some(Code::Semicolon));
+        // Neither a modifier nor the ISO-only key next to left Shift can be registered
+        assert_eq!(code_for_virtual_key(VK_LSHIFT), None);
+        assert_eq!(code_for_virtual_key(VK_OEM_102), None);
+    }
+}
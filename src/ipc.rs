@@ -0,0 +1,182 @@
+use log::{error, info, warn};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::windows::io::FromRawHandle;
+use std::thread;
+use std::time::Duration;
+use windows::{
+    Win32::{
+        Foundation::ERROR_PIPE_CONNECTED,
+        Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+            PIPE_WAIT,
+        },
+    },
+    core::HSTRING,
+};
+
+/// The named pipe the control server listens on.
+pub const PIPE_NAME: &str = r"\\.\pipe\soundswitch";
+
+const PIPE_BUFFER_SIZE: u32 = 4096;
+
+/// How long a client waits for its command to run before getting an error back.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command received over the control pipe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpcCommand {
+    SetOutput(String), // Device name or endpoint ID
+    SetInput(String),  // Device name or endpoint ID
+    ToggleMute,        // Mute or unmute the default output device
+    List,              // Names of the active output and input devices
+}
+
+impl IpcCommand {
+    /// Parses one line such as `set-output Headset`. The argument is the rest of the line, so
+    /// names may contain spaces; surrounding double quotes are removed.
+    pub fn parse(line: &str) -> Result<IpcCommand, String> {
+        let line = line.trim();
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim().trim_matches('"')),
+            None => (line, ""),
+        };
+        let device = |command: fn(String) -> IpcCommand| {
+            if argument.is_empty() {
+                Err(format!("'{}' needs a device name or ID", name))
+            } else {
+                Ok(command(argument.to_string()))
+            }
+        };
+        let no_argument = |command: IpcCommand| {
+            if argument.is_empty() {
+                Ok(command)
+            } else {
+                Err(format!("'{}' takes no argument", name))
+            }
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "set-output" => device(IpcCommand::SetOutput),
+            "set-input" => device(IpcCommand::SetInput),
+            "toggle-mute" => no_argument(IpcCommand::ToggleMute),
+            "list" => no_argument(IpcCommand::List),
+            _ => Err(format!(
+                "Unknown command '{}' (expected set-output, set-input, toggle-mute or list)",
+                name
+            )),
+        }
+    }
+}
+
+/// A command from the pipe and the channel its one-line result goes back on.
+pub struct IpcRequest {
+    pub command: IpcCommand,
+    pub reply: crossbeam_channel::Sender<String>,
+}
+
+/// Starts the control server on its own thread. Each command is handed to `forward`, which
+/// passes it to the thread that performs switches; the server waits for the reply and writes
+/// it back to the client. Clients are served one at a time.
+pub fn spawn_ipc_server(forward: impl Fn(IpcRequest) + Send + 'static) {
+    thread::spawn(move || {
+        info!("Control pipe listening on {}", PIPE_NAME); // Log info
+        let mut first_instance = true;
+        loop {
+            // Only the first instance may claim the name, so a pipe created by another
+            // process (e.g. a second SoundSwitch) isn't silently shared
+            let pipe = match create_pipe(first_instance) {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    error!("Failed to create control pipe {}: {}", PIPE_NAME, e); // Log error
+                    return;
+                }
+            };
+            first_instance = false;
+            if let Err(e) = serve_client(&pipe, &forward) {
+                warn!("Control pipe client failed: {}", e); // Log warning
+            }
+            // Dropping the handle disconnects the client before the next instance is created
+        }
+    });
+}
+
+// Creates one pipe instance and waits for a client to connect to it
+fn create_pipe(first_instance: bool) -> windows::core::Result<File> {
+    let open_mode = if first_instance {
+        PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        PIPE_ACCESS_DUPLEX
+    };
+    unsafe {
+        let handle = CreateNamedPipeW(
+            &HSTRING::from(PIPE_NAME),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            None,
+        );
+        if handle.is_invalid() {
+            return Err(windows::core::Error::from_win32());
+        }
+        // The file owns the handle from here on and closes it when dropped
+        let pipe = File::from_raw_handle(handle.0);
+        match ConnectNamedPipe(handle, None) {
+            Ok(()) => Ok(pipe),
+            // The client connected between creating the pipe and waiting for it
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => Ok(pipe),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Answers each line the client sends with one result line until it disconnects
+fn serve_client(pipe: &File, forward: &impl Fn(IpcRequest)) -> io::Result<()> {
+    let mut writer = pipe;
+    for line in BufReader::new(pipe).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break, // Client closed its end
+            Err(e) => return Err(e),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match IpcCommand::parse(&line) {
+            Ok(command) => {
+                info!("Control pipe command: {}", line.trim()); // Log info
+                let (reply, result) = crossbeam_channel::bounded(1);
+                forward(IpcRequest { command, reply });
+                result
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| "ERR SoundSwitch did not respond in time".to_string())
+            }
+            Err(e) => format!("ERR {}", e),
+        };
+        writeln!(writer, "{}", reply)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipc_commands_parse_with_names_containing_spaces() {
+        assert_eq!(
+            IpcCommand::parse("set-output Speakers (Realtek Audio)"),
+            Ok(IpcCommand::SetOutput("Speakers (Realtek Audio)".to_string()))
+        );
+        assert_eq!(IpcCommand::parse("SET-INPUT \"Headset Mic\""), Ok(IpcCommand::SetInput("Headset Mic".to_string())));
+        assert_eq!(IpcCommand::parse(" toggle-mute "), Ok(IpcCommand::ToggleMute));
+        assert_eq!(IpcCommand::parse("list"), Ok(IpcCommand::List));
+        assert!(IpcCommand::parse("set-output").is_err());
+        assert!(IpcCommand::parse("list all").is_err());
+        assert!(IpcCommand::parse("shutdown").unwrap_err().contains("Unknown command 'shutdown'"));
+    }
+}
@@ -0,0 +1,64 @@
+//! SoundSwitch switches the default Windows audio devices. The `sound_switch` binary wraps this
+//! library with hotkeys and a tray icon; other programs can use the same device listing,
+//! matching and switching directly.
+//!
+//! ```no_run
+//! use sound_switch::{
+//!     ensure_com_initialized, find_best_match, list_output_devices, load_config, set_default_output_device,
+//! };
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // The device functions expect COM to be initialized on the calling thread
+//! ensure_com_initialized()?;
+//! let config = load_config()?;
+//! let devices = list_output_devices()?;
+//! for device in &devices {
+//!     println!("{} ({})", device.name, device.id);
+//! }
+//! if let Some((headset, _score)) = find_best_match("Headset", &devices, &config) {
+//!     set_default_output_device(&headset.id)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything that talks to the audio system is Windows-only; on other targets only the
+//! logging helpers are compiled.
+
+#[cfg(windows)]
+pub mod audio_device;
+#[cfg(windows)]
+pub mod config;
+#[cfg(windows)]
+pub mod device_history;
+#[cfg(windows)]
+pub mod device_notifications;
+#[cfg(windows)]
+pub mod diagnostics;
+#[cfg(windows)]
+pub mod hidden_window;
+#[cfg(windows)]
+pub mod hotkey_manager;
+#[cfg(windows)]
+pub mod ipc;
+pub mod log_dedup;
+pub mod log_file;
+#[cfg(windows)]
+pub mod matching;
+#[cfg(windows)]
+pub mod power;
+#[cfg(windows)]
+pub mod session_end;
+#[cfg(windows)]
+pub mod snapshot;
+#[cfg(windows)]
+pub mod state;
+#[cfg(windows)]
+pub mod status;
+
+#[cfg(windows)]
+pub use audio_device::{AudioDevice, ensure_com_initialized, list_output_devices, set_default_output_device};
+#[cfg(windows)]
+pub use config::{Config, load_config};
+#[cfg(windows)]
+pub use matching::find_best_match;
@@ -0,0 +1,157 @@
+use log::{Level, Log, Metadata, Record};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default de-duplication window for identical log messages.
+pub const DEFAULT_LOG_DEDUP_WINDOW_MS: u64 = 5000;
+
+// A message that was logged recently, and how many identical ones were dropped since
+struct RecentMessage {
+    level: Level,
+    target: String,
+    message: String,
+    logged_at: Instant,
+    suppressed: u32,
+}
+
+/// Wraps the log sink and drops messages identical to one logged within the window.
+/// Once the window has passed, a single "(repeated N times)" line records what was dropped.
+pub struct DedupLogger {
+    inner: Box<dyn Log>,
+    // Changed once the config has loaded, while the logger is already installed
+    window_ms: AtomicU64,
+    recent: Mutex<HashMap<u64, RecentMessage>>,
+}
+
+impl DedupLogger {
+    /// Wraps `inner`, suppressing identical messages for `window_ms` (0 disables de-duplication).
+    pub fn new(inner: Box<dyn Log>, window_ms: u64) -> Self {
+        DedupLogger { inner, window_ms: AtomicU64::new(window_ms), recent: Mutex::new(HashMap::new()) }
+    }
+
+    /// Sets how long an identical message is suppressed after being logged (0 disables de-duplication).
+    pub fn set_window(&self, window_ms: u64) {
+        self.window_ms.store(window_ms, Ordering::Relaxed);
+    }
+
+    fn log_repeated(&self, entry: &RecentMessage) {
+        self.inner.log(
+            &Record::builder()
+                .level(entry.level)
+                .target(&entry.target)
+                .args(format_args!("(repeated {} times) {}", entry.suppressed, entry.message))
+                .build(),
+        );
+    }
+
+    // Removes messages whose window has passed, logging a summary for those that were repeated
+    fn expire(&self, recent: &mut HashMap<u64, RecentMessage>, window: Duration, now: Instant) {
+        recent.retain(|_, entry| {
+            let expired = now.duration_since(entry.logged_at) >= window;
+            if expired && entry.suppressed > 0 {
+                self.log_repeated(entry);
+            }
+            !expired
+        });
+    }
+}
+
+impl Log for DedupLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let window = Duration::from_millis(self.window_ms.load(Ordering::Relaxed));
+        if window.is_zero() {
+            self.inner.log(record);
+            return;
+        }
+
+        let message = record.args().to_string();
+        let mut hasher = DefaultHasher::new();
+        (record.level(), record.target(), &message).hash(&mut hasher);
+        let key = hasher.finish();
+
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        self.expire(&mut recent, window, now);
+        if let Some(entry) = recent.get_mut(&key) {
+            entry.suppressed += 1;
+            return;
+        }
+
+        self.inner.log(record);
+        recent.insert(
+            key,
+            RecentMessage {
+                level: record.level(),
+                target: record.target().to_string(),
+                message,
+                logged_at: now,
+                suppressed: 0,
+            },
+        );
+    }
+
+    // Writes out pending "(repeated N times)" lines, e.g. at shutdown
+    fn flush(&self) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, entry) in recent.drain() {
+            if entry.suppressed > 0 {
+                self.log_repeated(&entry);
+            }
+        }
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // Log sink that records each formatted message
+    struct RecordingLogger(Arc<Mutex<Vec<String>>>);
+
+    impl Log for RecordingLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn identical_log_messages_are_collapsed() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logger = DedupLogger::new(Box::new(RecordingLogger(lines.clone())), 60_000);
+        let log_line = |message: &str| {
+            logger.log(&Record::builder().level(Level::Info).args(format_args!("{}", message)).build());
+        };
+
+        log_line("Switching to Speakers");
+        log_line("Switching to Speakers");
+        log_line("Switching to Speakers");
+        log_line("Switching to Headset");
+        logger.flush();
+
+        assert_eq!(
+            *lines.lock().unwrap(),
+            vec![
+                "Switching to Speakers".to_string(),
+                "Switching to Headset".to_string(),
+                "(repeated 2 times) Switching to Speakers".to_string(),
+            ]
+        );
+    }
+}
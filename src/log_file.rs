@@ -0,0 +1,161 @@
+use log::{Level, Log, Metadata, Record};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// The log file, relative to the working directory.
+pub const LOG_FILE_NAME: &str = "sound_switch.log";
+
+/// Writes the log file and moves it to `<name>.1` (replacing an older one) once the next line
+/// would take it past `max_bytes`. A limit of 0 disables rotation.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    at_line_start: bool, // Only rotate between lines so a record is never split across files
+}
+
+impl RotatingFile {
+    /// Creates the file, truncating the log of a previous run.
+    pub fn create(path: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::create(&path)?;
+        Ok(RotatingFile { path, file, written: 0, max_bytes, at_line_start: true })
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = self.rotated_path();
+        // Windows won't rename onto an existing file
+        let _ = fs::remove_file(&rotated);
+        fs::rename(&self.path, &rotated)?;
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_bytes > 0
+            && self.at_line_start
+            && self.written > 0
+            && self.written + buf.len() as u64 > self.max_bytes
+        {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        if written > 0 {
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// A record logged before the sink was installed
+struct PendingRecord {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+static SINK: OnceLock<Box<dyn Log>> = OnceLock::new();
+static PENDING: Mutex<Vec<PendingRecord>> = Mutex::new(Vec::new());
+
+/// Forwards records to the sink set by `install_log_sink` and buffers them until then, so the
+/// config that picks the log level and size limit can load before the log file is opened.
+pub struct DeferredLogger;
+
+impl Log for DeferredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        SINK.get().is_none_or(|sink| sink.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(sink) = SINK.get() {
+            sink.log(record);
+            return;
+        }
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        // The sink may have been installed while waiting for the lock
+        if let Some(sink) = SINK.get() {
+            drop(pending);
+            sink.log(record);
+            return;
+        }
+        pending.push(PendingRecord {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {
+        if let Some(sink) = SINK.get() {
+            sink.flush();
+        }
+    }
+}
+
+/// Replays the buffered records into `sink` (subject to its own level) and forwards everything
+/// logged afterwards. Only the first call has an effect.
+pub fn install_log_sink(sink: Box<dyn Log>) {
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    if SINK.get().is_some() {
+        return;
+    }
+    for record in pending.drain(..) {
+        sink.log(
+            &Record::builder()
+                .level(record.level)
+                .target(&record.target)
+                .args(format_args!("{}", record.message))
+                .build(),
+        );
+    }
+    let _ = SINK.set(sink);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotating_file_moves_full_log_aside_between_lines() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("soundswitch_test_rotation_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log");
+
+        let mut file = RotatingFile::create(&path, 16).unwrap();
+        file.write_all(b"first line\n").unwrap();
+        // A line written in pieces is kept together even though it crosses the limit
+        file.write_all(b"second ").unwrap();
+        file.write_all(b"line\n").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("test.log.1")).unwrap(), "first line\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second line\n");
+
+        // The next rotation replaces the older rotated file
+        file.write_all(b"third\n").unwrap();
+        file.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("test.log.1")).unwrap(), "second line\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
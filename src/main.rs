@@ -2,28 +2,50 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use simplelog::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use serde::Serialize;
 use std::error::Error;
-use std::fs::File; // For log file creation // Import simplelog macros and types
+use std::path::Path;
+use std::os::windows::process::CommandExt;
+use std::process::Command;
 // use std::collections::HashMap; // Removed unused import
 // use std::sync::mpsc::{channel, Receiver as MpscReceiver}; // Keep commented
 use crossbeam_channel; // Restore
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use std::rc::Rc;
 use std::sync::Arc; // Restore
 use std::sync::atomic::{AtomicBool, Ordering}; // Restore
 use std::thread;
-use std::time::Duration; // Keep for sleep // Import log macros
-
-mod audio_device;
-mod config;
-mod hotkey_manager;
-
-use audio_device::{AudioDevice, list_output_devices, list_input_devices, set_default_output_device, set_default_input_device};
-use config::{Config, FuzzyMatchAlgorithm, load_config}; // Import Config struct and FuzzyMatchAlgorithm
-use fuzzy_matcher::FuzzyMatcher;
-use fuzzy_matcher::skim::SkimMatcherV2;
-use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState}; // Corrected import name
-use hotkey_manager::register_hotkeys;
+use std::time::{Duration, Instant}; // Keep for sleep // Import log macros
+
+use sound_switch::{
+    audio_device, config, device_history, device_notifications, diagnostics, hotkey_manager, ipc, log_dedup,
+    log_file, matching, power, session_end, snapshot, state, status,
+};
+
+use audio_device::{AudioBackend, AudioDevice, AudioDeviceModule, FormFactor, AudioRole, ComGuard, DeviceFlow, DeviceState, SystemBackend, adjust_output_volume, toggle_output_mute, configure_execution_policy_bypass, configure_powershell_fallback, configure_switch_limit, configure_switch_retries, ensure_com_initialized, play_test_tone, verify_module_integrity, get_default_input_device, get_default_output_device, get_device_by_id, list_devices_including_inactive, peak_input_level, RoleDefaults, locate_audio_device_module, list_output_devices, list_input_devices};
+use config::{Action, Config, DeviceRemovedPolicy, HotkeyAction, MappingRole, MissingDevicesNotice, TrayIcons, HotkeyMapping, Profile, SwitchSound, load_config}; // Import Config struct and FuzzyMatchAlgorithm
+use device_history::{DeviceChange, DeviceHistory, print_device_history};
+use diagnostics::write_diagnostics;
+use device_notifications::{DeviceEvent, DeviceNotifier};
+use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState, hotkey::HotKey}; // Corrected import name
+use hotkey_manager::{HotkeyDeviceMap, HotkeyRegistration, probe_hotkeys, register_hotkeys};
+use ipc::{IpcCommand, IpcRequest, spawn_ipc_server};
+use log_dedup::{DEFAULT_LOG_DEDUP_WINDOW_MS, DedupLogger};
+use matching::{
+    MatchError, find_best_input_match, find_best_match, match_mode_label, normalize_for_matching, resolve_match,
+    score_suffix,
+};
+use log_file::{DeferredLogger, LOG_FILE_NAME, RotatingFile, install_log_sink};
+use snapshot::{list_snapshots, load_snapshot, restore_snapshot, save_snapshot};
+use power::ResumeWatcher;
+use session_end::SessionEndWatcher;
+use state::{load_hotkeys_enabled, load_last_used, record_hotkeys_enabled, record_last_used, restore_last_used};
+use status::{read_status, remove_status_file, write_status};
 use tray_item::TrayItem;
+use windows::Win32::Media::Audio::DEVICE_STATE_ACTIVE;
+use windows::Win32::System::Console::{ATTACH_PARENT_PROCESS, AttachConsole, SetConsoleCtrlHandler};
 use windows::Win32::UI::WindowsAndMessaging::{
     DispatchMessageW, MSG, PM_REMOVE, PeekMessageW, TranslateMessage,
 };
@@ -32,9 +54,266 @@ use windows_core::BOOL; // Use windows_core::BOOL as suggested by compiler // Re
 // Enum for messages between threads
 enum AppMessage {
     HotkeyError(String), // Use String for thread safety
+    ReloadConfig,        // Re-parse the config file and restart the hotkey listener with it
+    RestartHotkeys,      // Restart the hotkey listener with the current config (e.g. after sleep/resume)
+    SetOutputDevice { id: String, name: String }, // Switch the output device chosen in the tray menu
+    ActivateProfile(String), // Switch to the devices of the profile chosen in the tray menu
+    ToggleStickyDefault, // Turn sticky-default on or off from the tray menu
+    ToggleEnabled,       // Turn all hotkeys off or on from the tray menu (the listener follows HOTKEYS_ENABLED)
+    TestConfig,          // Run the configured device test (see --test-config)
+    EditConfig,          // Open the loaded config file in the default editor
+    ShowMenu,            // Open the tray context menu (show-menu hotkey action)
+    DefaultOutputChanged, // Move the tray menu's default marker
+    DefaultInputChanged, // Update the default input shown in the tray tooltip
+    DevicesChanged,      // A device was added, removed or changed state; rebuild the tray menu
+    Ipc(IpcRequest),     // A command from the control pipe (see enable-ipc); the result goes back on its reply channel
     Quit,
 }
 
+// Windows may report the replacement default before the removal of the old one; a removal
+// this soon after the default changed away from the device still counts as removing the default
+const REPLACED_DEFAULT_WINDOW: Duration = Duration::from_secs(2);
+const DEFAULT_HISTORY_LEN: usize = 5;
+
+// Recent console-role defaults of one flow (endpoint IDs, newest first), as reported by Windows
+#[derive(Default)]
+struct DefaultHistory {
+    ids: Vec<String>,
+    changed_at: Option<Instant>,
+}
+
+impl DefaultHistory {
+    fn record(&mut self, device_id: &str, now: Instant) {
+        if self.ids.first().is_some_and(|id| id == device_id) {
+            return;
+        }
+        self.ids.retain(|id| id != device_id);
+        self.ids.insert(0, device_id.to_string());
+        self.ids.truncate(DEFAULT_HISTORY_LEN);
+        self.changed_at = Some(now);
+    }
+
+    // Whether the device was the default when it disappeared: it still is, or it was until moments ago
+    fn was_default(&self, device_id: &str, now: Instant) -> bool {
+        match self.ids.iter().position(|id| id == device_id) {
+            Some(0) => true,
+            Some(1) => self.changed_at.is_some_and(|at| now.duration_since(at) <= REPLACED_DEFAULT_WINDOW),
+            _ => false,
+        }
+    }
+
+    // The defaults before the given one, newest first
+    fn before<'a>(&'a self, device_id: &str) -> impl Iterator<Item = &'a str> {
+        let start = self.ids.iter().position(|id| id == device_id).map_or(self.ids.len(), |i| i + 1);
+        self.ids[start..].iter().map(String::as_str)
+    }
+}
+
+#[derive(Default)]
+struct DefaultTracker {
+    output: DefaultHistory,
+    input: DefaultHistory,
+}
+
+impl DefaultTracker {
+    fn flow(&mut self, flow: DeviceFlow) -> &mut DefaultHistory {
+        match flow {
+            DeviceFlow::Output => &mut self.output,
+            DeviceFlow::Input => &mut self.input,
+        }
+    }
+}
+
+// Applies on-device-removed when the device that disappeared was a default. Returns the device
+// switched to, if any.
+fn handle_default_removed(
+    device_id: &str,
+    defaults: &DefaultTracker,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+    now: Instant,
+) -> Option<AudioDevice> {
+    if config.on_device_removed == DeviceRemovedPolicy::Ignore {
+        return None;
+    }
+    for (flow, history, available) in [
+        (DeviceFlow::Output, &defaults.output, available_output_devices),
+        (DeviceFlow::Input, &defaults.input, available_input_devices),
+    ] {
+        if !history.was_default(device_id, now) {
+            continue;
+        }
+        let replacement = match config.on_device_removed {
+            DeviceRemovedPolicy::Ignore => None,
+            DeviceRemovedPolicy::FallbackToPrevious => history
+                .before(device_id)
+                .find_map(|id| available.iter().find(|d| d.id == id)),
+            DeviceRemovedPolicy::SwitchToConfigured => configured_device(flow, available, config),
+        };
+        let Some(replacement) = replacement else {
+            info!(
+                "Default {:?} device removed; no {:?} replacement is present",
+                flow, config.on_device_removed
+            ); // Log info
+            return None;
+        };
+
+        info!(
+            "Default {:?} device removed, switching to '{}' ({:?})",
+            flow, replacement.name, config.on_device_removed
+        ); // Log info
+        let result = match flow {
+            DeviceFlow::Output => backend.set_default_output_device(&replacement.id),
+            DeviceFlow::Input => backend.set_default_input_device(&replacement.id),
+        };
+        return match result {
+            Ok(()) => Some(replacement.clone()),
+            Err(e) => {
+                error!(
+                    "Failed to switch to '{}' after the default device was removed: {}",
+                    replacement.name, e
+                ); // Log error
+                None
+            }
+        };
+    }
+    None
+}
+
+// The first present device named by any hotkey mapping for this flow, in config order
+fn configured_device<'a>(flow: DeviceFlow, available: &'a [AudioDevice], config: &Config) -> Option<&'a AudioDevice> {
+    config
+        .hotkeys
+        .iter()
+        .flat_map(|mapping| -> Vec<&str> {
+            match flow {
+                DeviceFlow::Output => mapping
+                    .output_priority()
+                    .chain(mapping.device_names.iter().flatten().map(String::as_str))
+                    .chain(mapping.step_devices(flow))
+                    .collect(),
+                DeviceFlow::Input => mapping.input_target().into_iter().chain(mapping.step_devices(flow)).collect(),
+            }
+        })
+        .find_map(|target| match flow {
+            DeviceFlow::Output => find_best_match(target, available, config),
+            DeviceFlow::Input => find_best_input_match(target, available, config),
+        })
+        .map(|(device, _)| device)
+}
+
+// Handle to a running hotkey listener thread so it can be stopped (and replaced on reload)
+struct HotkeyListener {
+    shutdown_signal: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl HotkeyListener {
+    // Spawns a new hotkey listener thread for the given configuration
+    fn spawn(config: Config, error_sender: crossbeam_channel::Sender<AppMessage>) -> Self {
+        let shutdown_signal = Arc::new(AtomicBool::new(false));
+        let shutdown_signal_clone = Arc::clone(&shutdown_signal);
+        let handle = thread::spawn(move || {
+            hotkey_listener_thread(config, shutdown_signal_clone, error_sender)
+        });
+        info!("Hotkey listener thread spawned."); // Log info
+        HotkeyListener {
+            shutdown_signal,
+            handle: Some(handle),
+        }
+    }
+
+    // Signals the listener thread to stop and waits for it to unregister its hotkeys
+    fn stop(&mut self) {
+        info!("Setting shutdown signal for hotkey thread..."); // Log info
+        self.shutdown_signal.store(true, Ordering::Relaxed);
+
+        if let Some(handle) = self.handle.take() {
+            info!("Waiting for hotkey thread to join..."); // Log info
+            match handle.join() {
+                Ok(_) => info!("Hotkey thread joined successfully."), // Log info
+                Err(e) => error!(
+                    "Error joining hotkey thread (it might have panicked): {:?}",
+                    e
+                ), // Log error
+            }
+        }
+    }
+
+    // Whether the thread has exited on its own, i.e. without stop (e.g. it panicked)
+    fn has_exited(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| handle.is_finished())
+    }
+
+    // Like stop, but waits at most `timeout` for the thread to exit. A thread that doesn't is left
+    // detached; its hotkeys stay registered until it exits. Returns whether it stopped in time.
+    fn stop_within(&mut self, timeout: Duration) -> bool {
+        info!("Setting shutdown signal for hotkey thread..."); // Log info
+        self.shutdown_signal.store(true, Ordering::Relaxed);
+
+        let Some(handle) = self.handle.take() else {
+            return true;
+        };
+        let deadline = Instant::now() + timeout;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("Hotkey thread did not stop within {:?}; leaving it detached", timeout); // Log warning
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        if let Err(e) = handle.join() {
+            error!("Error joining hotkey thread (it might have panicked): {:?}", e); // Log error
+        }
+        true
+    }
+}
+
+// Handles a hotkey listener thread that exited without being stopped. Returns false if SoundSwitch
+// should exit (exit-on-channel-disconnect); otherwise replaces it with a listener from `respawn`.
+// A listener that is still running is left alone.
+fn recover_exited_listener(
+    listener: &mut HotkeyListener,
+    exit_on_failure: bool,
+    respawn: impl FnOnce() -> HotkeyListener,
+) -> bool {
+    if !listener.has_exited() {
+        return true;
+    }
+    if exit_on_failure {
+        error!("Hotkey listener thread exited unexpectedly. Exiting."); // Log error
+        return false;
+    }
+    warn!("Hotkey listener thread exited unexpectedly. Restarting it..."); // Log warning
+    listener.stop(); // Joins the finished thread, logging a panic
+    *listener = respawn();
+    info!("Hotkey listener restarted."); // Log info
+    true
+}
+
+// How long "Restart hotkeys" waits for the old listener thread before starting a new one
+const LISTENER_RESTART_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Replaces the hotkey listener with a fresh thread (new hotkey manager, registrations and COM
+// apartment) running the current config. For hotkeys that stopped responding, e.g. after resume.
+fn restart_hotkeys(
+    config: &Config,
+    listener: &mut HotkeyListener,
+    error_sender: &crossbeam_channel::Sender<AppMessage>,
+) {
+    info!("Restarting the hotkey listener..."); // Log info
+    let stopped = listener.stop_within(LISTENER_RESTART_TIMEOUT);
+    *listener = HotkeyListener::spawn(config.clone(), error_sender.clone());
+    let message = if stopped {
+        format!("Hotkeys restarted.\n\n{} hotkey(s) configured.", config.hotkeys.len())
+    } else {
+        "Hotkeys restarted, but the old listener didn't stop in time. Some hotkeys may fail to register until it does; see the log.".to_string()
+    };
+    show_notification("SoundSwitch - Hotkeys Restarted", &message, !stopped);
+}
+
 // Function to handle hotkey logic in a separate thread with a Win32 message loop
 fn hotkey_listener_thread(
     config: Config,
@@ -42,23 +321,23 @@ fn hotkey_listener_thread(
     error_sender: crossbeam_channel::Sender<AppMessage>,
 ) {
     info!("Hotkey listener thread started."); // Log info
+    configure_switch_limit(config.max_concurrent_switches, config.on_switch_limit);
+    configure_switch_retries(config.switch_retries);
+    configure_execution_policy_bypass(config.powershell_execution_policy_bypass);
+    configure_powershell_fallback(config.use_powershell_fallback);
 
     // Initialize COM for this thread (required by some system APIs)
-    // Revert back to Multi-Threaded Apartment (MTA)
-    let hr = unsafe {
-        windows::Win32::System::Com::CoInitializeEx(
-            None,
-            windows::Win32::System::Com::COINIT_MULTITHREADED,
-        )
+    // Multi-Threaded Apartment (MTA); the guard uninitializes when the thread function returns
+    let com = match ComGuard::new() {
+        Ok(guard) => guard,
+        Err(e) => {
+            let _ = error_sender.send(AppMessage::HotkeyError(format!(
+                "Hotkey thread failed to initialize COM (MTA): {}",
+                e
+            )));
+            return;
+        }
     };
-    if hr.is_err() {
-        // Check HRESULT directly
-        let _ = error_sender.send(AppMessage::HotkeyError(format!(
-            "Hotkey thread failed to initialize COM (MTA): {:?}",
-            hr
-        )));
-        return;
-    }
     info!("Hotkey thread COM initialized."); // Log info
 
     // 1. Create Hotkey Manager (must live in this thread)
@@ -69,35 +348,30 @@ fn hotkey_listener_thread(
                 "Failed to create GlobalHotKeyManager: {}",
                 e
             )));
-            unsafe { windows::Win32::System::Com::CoUninitialize() };
             return;
         }
     };
     info!("Hotkey manager created in thread."); // Log info
 
     // 2. Register Hotkeys
-    let (hotkey_device_map, hotkeys) = match register_hotkeys(&manager, &config) {
-        Ok((map, keys)) => {
-            info!("Hotkey registration successful in thread."); // Log info
-            (map, keys)
-        }
-        Err(e) => {
-            error!("Error registering hotkeys in thread: {}", e); // Log error
-            let _ = error_sender.send(AppMessage::HotkeyError(format!(
-                "Failed to register hotkeys: {}",
-                e
-            )));
-            unsafe { windows::Win32::System::Com::CoUninitialize() };
-            return;
-        }
-    };
+    // Continue with the hotkeys that work and report the rest in one message
+    let (mut hotkey_device_map, hotkeys, mut registrations, failures) = register_hotkeys(&manager, &config);
+    info!(
+        "Registered {} of {} hotkeys in thread.",
+        hotkeys.len(),
+        registrations.len()
+    ); // Log info
+    if !failures.is_empty() {
+        let _ = error_sender.send(AppMessage::HotkeyError(format_hotkey_failures(&failures)));
+    }
 
     // 3. Get Hotkey Event Receiver
     let receiver = GlobalHotKeyEvent::receiver();
     info!("Hotkey event listener waiting for events..."); // Log info
 
     // 4. Get initial list of audio devices (both output and input)
-    let available_output_devices = match list_output_devices() {
+    let backend = SystemBackend;
+    let mut available_output_devices = match backend.list_output_devices() {
         Ok(devices) => devices,
         Err(e) => {
             error!(
@@ -108,13 +382,12 @@ fn hotkey_listener_thread(
                 "Failed to list audio output devices: {}",
                 e
             )));
-            unsafe { windows::Win32::System::Com::CoUninitialize() };
             return;
         }
     };
     info!("Found {} audio output devices in thread.", available_output_devices.len()); // Log info
 
-    let available_input_devices = match list_input_devices() {
+    let mut available_input_devices = match backend.list_input_devices() {
         Ok(devices) => devices,
         Err(e) => {
             error!(
@@ -125,46 +398,194 @@ fn hotkey_listener_thread(
                 "Failed to list audio input devices: {}",
                 e
             )));
-            unsafe { windows::Win32::System::Com::CoUninitialize() };
             return;
         }
     };
     info!("Found {} audio input devices in thread.", available_input_devices.len()); // Log info
 
+    // 4.5. Watch for endpoint changes to keep the device history (non-fatal if unavailable)
+    let (device_event_sender, device_event_receiver) = crossbeam_channel::unbounded::<DeviceEvent>();
+    let device_notifier = match DeviceNotifier::register(device_event_sender) {
+        Ok(notifier) => Some(notifier),
+        Err(e) => {
+            warn!("Could not register for device notifications: {}", e); // Log warning
+            None
+        }
+    };
+    let teardown = Rc::new(RefCell::new(Some(ListenerTeardown { manager, hotkeys, device_notifier, com })));
+
+    // On logoff or shutdown Windows may kill the process right after WM_ENDSESSION returns, so the
+    // handler releases everything itself instead of waiting for the loop below to notice
+    let session_end_watcher = {
+        let teardown = Rc::clone(&teardown);
+        let shutdown_signal = Arc::clone(&shutdown_signal);
+        let error_sender = error_sender.clone();
+        SessionEndWatcher::create(move || {
+            shutdown_signal.store(true, Ordering::Relaxed);
+            if let Some(teardown) = teardown.borrow_mut().take() {
+                teardown.run(&error_sender);
+            }
+            let _ = error_sender.send(AppMessage::Quit);
+        })
+    };
+    let session_end_watcher = match session_end_watcher {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("Could not watch for session end; hotkeys are only released on a normal exit: {}", e); // Log warning
+            None
+        }
+    };
+
+    // Power broadcasts need a top-level window too (non-fatal if it can't be created)
+    let resume_watcher = match ResumeWatcher::create() {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("Could not watch for resume from sleep: {}", e); // Log warning
+            None
+        }
+    };
+
+    let mut device_history = DeviceHistory::load();
+    let mut defaults = DefaultTracker::default();
+    for flow in [DeviceFlow::Output, DeviceFlow::Input] {
+        if let Ok(device) = backend.default_device(flow, AudioRole::Console) {
+            defaults.flow(flow).record(&device.id, Instant::now());
+        }
+    }
+    let mut sticky = StickyWatchdog::default();
+    let mut key_repeat = KeyRepeatFilter::with_debounce(Duration::from_millis(config.debounce_ms));
+    let mut recent_outputs = RecentOutputs::default();
+
+    // Publish the initial status for external tooling
+    write_status(&registrations);
+
     // 5. Win32 Message Loop combined with Hotkey/Shutdown Check
     let mut msg = MSG::default();
+    let mut hotkeys_registered = true;
     loop {
+        // Follow the tray's "Enabled" item. Disabled hotkeys are unregistered, so other programs
+        // (e.g. a game bound to the same keys) receive them
+        let enabled = HOTKEYS_ENABLED.load(Ordering::Relaxed);
+        if enabled != hotkeys_registered
+            && let Some(teardown) = teardown.borrow_mut().as_mut()
+        {
+            if enabled {
+                (hotkey_device_map, registrations) = teardown.reregister_hotkeys(&config, &error_sender);
+            } else {
+                teardown.unregister_hotkeys(&error_sender);
+                for registration in &mut registrations {
+                    registration.registered = false;
+                    registration.error = Some("Hotkeys are disabled from the tray menu".to_string());
+                }
+            }
+            hotkeys_registered = enabled;
+            write_status(&registrations);
+        }
+
         // Check for hotkey events first (non-blocking)
         if let Ok(event) = receiver.try_recv() {
             // println!("--- DEBUG: Received hotkey event: ID={}, State={:?}", event.id, event.state); // Remove debug print
-            if event.state == HotKeyState::Pressed {
-                let hotkey_id = event.id;
-                if let Some(mapping) = hotkey_device_map.get(&hotkey_id) {
-                    info!(
-                        // Log info
-                        "Hotkey ID {} pressed, switching to output: '{}', input: '{:?}'",
-                        hotkey_id, mapping.device_name, mapping.input_device_name
-                    );
-                    
-                    // Switch output device
-                    match find_and_set_output_device(&mapping.device_name, &available_output_devices, &config) {
-                        Ok(name) => info!("Successfully set output device to {}", name), // Log info
-                        Err(e) => error!("Failed to set output device: {}", e),          // Log error
+            let repeatable = hotkey_device_map.get(event.id).is_some_and(HotkeyMapping::is_repeatable);
+            if key_repeat.should_fire(event.id, event.state, repeatable, Instant::now())
+                && !blocked_by_dnd(event.id, hotkey_device_map.get(event.id), &config, local_minute_of_day())
+            {
+                let mapping = hotkey_device_map.get(event.id);
+                let outcome = if mapping.is_some_and(|m| m.action == Some(HotkeyAction::ShowMenu)) {
+                    // The tray belongs to the main thread
+                    info!("Hotkey '{}' pressed, opening tray menu", mapping.map_or("?", HotkeyMapping::display_name)); // Log info
+                    let _ = error_sender.send(AppMessage::ShowMenu);
+                    None
+                } else {
+                    handle_hotkey_press(
+                        event.id,
+                        &hotkey_device_map,
+                        &backend,
+                        &available_output_devices,
+                        &available_input_devices,
+                        &config,
+                        &mut recent_outputs,
+                    )
+                };
+                if outcome.as_ref().is_some_and(SwitchOutcome::switched_any) {
+                    record_last_used(&backend);
+                    if let Some(mapping) = mapping {
+                        launch_after_switch(mapping);
                     }
-                    
-                    // Switch input device if specified
-                    if let Some(input_device_name) = &mapping.input_device_name {
-                        match find_and_set_input_device(input_device_name, &available_input_devices, &config) {
-                            Ok(name) => info!("Successfully set input device to {}", name), // Log info
-                            Err(e) => error!("Failed to set input device: {}", e),          // Log error
-                        }
+                }
+                sticky.update(hotkey_device_map.get(event.id), outcome.as_ref(), &config);
+                write_status(&registrations);
+            }
+        }
+
+        // Record any device changes reported by the notification callback
+        let mut devices_changed = false;
+        let mut removed_ids = Vec::new();
+        while let Ok(device_event) = device_event_receiver.try_recv() {
+            match &device_event {
+                DeviceEvent::Removed { device_id } => removed_ids.push(device_id.clone()),
+                DeviceEvent::StateChanged { device_id, state } if *state != DEVICE_STATE_ACTIVE.0 => {
+                    removed_ids.push(device_id.clone())
+                }
+                _ => {}
+            }
+            match device_event {
+                DeviceEvent::DefaultChanged { role: AudioRole::Console, flow, ref device_id } => {
+                    if let Some(device_id) = device_id {
+                        defaults.flow(flow).record(device_id, Instant::now());
                     }
-                } else {
-                    warn!("Received event for unknown hotkey ID: {}", hotkey_id); // Log warning
+                    write_status(&registrations);
+                    let _ = error_sender.send(match flow {
+                        DeviceFlow::Output => AppMessage::DefaultOutputChanged,
+                        DeviceFlow::Input => AppMessage::DefaultInputChanged,
+                    });
+                    sticky.on_default_changed(flow, &backend, &config);
                 }
+                DeviceEvent::DefaultChanged { .. } => {}
+                DeviceEvent::Added { .. } | DeviceEvent::Removed { .. } | DeviceEvent::StateChanged { .. } => {
+                    let _ = error_sender.send(AppMessage::DevicesChanged);
+                    devices_changed = true;
+                }
+            }
+            record_device_event(
+                &device_event,
+                &mut device_history,
+                &available_output_devices,
+                &available_input_devices,
+            );
+        }
+        // Refresh after recording, so removed devices could still be named in the history
+        if devices_changed {
+            refresh_device_list(DeviceFlow::Output, &backend, &mut available_output_devices);
+            refresh_device_list(DeviceFlow::Input, &backend, &mut available_input_devices);
+        }
+        // Replace a default device that just disappeared, if configured (the lists are fresh now)
+        for device_id in &removed_ids {
+            handle_default_removed(
+                device_id,
+                &defaults,
+                &backend,
+                &available_output_devices,
+                &available_input_devices,
+                &config,
+                Instant::now(),
+            );
+        }
+
+        // After sleep, endpoint IDs may have changed and Windows may have dropped hotkeys
+        if resume_watcher.as_ref().is_some_and(ResumeWatcher::take_resumed) {
+            info!("Re-enumerating devices and re-registering hotkeys after resume"); // Log info
+            refresh_device_list(DeviceFlow::Output, &backend, &mut available_output_devices);
+            refresh_device_list(DeviceFlow::Input, &backend, &mut available_input_devices);
+            if hotkeys_registered && let Some(teardown) = teardown.borrow_mut().as_mut() {
+                (hotkey_device_map, registrations) = teardown.reregister_hotkeys(&config, &error_sender);
             }
+            write_status(&registrations);
+            let _ = error_sender.send(AppMessage::DevicesChanged);
         }
 
+        // Re-apply the sticky devices if another app stole the default
+        sticky.check(&backend, &config);
+
         // Process Windows messages (crucial for global-hotkey)
         // Use PeekMessageW for non-blocking check
         let message_handled: BOOL = unsafe { PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE) };
@@ -188,423 +609,5436 @@ fn hotkey_listener_thread(
         }
     }
 
-    // Cleanup
-    info!("Unregistering all hotkeys..."); // Log info
-    if let Err(e) = manager.unregister_all(&hotkeys) {
-        error!("Error unregistering hotkeys: {}", e); // Log error
-        let _ = error_sender.send(AppMessage::HotkeyError(format!(
-            "Failed to unregister hotkeys: {}",
-            e
-        )));
-    } else {
-        info!("Hotkeys unregistered successfully."); // Log info
+    // Cleanup (unless the session end handler already did it)
+    drop(resume_watcher);
+    drop(session_end_watcher);
+    if let Some(teardown) = teardown.borrow_mut().take() {
+        teardown.run(&error_sender);
     }
 
-    // Uninitialize COM for this thread
-    unsafe { windows::Win32::System::Com::CoUninitialize() };
-    info!("Hotkey thread COM uninitialized."); // Log info
-
     info!("Hotkey listener thread finished."); // Log info
 }
 
-// Helper function to find the best matching device using the configured fuzzy match algorithm
-fn find_best_match<'a>(
-    target_name: &str,
-    available_devices: &'a [AudioDevice],
-    config: &Config,
-) -> Option<&'a AudioDevice> {
-    info!(
-        "find_best_match called: target='{}', fuzzy_match={}, algorithm={:?}, threshold={}",
-        target_name, config.fuzzy_match, config.fuzzy_match_algorithm, config.fuzzy_match_threshold
-    );
+// What the listener thread releases when it stops, in order: the hotkeys, the notification
+// callback, then COM. Runs once, from the end of the thread or from the session end handler.
+struct ListenerTeardown {
+    manager: GlobalHotKeyManager,
+    hotkeys: Vec<HotKey>,
+    device_notifier: Option<DeviceNotifier>,
+    com: ComGuard,
+}
 
-    if !config.fuzzy_match {
-        // Exact match mode
-        info!("Using exact match mode");
-        return available_devices.iter().find(|d| d.name == target_name);
+impl ListenerTeardown {
+    // Unregisters every hotkey but keeps the manager, so they can be registered again
+    fn unregister_hotkeys(&mut self, error_sender: &crossbeam_channel::Sender<AppMessage>) {
+        match self.manager.unregister_all(&self.hotkeys) {
+            Ok(()) => info!("Unregistered {} hotkeys.", self.hotkeys.len()), // Log info
+            Err(e) => {
+                error!("Error unregistering hotkeys: {}", e); // Log error
+                let _ = error_sender.send(AppMessage::HotkeyError(format!("Failed to unregister hotkeys: {}", e)));
+            }
+        }
+        self.hotkeys.clear();
     }
 
-    // Fuzzy match mode - use the configured algorithm
-    info!("Using fuzzy match mode with {:?} algorithm", config.fuzzy_match_algorithm);
-    match config.fuzzy_match_algorithm {
-        FuzzyMatchAlgorithm::Skim => {
-            let matcher = SkimMatcherV2::default();
-            let mut best_match: Option<(i64, &AudioDevice)> = None;
-
-            for device in available_devices {
-                if let Some(score) = matcher.fuzzy_match(&device.name, target_name) {
-                    if best_match.is_none() || score > best_match.unwrap().0 {
-                        best_match = Some((score, device));
-                    }
-                }
-            }
+    // Unregisters and registers every hotkey again with the same manager. Returns the new map and
+    // registrations; failures are reported like at startup.
+    fn reregister_hotkeys(
+        &mut self,
+        config: &Config,
+        error_sender: &crossbeam_channel::Sender<AppMessage>,
+    ) -> (HotkeyDeviceMap, Vec<HotkeyRegistration>) {
+        if let Err(e) = self.manager.unregister_all(&self.hotkeys) {
+            warn!("Error unregistering hotkeys before re-registering them: {}", e); // Log warning
+        }
+        let (hotkey_device_map, hotkeys, registrations, failures) = register_hotkeys(&self.manager, config);
+        info!("Re-registered {} of {} hotkeys.", hotkeys.len(), registrations.len()); // Log info
+        if !failures.is_empty() {
+            let _ = error_sender.send(AppMessage::HotkeyError(format_hotkey_failures(&failures)));
+        }
+        self.hotkeys = hotkeys;
+        (hotkey_device_map, registrations)
+    }
 
-            best_match.map(|(_score, device)| device)
+    fn run(self, error_sender: &crossbeam_channel::Sender<AppMessage>) {
+        info!("Unregistering all hotkeys..."); // Log info
+        if let Err(e) = self.manager.unregister_all(&self.hotkeys) {
+            error!("Error unregistering hotkeys: {}", e); // Log error
+            let _ = error_sender.send(AppMessage::HotkeyError(format!(
+                "Failed to unregister hotkeys: {}",
+                e
+            )));
+        } else {
+            info!("Hotkeys unregistered successfully."); // Log info
         }
-        FuzzyMatchAlgorithm::Levenshtein => {
-            // Use normalized Levenshtein distance (0.0 = completely different, 1.0 = identical)
-            // Lower distance is better, so we look for the minimum distance
-            let mut best_match: Option<(f64, &AudioDevice)> = None;
 
-            for device in available_devices {
-                // Normalize both strings to lowercase for case-insensitive comparison
-                let device_name_lower = device.name.to_lowercase();
-                let target_name_lower = target_name.to_lowercase();
+        // Release the notification callback while COM is still initialized
+        drop(self.device_notifier);
 
-                // Calculate normalized Levenshtein similarity (1.0 = identical, 0.0 = completely different)
-                let similarity = strsim::normalized_levenshtein(&device_name_lower, &target_name_lower);
+        // Uninitialize COM for this thread
+        drop(self.com);
+        info!("Hotkey thread COM uninitialized."); // Log info
+    }
+}
 
-                info!(
-                    "Levenshtein similarity: '{}' vs '{}' = {:.3}",
-                    device.name, target_name, similarity
-                );
+// Minutes since local midnight
+fn local_minute_of_day() -> u32 {
+    let now = unsafe { windows::Win32::System::SystemInformation::GetLocalTime() };
+    u32::from(now.wHour) * 60 + u32::from(now.wMinute)
+}
 
-                // Keep the device with highest similarity
-                if best_match.is_none() || similarity > best_match.unwrap().0 {
-                    best_match = Some((similarity, device));
-                }
-            }
+// Whether a hotkey press falls in the disable-between window (and the mapping doesn't opt out)
+fn blocked_by_dnd(hotkey_id: u32, mapping: Option<&HotkeyMapping>, config: &Config, minute_of_day: u32) -> bool {
+    let Some(window) = config.disable_between else {
+        return false;
+    };
+    if mapping.is_some_and(|m| m.ignore_dnd) || !window.contains(minute_of_day) {
+        return false;
+    }
+    match mapping {
+        Some(mapping) => info!("Hotkey '{}' ignored: DND window.", mapping.display_name()), // Log info
+        None => info!("Hotkey ID {} ignored: DND window.", hotkey_id), // Log info
+    }
+    true
+}
 
-            // Use the configurable threshold from config
-            let threshold = config.fuzzy_match_threshold;
-            best_match.and_then(|(similarity, device)| {
-                if similarity >= threshold {
-                    info!(
-                        "Best match found: '{}' with similarity {:.3} (threshold: {:.3})",
-                        device.name, similarity, threshold
-                    );
-                    Some(device)
-                } else {
-                    warn!(
-                        "Best candidate '{}' has similarity {:.3} below threshold {:.3}",
-                        device.name, similarity, threshold
-                    );
-                    None
-                }
-            })
+// Windows keeps sending Pressed events while a hotkey is held down. A Pressed event for a hotkey
+// that was pressed within KEY_REPEAT_WINDOW and not released since is treated as a key repeat.
+const KEY_REPEAT_WINDOW: Duration = Duration::from_millis(1000);
+
+// Decides which hotkey events trigger their mapping: switches fire once per press, repeatable
+// actions (volume) keep firing while held
+#[derive(Default)]
+struct KeyRepeatFilter {
+    held: HashMap<u32, Instant>,
+    last_fired: HashMap<u32, Instant>,
+    debounce: Duration, // Minimum time between two firings of the same non-repeatable hotkey
+}
+
+impl KeyRepeatFilter {
+    fn with_debounce(debounce: Duration) -> Self {
+        KeyRepeatFilter { debounce, ..KeyRepeatFilter::default() }
+    }
+
+    fn should_fire(&mut self, hotkey_id: u32, state: HotKeyState, repeatable: bool, now: Instant) -> bool {
+        if state == HotKeyState::Released {
+            self.held.remove(&hotkey_id);
+            return false;
+        }
+        let is_repeat = self
+            .held
+            .insert(hotkey_id, now)
+            .is_some_and(|previous| now.duration_since(previous) < KEY_REPEAT_WINDOW);
+        if is_repeat && !repeatable {
+            debug!("Ignoring key repeat for hotkey ID {}", hotkey_id);
+            return false;
+        }
+        // Separate presses in quick succession: only the first one within the window switches
+        if !repeatable
+            && let Some(last) = self.last_fired.get(&hotkey_id)
+            && now.duration_since(*last) < self.debounce
+        {
+            debug!("Ignoring hotkey ID {} pressed again within {:?}", hotkey_id, self.debounce);
+            return false;
         }
+        self.last_fired.insert(hotkey_id, now);
+        true
     }
 }
 
-// Helper function to find and set the audio output device
-fn find_and_set_output_device(
-    target_device_name: &str,
-    available_devices: &[AudioDevice],
-    config: &Config,
-) -> Result<String, Box<dyn Error>> {
-    match find_best_match(target_device_name, available_devices, config) {
-        Some(device) => {
-            set_default_output_device(&device.id)?;
-            Ok(device.name.clone())
+// Appends an endpoint add/remove/state change to the persisted device history
+fn record_device_event(
+    event: &DeviceEvent,
+    history: &mut DeviceHistory,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+) {
+    let (change, device_id) = match event {
+        DeviceEvent::Added { device_id } => (DeviceChange::Added, device_id),
+        DeviceEvent::Removed { device_id } => (DeviceChange::Removed, device_id),
+        DeviceEvent::StateChanged { device_id, state } => match DeviceChange::from_state(*state) {
+            Some(change) => (change, device_id),
+            None => return,
+        },
+        DeviceEvent::DefaultChanged { .. } => return,
+    };
+
+    // Prefer the cached name (removed devices may no longer be queryable)
+    let device_name = available_output_devices
+        .iter()
+        .chain(available_input_devices)
+        .find(|d| &d.id == device_id)
+        .map(|d| d.name.clone())
+        .or_else(|| get_device_by_id(device_id).ok().map(|d| d.name))
+        .unwrap_or_else(|| "Unknown device".to_string());
+
+    history.record(change, device_id, &device_name);
+    if let Err(e) = history.save() {
+        warn!("Failed to save device history: {}", e); // Log warning
+    }
+}
+
+// Re-enumerates the devices of one flow after a hot-plug event; keeps the previous list on failure
+fn refresh_device_list(flow: DeviceFlow, backend: &dyn AudioBackend, devices: &mut Vec<AudioDevice>) {
+    let refreshed = match flow {
+        DeviceFlow::Output => backend.list_output_devices(),
+        DeviceFlow::Input => backend.list_input_devices(),
+    };
+    match refreshed {
+        Ok(refreshed) => {
+            info!("Refreshed {:?} devices: {} available", flow, refreshed.len()); // Log info
+            *devices = refreshed;
         }
-        None => {
-            let match_type = if config.fuzzy_match {
-                format!("{:?} fuzzy match", config.fuzzy_match_algorithm)
-            } else {
-                "exact match".to_string()
-            };
-            Err(format!("No {} found for output device '{}'", match_type, target_device_name).into())
+        Err(e) => warn!("Failed to refresh {:?} devices, keeping the previous list: {}", flow, e), // Log warning
+    }
+}
+
+// Dispatches a pressed hotkey to its configured action or device switch
+fn handle_hotkey_press(
+    hotkey_id: u32,
+    hotkey_device_map: &HotkeyDeviceMap,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+    recent_outputs: &mut RecentOutputs,
+) -> Option<SwitchOutcome> {
+    let Some(mapping) = hotkey_device_map.get(hotkey_id) else {
+        warn!("Received event for unknown hotkey ID: {}", hotkey_id); // Log warning
+        return None;
+    };
+    // Toggle-back state belongs to the mapping, whichever of its combinations was pressed
+    let mapping_id = hotkey_device_map.primary_id(hotkey_id).unwrap_or(hotkey_id);
+    let label = mapping.display_name();
+    let steps = mapping.steps();
+    if !mapping.actions.is_empty() {
+        info!("Hotkey '{}' pressed, running {} action(s)", label, steps.len()); // Log info
+    }
+
+    // Every step runs, even after a failed one; the failures are reported together
+    let mut outcome: Option<SwitchOutcome> = None;
+    let mut failures = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        let result = match step {
+            Action::Fields => Ok(run_mapping_fields(
+                mapping_id,
+                mapping,
+                backend,
+                available_output_devices,
+                available_input_devices,
+                config,
+                recent_outputs,
+            )),
+            _ => run_action(step, mapping.role, backend, available_output_devices, available_input_devices, config, recent_outputs),
+        };
+        match result {
+            Ok(Some(step_outcome)) => outcome.get_or_insert_default().merge(step_outcome),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Hotkey '{}': action {} ('{}') failed: {}", label, i + 1, step.kind(), e); // Log error
+                failures.push(format!("{}. {}: {}", i + 1, step.kind(), e));
+            }
         }
     }
+
+    if let Some(outcome) = &outcome {
+        announce_switch(outcome, Some(label), config);
+    }
+    if !failures.is_empty() {
+        show_notification(
+            &notification_title(Some(label)),
+            &format!("{} of {} actions failed:\n{}", failures.len(), steps.len(), failures.join("\n")),
+            true,
+        );
+    }
+    outcome
 }
 
-// Helper function to find and set the audio input device
-fn find_and_set_input_device(
-    target_device_name: &str,
-    available_devices: &[AudioDevice],
+// Runs a mapping written with the flat fields: its 'action', 'activate-profile' or device switch.
+// `mapping_id` identifies the mapping for toggle-back (see HotkeyDeviceMap::primary_id).
+fn run_mapping_fields(
+    mapping_id: u32,
+    mapping: &HotkeyMapping,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
     config: &Config,
-) -> Result<String, Box<dyn Error>> {
-    match find_best_match(target_device_name, available_devices, config) {
-        Some(device) => {
-            set_default_input_device(&device.id)?;
-            Ok(device.name.clone())
+    recent_outputs: &mut RecentOutputs,
+) -> Option<SwitchOutcome> {
+    let label = mapping.display_name();
+    match &mapping.action {
+        Some(HotkeyAction::SaveSnapshot) => {
+            info!("Hotkey '{}' pressed, saving snapshot '{}'", label, mapping.snapshot_name()); // Log info
+            if let Err(e) = save_snapshot(mapping.snapshot_name()) {
+                error!("Failed to save snapshot '{}': {}", mapping.snapshot_name(), e); // Log error
+            }
+            None
+        }
+        Some(HotkeyAction::RestoreSnapshot) => {
+            info!("Hotkey '{}' pressed, restoring snapshot '{}'", label, mapping.snapshot_name()); // Log info
+            match load_snapshot(mapping.snapshot_name()).and_then(|snapshot| restore_snapshot(&snapshot)) {
+                Ok(()) => info!("Successfully restored snapshot '{}'", mapping.snapshot_name()), // Log info
+                Err(e) => error!("Failed to restore snapshot '{}': {}", mapping.snapshot_name(), e), // Log error
+            }
+            None
+        }
+        Some(action @ (HotkeyAction::VolumeUp | HotkeyAction::VolumeDown)) => {
+            let delta = if *action == HotkeyAction::VolumeUp { mapping.volume_step() } else { -mapping.volume_step() };
+            match adjust_output_volume(delta) {
+                Ok(level) => info!("Hotkey '{}' pressed, output volume now {:.0}%", label, level * 100.0), // Log info
+                Err(e) => error!("Failed to change output volume: {}", e), // Log error
+            }
+            None
+        }
+        Some(HotkeyAction::ToggleMute) => {
+            match toggle_output_mute() {
+                Ok((device_name, muted)) => {
+                    let state = if muted { "muted" } else { "unmuted" };
+                    info!("Hotkey '{}' pressed, output device '{}' {}", label, device_name, state); // Log info
+                    show_notification(&notification_title(Some(label)), &format!("{} {}", device_name, state), false);
+                }
+                Err(e) => error!("Failed to toggle output mute: {}", e), // Log error
+            }
+            None
+        }
+        Some(HotkeyAction::ToggleRecent) => {
+            toggle_recent_output(label, mapping.current_role(), backend, recent_outputs)
+        }
+        Some(HotkeyAction::ShowMenu) => {
+            // Forwarded to the main thread by the listener loop, nothing to switch here
+            None
+        }
+        Some(HotkeyAction::EditConfig) => {
+            info!("Hotkey '{}' pressed, opening the config file", label); // Log info
+            open_config_in_editor(config.source_path.as_deref());
+            None
+        }
+        Some(action @ (HotkeyAction::NextOutput | HotkeyAction::PrevOutput)) => {
+            let forward = *action == HotkeyAction::NextOutput;
+            let outcome = step_device(label, DeviceFlow::Output, forward, mapping.current_role(), backend)?;
+            if let Some(device) = &outcome.output {
+                recent_outputs.record(device);
+            }
+            Some(outcome)
+        }
+        Some(action @ (HotkeyAction::NextInput | HotkeyAction::PrevInput)) => {
+            let forward = *action == HotkeyAction::NextInput;
+            step_device(label, DeviceFlow::Input, forward, mapping.current_role(), backend)
+        }
+        Some(HotkeyAction::SetBothRoles) => {
+            let outcome = set_all_roles(
+                mapping,
+                backend,
+                available_output_devices,
+                available_input_devices,
+                config,
+            );
+            if let Some(device) = &outcome.output {
+                recent_outputs.record(device);
+            }
+            Some(outcome)
         }
         None => {
-            let match_type = if config.fuzzy_match {
-                format!("{:?} fuzzy match", config.fuzzy_match_algorithm)
+            let outcome = if let Some(profile_name) = &mapping.activate_profile {
+                info!("Hotkey '{}' pressed, activating profile '{}'", label, profile_name); // Log info
+                switch_to_profile(profile_name, backend, available_output_devices, available_input_devices, config)
             } else {
-                "exact match".to_string()
+                match recent_outputs.take_toggle_back(mapping_id) {
+                    Some(previous) => restore_toggle_back(label, &previous, backend, available_output_devices),
+                    None => {
+                        let previous = if mapping.toggle_back {
+                            backend.default_device(DeviceFlow::Output, mapping.current_role()).ok()
+                        } else {
+                            None
+                        };
+                        let outcome = switch_mapping_devices(
+                            mapping,
+                            backend,
+                            available_output_devices,
+                            available_input_devices,
+                            config,
+                        );
+                        if let (Some(previous), Some(device)) = (previous, &outcome.output)
+                            && previous.id != device.id
+                        {
+                            recent_outputs.remember_toggle_back(mapping_id, previous);
+                        }
+                        outcome
+                    }
+                }
             };
-            Err(format!("No {} found for input device '{}'", match_type, target_device_name).into())
+            if let Some(device) = &outcome.output {
+                recent_outputs.record(device);
+            }
+            Some(outcome)
         }
     }
 }
 
-// Function to validate that configured devices exist on the system
-fn validate_configured_devices(config: &Config) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>) {
-    let mut missing_output_devices = Vec::new();
-    let mut missing_input_devices = Vec::new();
-
-    // Get available devices
-    let available_output_devices = match list_output_devices() {
-        Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to list output devices during validation: {}", e);
-            return (missing_output_devices, missing_input_devices, Vec::new(), Vec::new());
+// Runs one step of an 'actions' list. The device steps switch the roles given by the mapping's 'role'.
+fn run_action(
+    step: &Action,
+    role: MappingRole,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+    recent_outputs: &mut RecentOutputs,
+) -> Result<Option<SwitchOutcome>, Box<dyn Error>> {
+    let role = role.audio_role();
+    match step {
+        Action::SetOutput { device } => {
+            let (device, score) = match role {
+                Some(role) => find_and_set_output_role(backend, device, available_output_devices, config, role)?,
+                None => find_and_set_output_device(backend, device, available_output_devices, config)?,
+            };
+            let mut outcome = SwitchOutcome::default();
+            outcome.record_score(&device, score);
+            if role == Some(AudioRole::Communications) {
+                info!("Successfully set communications output device to {}{}", device.name, score_suffix(score, config)); // Log info
+                outcome.communications = Some(device);
+                return Ok(Some(outcome));
+            }
+            info!("Successfully set output device to {}{}", device.name, score_suffix(score, config)); // Log info
+            recent_outputs.record(&device);
+            outcome.output = Some(device);
+            Ok(Some(outcome))
         }
-    };
-
-    let available_input_devices = match list_input_devices() {
-        Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to list input devices during validation: {}", e);
-            return (missing_output_devices, missing_input_devices, Vec::new(), Vec::new());
+        Action::SetInput { device } => {
+            let (device, score) = match role {
+                Some(role) => find_and_set_input_role(backend, device, available_input_devices, config, role)?,
+                None => find_and_set_input_device(backend, device, available_input_devices, config)?,
+            };
+            info!("Successfully set input device to {}{}", device.name, score_suffix(score, config)); // Log info
+            let mut outcome = SwitchOutcome::default();
+            outcome.record_score(&device, score);
+            outcome.input = Some(device);
+            Ok(Some(outcome))
         }
-    };
-
-    // Create lists of available device names for the notification
-    let available_output_names: Vec<String> = available_output_devices.iter().map(|d| d.name.clone()).collect();
-    let available_input_names: Vec<String> = available_input_devices.iter().map(|d| d.name.clone()).collect();
-
-    // Check each configured hotkey mapping
-    for mapping in &config.hotkeys {
-        // Check output device using the unified matching logic
-        if find_best_match(&mapping.device_name, &available_output_devices, config).is_none() {
-            let entry = format!("{} (hotkey: {})", mapping.device_name, mapping.keys);
-            missing_output_devices.push(entry.clone());
-            warn!("Output device not found: {}", entry);
+        Action::SetVolume { level } => {
+            let device = backend.default_output_device()?;
+            backend.set_volume(&device.id, *level)?;
+            info!("Set volume of '{}' to {:.0}%", device.name, level * 100.0); // Log info
+            Ok(None)
         }
-
-        // Check input device if specified
-        if let Some(input_device_name) = &mapping.input_device_name {
-            if find_best_match(input_device_name, &available_input_devices, config).is_none() {
-                let entry = format!("{} (hotkey: {})", input_device_name, mapping.keys);
-                missing_input_devices.push(entry.clone());
-                warn!("Input device not found: {}", entry);
-            }
+        Action::ToggleMute => {
+            let (device_name, muted) = toggle_output_mute()?;
+            info!("Output device '{}' {}", device_name, if muted { "muted" } else { "unmuted" }); // Log info
+            Ok(None)
         }
+        Action::Run { program, args } => {
+            let pid = spawn_program(program, args)?;
+            info!("Started '{}' (PID {})", program, pid); // Log info
+            Ok(None)
+        }
+        Action::Fields => Ok(None), // Run by handle_hotkey_press, which has the whole mapping
     }
+}
 
-    (missing_output_devices, missing_input_devices, available_output_names, available_input_names)
+// The devices a hotkey press actually switched to
+#[derive(Debug, Default)]
+struct SwitchOutcome {
+    output: Option<AudioDevice>,
+    input: Option<AudioDevice>,
+    communications: Option<AudioDevice>, // Set when the mapping routes the communications role separately
+    scores: HashMap<String, f64>, // Match score by device ID, for devices resolved from a configured name
 }
 
-// Function to show a Windows notification for missing devices
-fn show_missing_devices_notification(
-    missing_output: &[String], 
-    missing_input: &[String], 
-    available_output: &[String], 
-    available_input: &[String]
-) {
-    if missing_output.is_empty() && missing_input.is_empty() {
-        return; // Nothing to show
-    }
+// The two most recently activated output devices, most recent first, plus the output
+// each toggle-back hotkey should return to on its next press
+#[derive(Default)]
+struct RecentOutputs {
+    devices: Vec<AudioDevice>,
+    toggle_back: HashMap<u32, AudioDevice>,
+}
 
-    let mut message = String::from("SoundSwitch has started but some configured devices were not found:\n\n");
+impl RecentOutputs {
+    fn record(&mut self, device: &AudioDevice) {
+        self.devices.retain(|d| d.id != device.id);
+        self.devices.insert(0, device.clone());
+        self.devices.truncate(2);
+    }
 
-    if !missing_output.is_empty() {
-        message.push_str(&format!("Missing Output Device{} ({}):\n", 
-            if missing_output.len() > 1 { "s" } else { "" },
-            missing_output.len()));
-        for device in missing_output {
-            message.push_str(&format!("  • {}\n", device));
+    // The recent device that isn't the current default (the most recent one if neither is)
+    fn toggle_target(&self, current_id: Option<&str>) -> Option<&AudioDevice> {
+        if self.devices.len() < 2 {
+            return None;
         }
-        message.push('\n');
+        self.devices.iter().find(|d| Some(d.id.as_str()) != current_id)
     }
 
-    if !missing_input.is_empty() {
-        message.push_str(&format!("Missing Input Device{} ({}):\n", 
-            if missing_input.len() > 1 { "s" } else { "" },
-            missing_input.len()));
-        for device in missing_input {
-            message.push_str(&format!("  • {}\n", device));
-        }
-        message.push('\n');
+    fn remember_toggle_back(&mut self, mapping_id: u32, previous: AudioDevice) {
+        self.toggle_back.insert(mapping_id, previous);
     }
 
-    message.push_str("The application will continue to run, but these hotkeys will not work until the devices are available.\n\n");
+    fn take_toggle_back(&mut self, mapping_id: u32) -> Option<AudioDevice> {
+        self.toggle_back.remove(&mapping_id)
+    }
+}
 
-    // Add available devices list to help with configuration
-    if !missing_output.is_empty() && !available_output.is_empty() {
-        message.push_str(&format!("Available Output Devices ({}):\n", available_output.len()));
-        for device in available_output {
-            message.push_str(&format!("  • {}\n", device));
+// Switches to the devices of a [[profiles]] entry (in the configured switch order).
+// Used by activate-profile hotkeys and the tray menu.
+fn switch_to_profile(
+    profile_name: &str,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+) -> SwitchOutcome {
+    let mut outcome = SwitchOutcome::default();
+    let Some(profile) = config.profile(profile_name) else {
+        warn!("Profile '{}' is not defined", profile_name); // Log warning
+        return outcome;
+    };
+
+    for flow in config.switch_order.flows() {
+        match flow {
+            DeviceFlow::Output => {
+                if let Some(device_name) = &profile.device_name {
+                    match find_and_set_output_device(backend, device_name, available_output_devices, config) {
+                        Ok((device, score)) => {
+                            info!("Profile '{}': set output device to {}{}", profile.name, device.name, score_suffix(score, config)); // Log info
+                            outcome.record_score(&device, score);
+                            outcome.output = Some(device);
+                        }
+                        Err(e) => error!("Profile '{}': failed to set output device: {}", profile.name, e), // Log error
+                    }
+                }
+            }
+            DeviceFlow::Input => {
+                if let Some(device_name) = &profile.input_device_name {
+                    match find_and_set_input_device(backend, device_name, available_input_devices, config) {
+                        Ok((device, score)) => {
+                            info!("Profile '{}': set input device to {}{}", profile.name, device.name, score_suffix(score, config)); // Log info
+                            outcome.record_score(&device, score);
+                            outcome.input = Some(device);
+                        }
+                        Err(e) => error!("Profile '{}': failed to set input device: {}", profile.name, e), // Log error
+                    }
+                }
+            }
         }
-        message.push('\n');
     }
+    outcome
+}
 
-    if !missing_input.is_empty() && !available_input.is_empty() {
-        message.push_str(&format!("Available Input Devices ({}):\n", available_input.len()));
-        for device in available_input {
-            message.push_str(&format!("  • {}\n", device));
+// Second press of a toggle-back hotkey: returns to the output that was default before the
+// first press. If that device has disappeared since, the current default is left alone.
+fn restore_toggle_back(
+    label: &str,
+    previous: &AudioDevice,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+) -> SwitchOutcome {
+    let Some(device) = available_output_devices.iter().find(|d| d.id == previous.id) else {
+        warn!(
+            "Hotkey '{}' pressed, but previous output device '{}' is no longer available; leaving the current device",
+            label, previous.name
+        ); // Log warning
+        return SwitchOutcome::default();
+    };
+
+    info!("Hotkey '{}' pressed, toggling back to output device '{}'", label, device.name); // Log info
+    match backend.set_default_output_device(&device.id) {
+        Ok(()) => {
+            info!("Successfully set output device to {}", device.name); // Log info
+            SwitchOutcome { output: Some(device.clone()), ..SwitchOutcome::default() }
+        }
+        Err(e) => {
+            error!("Failed to set output device: {}", e); // Log error
+            SwitchOutcome::default()
         }
-        message.push('\n');
     }
+}
 
-    message.push_str("Possible solutions:\n");
-    message.push_str("• Check that the devices are connected and enabled in Windows Sound settings\n");
-    message.push_str("• Verify the device names in your config.toml file match the available devices above\n");
-    message.push_str("• Consider enabling fuzzy matching in your configuration");
+// Flips between the two most recently activated output devices. `role` is the default that counts as current.
+fn toggle_recent_output(
+    label: &str,
+    role: AudioRole,
+    backend: &dyn AudioBackend,
+    recent_outputs: &mut RecentOutputs,
+) -> Option<SwitchOutcome> {
+    let current = backend.default_device(DeviceFlow::Output, role).ok();
+    let Some(target) = recent_outputs.toggle_target(current.as_ref().map(|d| d.id.as_str())).cloned() else {
+        warn!("Hotkey '{}' pressed, but there are fewer than two recent output switches to toggle", label); // Log warning
+        show_notification(
+            "SoundSwitch - Toggle Recent",
+            "Need at least two recent switches to toggle between them.",
+            false,
+        );
+        return None;
+    };
 
-    // Show Windows MessageBox
-    use windows::Win32::UI::WindowsAndMessaging::{MB_ICONWARNING, MB_OK, MessageBoxW};
-    use windows::core::HSTRING;
+    info!("Hotkey '{}' pressed, toggling to recent output device '{}'", label, target.name); // Log info
+    match backend.set_default_output_device(&target.id) {
+        Ok(()) => {
+            info!("Successfully set output device to {}", target.name); // Log info
+            recent_outputs.record(&target);
+            Some(SwitchOutcome { output: Some(target), ..SwitchOutcome::default() })
+        }
+        Err(e) => {
+            error!("Failed to set output device: {}", e); // Log error
+            None
+        }
+    }
+}
 
-    let title = HSTRING::from("SoundSwitch - Missing Audio Devices");
-    let content = HSTRING::from(message);
+// Switches to the device after (or, if not `forward`, before) the current default of `role` among
+// all connected devices of the flow, in enumeration order and wrapping around. Starts at the first
+// device if the current default isn't connected. Sets every role.
+fn step_device(
+    label: &str,
+    flow: DeviceFlow,
+    forward: bool,
+    role: AudioRole,
+    backend: &dyn AudioBackend,
+) -> Option<SwitchOutcome> {
+    let devices = match flow {
+        DeviceFlow::Output => backend.list_output_devices(),
+        DeviceFlow::Input => backend.list_input_devices(),
+    };
+    let devices = match devices {
+        Ok(devices) => devices,
+        Err(e) => {
+            error!("Failed to list {:?} devices: {}", flow, e); // Log error
+            return None;
+        }
+    };
+    let current = backend.default_device(flow, role).ok();
+    let current_index = current.as_ref().and_then(|current| devices.iter().position(|d| d.id == current.id));
+    let target_index = match (current_index, devices.len()) {
+        (_, 0) => {
+            warn!("Hotkey '{}' pressed, but no {:?} device is connected", label, flow); // Log warning
+            return None;
+        }
+        (Some(_), 1) => {
+            info!("Hotkey '{}' pressed, but '{}' is the only {:?} device", label, devices[0].name, flow); // Log info
+            return None;
+        }
+        (None, _) => 0,
+        (Some(i), len) if forward => (i + 1) % len,
+        (Some(i), len) => (i + len - 1) % len,
+    };
+    let target = devices[target_index].clone();
 
-    unsafe {
-        MessageBoxW(
-            None,
-            &content,
-            &title,
-            MB_OK | MB_ICONWARNING,
-        );
+    info!(
+        "Hotkey '{}' pressed, stepping {:?} from index {:?} to {} ('{}')",
+        label, flow, current_index, target_index, target.name
+    ); // Log info
+    let result = match flow {
+        DeviceFlow::Output => backend.set_default_output_device(&target.id),
+        DeviceFlow::Input => backend.set_default_input_device(&target.id),
+    };
+    match (result, flow) {
+        (Ok(()), DeviceFlow::Output) => {
+            info!("Successfully set output device to {}", target.name); // Log info
+            Some(SwitchOutcome { output: Some(target), ..SwitchOutcome::default() })
+        }
+        (Ok(()), DeviceFlow::Input) => {
+            info!("Successfully set input device to {}", target.name); // Log info
+            Some(SwitchOutcome { input: Some(target), ..SwitchOutcome::default() })
+        }
+        (Err(e), _) => {
+            error!("Failed to set {:?} device: {}", flow, e); // Log error
+            None
+        }
     }
 }
 
-fn run_tray_app() -> Result<(), Box<dyn Error>> {
-    info!("Starting SoundSwitch with Tray Icon..."); // Log info
+// Switches the output device and, if configured, the input device of a mapping
+fn switch_mapping_devices(
+    mapping: &HotkeyMapping,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+) -> SwitchOutcome {
+    info!(
+        // Log info
+        "Hotkey '{}' pressed, switching to output: '{:?}', input: '{:?}'",
+        mapping.display_name(), mapping.device_name, mapping.input_device_name
+    );
+    let mut outcome = SwitchOutcome::default();
+    if mapping.output_target().is_some() && mapping.input_target().is_some() {
+        info!("Switch order: {:?}", config.switch_order); // Log info
+    }
 
-    // 1. Load Configuration (needed for the hotkey thread)
-    let config = match load_config() {
-        Ok(cfg) => {
-            info!("Configuration loaded successfully."); // Log info
-            if cfg.hotkeys.is_empty() {
-                warn!("No hotkeys defined in the configuration."); // Log warning
+    for flow in config.switch_order.flows() {
+        match flow {
+            DeviceFlow::Output => {
+                // A device-names list switches to the next entry after the current default
+                let device_name = match &mapping.device_names {
+                    Some(names) => next_in_cycle(
+                        mapping.display_name(),
+                        names,
+                        mapping.current_role(),
+                        backend,
+                        available_output_devices,
+                        config,
+                    ),
+                    None if mapping.fallback_devices.is_some() => {
+                        preferred_output(mapping, available_output_devices, config).or(mapping.output_target())
+                    }
+                    None => mapping.output_target(),
+                };
+
+                // Switch output device (only console/multimedia if communications goes elsewhere)
+                if let Some(device_name) = device_name {
+                    let role = if mapping.comms_device_name.is_some() { Some(AudioRole::Console) } else { mapping.role.audio_role() };
+                    let result = match role {
+                        Some(role) => find_and_set_output_role(backend, device_name, available_output_devices, config, role),
+                        None => find_and_set_output_device(backend, device_name, available_output_devices, config),
+                    };
+                    match result {
+                        Ok((device, score)) if role == Some(AudioRole::Communications) => {
+                            info!("Successfully set communications output device to {}{}", device.name, score_suffix(score, config)); // Log info
+                            outcome.record_score(&device, score);
+                            outcome.communications = Some(device);
+                        }
+                        Ok((device, score)) => {
+                            info!("Successfully set output device to {}{}", device.name, score_suffix(score, config)); // Log info
+                            apply_mapping_format(mapping, &device, backend);
+                            apply_mapping_volume(mapping, &device, backend);
+                            outcome.record_score(&device, score);
+                            outcome.output = Some(device);
+                        }
+                        Err(e) => error!("Failed to set output device: {}", e), // Log error
+                    }
+                }
+
+                // Follow the output with the capture endpoint of the same physical device
+                if config.auto_pair_input
+                    && mapping.input_target().is_none()
+                    && let Some(output) = &outcome.output
+                {
+                    match paired_input_device(output, available_input_devices) {
+                        Some(input) => match backend.set_default_input_device(&input.id) {
+                            Ok(()) => {
+                                info!("Successfully set paired input device to {}", input.name); // Log info
+                                outcome.input = Some(input.clone());
+                            }
+                            Err(e) => error!("Failed to set paired input device: {}", e), // Log error
+                        },
+                        None => info!("No paired input device found for '{}'", output.name), // Log info
+                    }
+                }
+
+                // Switch the communications output device if routed separately
+                if let Some(comms_device_name) = &mapping.comms_device_name {
+                    match find_and_set_output_role(
+                        backend,
+                        comms_device_name,
+                        available_output_devices,
+                        config,
+                        AudioRole::Communications,
+                    ) {
+                        Ok((device, score)) => {
+                            info!("Successfully set communications output device to {}{}", device.name, score_suffix(score, config)); // Log info
+                            outcome.record_score(&device, score);
+                            outcome.communications = Some(device);
+                        }
+                        Err(e) => error!("Failed to set communications output device: {}", e), // Log error
+                    }
+                }
+            }
+            DeviceFlow::Input => {
+                // Switch input device if specified
+                if let Some(input_device_name) = mapping.input_target() {
+                    let result = match mapping.role.audio_role() {
+                        Some(role) => find_and_set_input_role(backend, input_device_name, available_input_devices, config, role),
+                        None => find_and_set_input_device(backend, input_device_name, available_input_devices, config),
+                    };
+                    match result {
+                        Ok((device, score)) => {
+                            info!("Successfully set input device to {}{}", device.name, score_suffix(score, config)); // Log info
+                            outcome.record_score(&device, score);
+                            outcome.input = Some(device);
+                        }
+                        Err(e) => error!("Failed to set input device: {}", e), // Log error
+                    }
+                }
             }
-            cfg // Return the loaded config
-        }
-        Err(e) => {
-            // Print the specific config error and return it to exit run_tray_app
-            error!("!!! Fatal: Error loading configuration: {} !!!", e); // Log error
-            return Err(e); // Propagate the error
         }
-    };
-    // If we reach here, config loaded successfully.
-
-    // 1.5. Validate configured devices and show notification if any are missing
-    info!("Validating configured devices..."); // Log info
-    let (missing_output, missing_input, available_output, available_input) = validate_configured_devices(&config);
-    if !missing_output.is_empty() || !missing_input.is_empty() {
-        warn!(
-            "Missing devices found - Output: {:?}, Input: {:?}",
-            missing_output, missing_input
-        ); // Log warning
-        show_missing_devices_notification(&missing_output, &missing_input, &available_output, &available_input);
-    } else {
-        info!("All configured devices found."); // Log info
     }
 
-    // 2. Setup communication channels (Restore)
-    let shutdown_signal = Arc::new(AtomicBool::new(false));
-    let (error_sender, error_receiver) = crossbeam_channel::unbounded::<AppMessage>();
+    outcome
+}
 
-    // 3. Spawn Hotkey Listener Thread (Restore)
-    let shutdown_signal_clone = Arc::clone(&shutdown_signal);
-    let error_sender_clone = error_sender.clone(); // Clone sender for the thread
-    let config_clone = config.clone(); // Clone config for the thread
+// The first entry of the mapping's priority list (device-name, then fallback-devices) that resolves
+// to a connected device. None if none does; the caller then reports the primary as missing.
+fn preferred_output<'a>(
+    mapping: &'a HotkeyMapping,
+    available_output_devices: &[AudioDevice],
+    config: &Config,
+) -> Option<&'a str> {
+    let priority: Vec<&str> = mapping.output_priority().collect();
+    let (position, target) = priority
+        .iter()
+        .enumerate()
+        .find(|(_, target)| find_best_match(target, available_output_devices, config).is_some())?;
+    info!(
+        "Hotkey '{}': priority entry {} of {} ('{}') is available",
+        mapping.display_name(),
+        position + 1,
+        priority.len(),
+        target
+    ); // Log info
+    Some(target)
+}
 
-    let hotkey_thread_handle = thread::spawn(move || {
-        hotkey_listener_thread(config_clone, shutdown_signal_clone, error_sender_clone)
-    });
-    info!("Hotkey listener thread spawned."); // Log info
+// Sets the mapping's configured format on the output device it switched to. A device that
+// refuses (e.g. because an application holds it in exclusive mode) is only logged; the switch
+// itself already succeeded.
+fn apply_mapping_format(mapping: &HotkeyMapping, device: &AudioDevice, backend: &dyn AudioBackend) {
+    let Some(format) = mapping.format else {
+        return;
+    };
+    match backend.set_device_format(&device.id, format) {
+        Ok(()) => info!("Set format of '{}' to {} Hz / {} bit", device.name, format.sample_rate, format.bits), // Log info
+        Err(e) => warn!(
+            "Failed to set format of '{}' to {} Hz / {} bit: {}",
+            device.name, format.sample_rate, format.bits, e
+        ), // Log warning
+    }
+}
 
-    // 4. Setup Tray Icon (Restore)
-    // Use a simple placeholder icon name for now.
-    // For a real icon, you'd load it from a file (e.g., .ico on Windows)
-    // using `tray.set_icon(Icon::from_path("path/to/icon.ico")?)`
-    let mut tray = TrayItem::new(
-        "SoundSwitch",
-        tray_item::IconSource::Resource("default-icon"),
-    )
-    .map_err(|e| format!("Failed to create tray icon: {}", e))?;
-    info!("Tray icon created."); // Log info
+// Sets the mapping's configured volume (clamped to 0.0-1.0) on the output device it switched to
+fn apply_mapping_volume(mapping: &HotkeyMapping, device: &AudioDevice, backend: &dyn AudioBackend) {
+    let Some(requested) = mapping.volume else {
+        return;
+    };
+    let level = requested.clamp(0.0, 1.0);
+    if level != requested {
+        warn!("Volume {} for hotkey '{}' is outside 0.0-1.0; using {}", requested, mapping.keys, level); // Log warning
+    }
+    match backend.set_volume(&device.id, level) {
+        Ok(()) => info!("Set volume of '{}' to {:.0}%", device.name, level * 100.0), // Log info
+        Err(e) => error!("Failed to set volume of '{}': {}", device.name, e), // Log error
+    }
+}
 
-    // Add Quit menu item
-    // Use the error_sender (renamed quit_sender) for the Quit message
-    let quit_sender = error_sender.clone();
-    tray.add_menu_item("Quit", move || {
-        info!("Quit menu item selected."); // Log info
-        // Send a Quit message to the main loop to initiate shutdown
-        let _ = quit_sender.send(AppMessage::Quit);
-    })
-    .map_err(|e| format!("Failed to add 'Quit' menu item: {}", e))?;
-    info!("'Quit' menu item added."); // Log info
+// Makes the mapping's devices the default for every role (console, multimedia and communications)
+// in one operation per flow, then reads each role back and reports any that didn't take
+fn set_all_roles(
+    mapping: &HotkeyMapping,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+) -> SwitchOutcome {
+    info!(
+        // Log info
+        "Hotkey '{}' pressed, setting all roles to output: '{:?}', input: '{:?}'",
+        mapping.display_name(), mapping.device_name, mapping.input_device_name
+    );
+    let mut outcome = SwitchOutcome::default();
+    let mut failures = Vec::new();
 
-    // 5. Main Event Loop (Handling Tray Events and Messages from hotkey thread)
-    info!("Main thread entering event loop (polling for messages)..."); // Log info
-    loop {
-        // Check for messages from the hotkey thread or quit callback
-        match error_receiver.try_recv() {
-            Ok(AppMessage::HotkeyError(err)) => {
-                // Log the error string. Could potentially show a notification.
-                error!("Error received from hotkey thread: {}", err); // Log error
-                // Decide if the app should quit on certain errors. For now, just log.
+    for flow in config.switch_order.flows() {
+        let (name, result) = match flow {
+            DeviceFlow::Output => match mapping.output_target() {
+                Some(name) => (name, find_and_set_output_device(backend, name, available_output_devices, config)),
+                None => continue,
+            },
+            DeviceFlow::Input => match mapping.input_target() {
+                Some(name) => (name, find_and_set_input_device(backend, name, available_input_devices, config)),
+                None => continue,
+            },
+        };
+        let device = match result {
+            Ok((device, score)) => {
+                outcome.record_score(&device, score);
+                device
             }
-            Ok(AppMessage::Quit) => {
-                info!("Quit message received. Initiating shutdown..."); // Log info
-                break; // Exit the main loop to start shutdown
+            Err(e) => {
+                error!("Failed to set {:?} device '{}' for all roles: {}", flow, name, e); // Log error
+                failures.push(format!("{:?} '{}': {}", flow, name, e));
+                continue;
             }
-            Err(crossbeam_channel::TryRecvError::Empty) => {
-                // No message, continue polling
+        };
+
+        let unconfirmed = unconfirmed_roles(backend, flow, &device);
+        if unconfirmed.is_empty() {
+            info!("Verified '{}' is the {:?} default for all roles", device.name, flow); // Log info
+        } else {
+            warn!("'{}' did not become the {:?} default for roles: {:?}", device.name, flow, unconfirmed); // Log warning
+            failures.push(format!("{:?} '{}': not default for {:?}", flow, device.name, unconfirmed));
+        }
+        match flow {
+            DeviceFlow::Output => {
+                apply_mapping_format(mapping, &device, backend);
+                apply_mapping_volume(mapping, &device, backend);
+                outcome.output = Some(device);
             }
-            Err(crossbeam_channel::TryRecvError::Disconnected) => {
-                error!("Communication channel disconnected unexpectedly. Exiting."); // Log error
-                // Signal shutdown just in case the hotkey thread is still running somehow
-                shutdown_signal.store(true, Ordering::Relaxed);
-                break; // Exit loop
+            DeviceFlow::Input => outcome.input = Some(device),
+        }
+    }
+
+    if !failures.is_empty() {
+        show_notification(
+            "SoundSwitch - Set All Roles",
+            &format!("Some roles could not be set:\n\n{}", failures.join("\n")),
+            true,
+        );
+    }
+    outcome
+}
+
+// The roles of a flow whose default is not the given device after a switch
+fn unconfirmed_roles(backend: &dyn AudioBackend, flow: DeviceFlow, device: &AudioDevice) -> Vec<AudioRole> {
+    AudioRole::ALL
+        .into_iter()
+        .filter(|role| match backend.default_device(flow, *role) {
+            Ok(current) => current.id != device.id,
+            Err(e) => {
+                warn!("Failed to read the {:?} default for role {:?}: {}", flow, role, e); // Log warning
+                true
             }
+        })
+        .collect()
+}
+
+// The entry of a device-names list after the one matching the current default output of `role`,
+// skipping entries that aren't connected. Starts from the first entry if the current default isn't in the list.
+fn next_in_cycle<'a>(
+    label: &str,
+    names: &'a [String],
+    role: AudioRole,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    config: &Config,
+) -> Option<&'a str> {
+    let defaults = RoleDefaults::read(backend, DeviceFlow::Output);
+    if defaults.differ() {
+        let name = |device: &Option<AudioDevice>| device.as_ref().map_or("none", |d| d.name.as_str()).to_string();
+        info!(
+            "Hotkey '{}': output roles differ (console '{}', multimedia '{}', communications '{}'), cycling from the {:?} default",
+            label,
+            name(&defaults.console),
+            name(&defaults.multimedia),
+            name(&defaults.communications),
+            role
+        ); // Log info
+    }
+    let current = defaults.get(role);
+    let current_index = current.and_then(|current| {
+        names.iter().position(|name| {
+            resolve_match(name, available_output_devices, config, false).is_ok_and(|(d, _)| d.id == current.id)
+        })
+    });
+    let start = current_index.map_or(0, |i| (i + 1) % names.len());
+
+    let next_index = (0..names.len())
+        .map(|offset| (start + offset) % names.len())
+        .find(|&i| resolve_match(&names[i], available_output_devices, config, false).is_ok());
+    match next_index {
+        Some(i) => {
+            info!(
+                "Hotkey '{}' cycling output: current index {:?}, chose index {} ('{}')",
+                label, current_index, i, names[i]
+            ); // Log info
+            Some(&names[i])
+        }
+        None => {
+            warn!("Hotkey '{}' pressed, but no device in its device-names list is connected", label); // Log warning
+            None
         }
+    }
+}
 
-        // Add a small sleep to prevent the loop from spinning excessively
-        thread::sleep(Duration::from_millis(100));
+// Container shared by all built-in endpoints; it doesn't identify a single physical device
+const SYSTEM_CONTAINER_ID: &str = "00000000-0000-0000-FFFF-FFFFFFFFFFFF";
 
-        // Check if shutdown was requested via Quit menu
-        // This check is technically redundant now as the Quit match arm breaks the loop,
-        // but keep it for clarity or if other shutdown mechanisms are added.
-        if shutdown_signal.load(Ordering::Relaxed) {
-            warn!("Shutdown signal detected in main loop."); // Log warning (Should not happen if Quit breaks loop)
-            break;
+// The capture endpoint in the same device container as an output (e.g. a headset's microphone).
+// None if the container is unknown, shared by the system, or holds more than one capture endpoint.
+fn paired_input_device<'a>(output: &AudioDevice, available_input_devices: &'a [AudioDevice]) -> Option<&'a AudioDevice> {
+    let container_id = output.container_id.as_deref()?;
+    if container_id.eq_ignore_ascii_case(SYSTEM_CONTAINER_ID) {
+        return None;
+    }
+    let mut candidates = available_input_devices
+        .iter()
+        .filter(|d| d.container_id.as_deref().is_some_and(|id| id.eq_ignore_ascii_case(container_id)));
+    match (candidates.next(), candidates.next()) {
+        (Some(input), None) => Some(input),
+        (Some(_), Some(_)) => {
+            warn!("More than one input device shares the container of '{}'; not pairing", output.name); // Log warning
+            None
         }
+        _ => None,
     }
+}
 
-    // 6. Shutdown Sequence (Restore original logic)
-    info!("Starting shutdown sequence..."); // Log info
+impl SwitchOutcome {
+    // Whether at least one device was switched
+    fn switched_any(&self) -> bool {
+        self.output.is_some() || self.input.is_some() || self.communications.is_some()
+    }
 
-    // Signal the hotkey thread to stop
-    info!("Setting shutdown signal for hotkey thread..."); // Log info
-    shutdown_signal.store(true, Ordering::Relaxed);
+    // Takes the devices a later step switched to, keeping the earlier ones it didn't touch
+    fn merge(&mut self, later: SwitchOutcome) {
+        self.output = later.output.or(self.output.take());
+        self.input = later.input.or(self.input.take());
+        self.communications = later.communications.or(self.communications.take());
+        self.scores.extend(later.scores);
+    }
 
-    // Wait for the hotkey thread to finish
-    info!("Waiting for hotkey thread to join..."); // Log info
-    match hotkey_thread_handle.join() {
-        Ok(_) => info!("Hotkey thread joined successfully."), // Log info
-        Err(e) => error!(
-            "Error joining hotkey thread (it might have panicked): {:?}",
-            e
-        ), // Log error
+    fn record_score(&mut self, device: &AudioDevice, score: f64) {
+        self.scores.insert(device.id.clone(), score);
     }
 
-    info!("SoundSwitch application finished."); // Log info
-    // println!("--- EXITING run_tray_app (Ok) ---"); // Removed debug print
-    Ok(())
+    // Notification body: one line per switched device, prefixed with a role glyph unless disabled
+    fn notification_body(&self, config: &Config) -> Option<String> {
+        let lines: Vec<String> = [
+            ("\u{1F50A}", "Output", &self.output),
+            ("\u{1F4AC}", "Communications", &self.communications),
+            ("\u{1F3A4}", "Input", &self.input),
+        ]
+            .into_iter()
+            .filter_map(|(glyph, role, device)| {
+                let device = device.as_ref()?;
+                let score = self.scores.get(&device.id).map(|score| score_suffix(*score, config)).unwrap_or_default();
+                Some(if config.notification_glyphs {
+                    format!("{} {}{}", glyph, device.name, score)
+                } else {
+                    format!("{}: {}{}", role, device.name, score)
+                })
+            })
+            .collect();
+        if lines.is_empty() { None } else { Some(lines.join("\n")) }
+    }
 }
 
-fn main() {
-    let _logger = WriteLogger::init(
-        LevelFilter::Info,
-        ConfigBuilder::new().build(),
-        File::create("sound_switch.log").unwrap(), // Create log file
-    )
-    .unwrap();
-    // Use run_tray_app instead of run_app
-    if let Err(e) = run_tray_app() {
-        // Using eprintln might not be visible if the console is hidden.
-        // Consider logging to a file or using a message box for errors in release.
-        eprintln!("Application exited with error: {}", e);
-        // For now, just print to stderr, which might go nowhere in release.
-        // A message box could be used here for critical errors.
-        // Example (requires enabling UI features in windows-rs):
-        use windows::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_OK, MessageBoxW};
-        use windows::core::w;
-        unsafe {
-            MessageBoxW(
-                None,
-                w!("Application exited with error."),
-                w!("SoundSwitch Error"),
-                MB_OK | MB_ICONERROR,
-            );
+// Starts the mapping's `launch` program after a successful switch, without waiting for it.
+// A failed launch is only logged: the switch itself already succeeded.
+fn launch_after_switch(mapping: &HotkeyMapping) {
+    let Some(program) = &mapping.launch else {
+        return;
+    };
+    let args = mapping.launch_args.as_deref().unwrap_or_default();
+    match spawn_program(program, args) {
+        Ok(pid) => info!("Hotkey '{}' launched '{}' (PID {})", mapping.keys, program, pid), // Log info
+        Err(e) => error!("Hotkey '{}' failed to launch '{}': {}", mapping.keys, program, e), // Log error
+    }
+}
+
+// Starts a program without a console window and without waiting for it; returns its PID
+fn spawn_program(program: &str, args: &[String]) -> std::io::Result<u32> {
+    const CREATE_NO_WINDOW: u32 = 0x08000000; // Same flag as the PowerShell calls; no console window
+    let child = Command::new(program).args(args).creation_flags(CREATE_NO_WINDOW).spawn()?;
+    Ok(child.id())
+}
+
+// Global sticky mode (sticky-default): every hotkey switch is sticky. Toggled from the tray menu
+static STICKY_DEFAULT: AtomicBool = AtomicBool::new(false);
+// Whether the listener keeps its hotkeys registered. Toggled from the tray menu and kept in state.json
+static HOTKEYS_ENABLED: AtomicBool = AtomicBool::new(true);
+// Set by switches made outside the hotkey listener (tray menu) so the watchdog lets go of the old devices
+static STICKY_RELEASE: AtomicBool = AtomicBool::new(false);
+// Set by the console control handler on Ctrl+C, Ctrl+Break or closing the console; the main loop then shuts down
+static CONSOLE_QUIT: AtomicBool = AtomicBool::new(false);
+
+// Keeps re-applying the devices of the last hotkey switch if something else changes the default.
+// Active after a mapping or profile with `sticky = true` (or any switch with sticky-default) until
+// the next switch.
+#[derive(Default)]
+struct StickyWatchdog {
+    output: Option<AudioDevice>,
+    input: Option<AudioDevice>,
+    pinned: bool, // The switch came from a `sticky = true` mapping or profile
+    last_check: Option<Instant>,
+    last_reassert: Option<Instant>,
+}
+
+impl StickyWatchdog {
+    // Remembers the devices of a hotkey switch; they are enforced if the mapping or the profile it
+    // activates is sticky, or sticky-default is on. Any press without an outcome clears them
+    fn update(&mut self, mapping: Option<&HotkeyMapping>, outcome: Option<&SwitchOutcome>, config: &Config) {
+        // Volume, mute, menu and editor actions don't touch the default device, so they leave sticky mode alone
+        if mapping.is_some_and(|m| {
+            matches!(
+                m.action,
+                Some(
+                    HotkeyAction::VolumeUp
+                        | HotkeyAction::VolumeDown
+                        | HotkeyAction::ToggleMute
+                        | HotkeyAction::ShowMenu
+                        | HotkeyAction::EditConfig
+                )
+            )
+        }) {
+            return;
+        }
+        let was_enforcing = self.is_enforcing();
+        *self = StickyWatchdog::default();
+        if let (Some(mapping), Some(outcome)) = (mapping, outcome) {
+            self.output = outcome.output.clone();
+            self.input = outcome.input.clone();
+            let profile = mapping.activate_profile.as_deref().and_then(|name| config.profile(name));
+            self.pinned = mapping.sticky || profile.is_some_and(|p| p.sticky);
+            self.last_check = Some(Instant::now());
+        }
+        if self.is_enforcing() {
+            info!(
+                "Sticky mode active for output: {:?}, input: {:?}",
+                self.output.as_ref().map(|d| &d.name),
+                self.input.as_ref().map(|d| &d.name)
+            ); // Log info
+        } else if was_enforcing {
+            info!("Sticky mode ended by manual switch."); // Log info
+        }
+    }
+
+    fn is_enforcing(&self) -> bool {
+        self.enforces(STICKY_DEFAULT.load(Ordering::Relaxed))
+    }
+
+    // Whether the devices are enforced with sticky-default on or off
+    fn enforces(&self, sticky_default: bool) -> bool {
+        (self.output.is_some() || self.input.is_some()) && (self.pinned || sticky_default)
+    }
+
+    // Forgets the devices after a tray menu switch, which should not be reverted
+    fn take_release(&mut self) -> bool {
+        if !STICKY_RELEASE.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        if self.is_enforcing() {
+            info!("Sticky mode ended by tray menu switch."); // Log info
+        }
+        *self = StickyWatchdog::default();
+        true
+    }
+
+    // Re-reads the current defaults every `sticky_interval_ms` and re-applies the intended devices.
+    // A fallback for changes that arrive without a notification (see on_default_changed)
+    fn check(&mut self, backend: &dyn AudioBackend, config: &Config) {
+        if self.take_release() || !self.is_enforcing() {
+            return;
+        }
+        let interval = Duration::from_millis(config.sticky_interval_ms);
+        if self.last_check.is_some_and(|last| last.elapsed() < interval) {
+            return;
+        }
+        self.enforce(None, backend, config);
+    }
+
+    // Windows reported a new default for `flow`: re-apply right away instead of at the next check
+    fn on_default_changed(&mut self, flow: DeviceFlow, backend: &dyn AudioBackend, config: &Config) {
+        if self.take_release() || !self.is_enforcing() {
+            return;
+        }
+        self.enforce(Some(flow), backend, config);
+    }
+
+    fn enforce(&mut self, only: Option<DeviceFlow>, backend: &dyn AudioBackend, config: &Config) {
+        self.last_check = Some(Instant::now());
+        // Don't fight an app that keeps switching back: at most one re-assert per cooldown
+        let cooldown = Duration::from_millis(config.sticky_cooldown_ms);
+        if self.last_reassert.is_some_and(|last| last.elapsed() < cooldown) {
+            debug!("Sticky: within the {:?} cooldown, not re-asserting yet", cooldown);
+            return;
+        }
+
+        let mut reasserted = false;
+        if only != Some(DeviceFlow::Input)
+            && let Some(intended) = &self.output
+        {
+            reasserted |= reassert_default(DeviceFlow::Output, intended, backend, |id| backend.set_default_output_device(id));
+        }
+        if only != Some(DeviceFlow::Output)
+            && let Some(intended) = &self.input
+        {
+            reasserted |= reassert_default(DeviceFlow::Input, intended, backend, |id| backend.set_default_input_device(id));
+        }
+        if reasserted {
+            self.last_reassert = Some(Instant::now());
+        }
+    }
+}
+
+// Re-applies the intended device if the current default of that flow differs from it.
+// Returns whether it tried to.
+fn reassert_default(
+    flow: DeviceFlow,
+    intended: &AudioDevice,
+    backend: &dyn AudioBackend,
+    set_default: impl Fn(&str) -> Result<(), Box<dyn Error>>,
+) -> bool {
+    let current = match backend.default_device(flow, AudioRole::Console) {
+        Ok(device) => device,
+        Err(e) => {
+            warn!("Sticky: could not read the current default {:?} device: {}", flow, e); // Log warning
+            return false;
+        }
+    };
+    if current.id == intended.id {
+        return false;
+    }
+    info!(
+        "Sticky: default {:?} device changed to '{}', re-asserting '{}'",
+        flow, current.name, intended.name
+    ); // Log info
+    if let Err(e) = set_default(&intended.id) {
+        error!("Sticky: failed to re-assert '{}': {}", intended.name, e); // Log error
+    }
+    true
+}
+
+// Parts of a device name that tend to change between reboots or driver updates: Windows' "2- "
+// endpoint numbering and driver versions in parentheses. Returns a description of each kind found.
+fn dynamic_name_hints(name: &str) -> Vec<&'static str> {
+    let mut hints = Vec::new();
+
+    // "2- USB Audio" or "Speakers (2- USB Audio)"
+    let has_numbered_prefix = std::iter::once(name)
+        .chain(name.match_indices('(').map(|(i, _)| &name[i + 1..]))
+        .any(|part| {
+            let digits = part.chars().take_while(char::is_ascii_digit).count();
+            digits > 0 && part[digits..].starts_with("- ")
+        });
+    if has_numbered_prefix {
+        hints.push("a numbered prefix like '2- '");
+    }
+
+    // "(Driver 6.0.1.8)" - a dotted number inside parentheses
+    let has_version = name
+        .split('(')
+        .skip(1)
+        .map(|group| group.split(')').next().unwrap_or(group))
+        .flat_map(str::split_whitespace)
+        .any(|token| {
+            let token = token.trim_start_matches(['v', 'V']);
+            token.contains('.')
+                && token
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        });
+    if has_version {
+        hints.push("a version number in parentheses");
+    }
+
+    hints
+}
+
+// Startup nudge: exact matching against names with volatile parts breaks after driver updates
+fn warn_about_dynamic_names(config: &Config) {
+    if config.fuzzy_match || !config.warn_dynamic_names {
+        return;
+    }
+    let mut warnings = Vec::new();
+    for mapping in &config.hotkeys {
+        let names = [&mapping.device_name, &mapping.input_device_name, &mapping.comms_device_name];
+        let cycled = mapping.device_names.iter().flatten();
+        for name in names.into_iter().flatten().chain(cycled) {
+            // Parenthesized parts don't matter if they are stripped before matching
+            let compared = normalize_for_matching(name, config);
+            let hints = dynamic_name_hints(&compared);
+            if !hints.is_empty() {
+                warn!(
+                    "Device name '{}' (hotkey: {}) contains {}; exact matching may break when it changes",
+                    name,
+                    mapping.keys,
+                    hints.join(" and ")
+                ); // Log warning
+                warnings.push(format!("  • {} ({})", name, hints.join(", ")));
+            }
+        }
+    }
+    if warnings.is_empty() {
+        return;
+    }
+    show_notification(
+        "SoundSwitch - Device Names May Change",
+        &format!(
+            "Exact matching is on, but these configured names contain parts Windows may change \
+            after a reboot or driver update:\n\n{}\n\n\
+            Consider fuzzy-match = true or ignore-parentheticals = true, or remove the changing parts.\n\
+            Set warn-dynamic-names = false to hide this message.",
+            warnings.join("\n")
+        ),
+        false,
+    );
+}
+
+// Turns a failed match into an error message, notifying the user when an ambiguous match was refused
+fn match_failure(device_kind: &str, target_device_name: &str, error: MatchError, config: &Config) -> Box<dyn Error> {
+    match error {
+        MatchError::NoMatch => format!(
+            "No {} found for {} device '{}'",
+            match_mode_label(config),
+            device_kind,
+            target_device_name
+        )
+        .into(),
+        MatchError::Ambiguous(tied) => {
+            let names: Vec<String> = tied.iter().map(|d| format!("'{}'", d.name)).collect();
+            let message = format!(
+                "Ambiguous {} for {} device '{}' between {}. No device was switched; make the name more specific.",
+                match_mode_label(config),
+                device_kind,
+                target_device_name,
+                names.join(" and ")
+            );
+            show_notification("SoundSwitch - Ambiguous Device Match", &message, true);
+            message.into()
+        }
+        MatchError::DuplicateExact(duplicates) => {
+            let ids: Vec<String> = duplicates.iter().map(|d| format!("  {}", d.id)).collect();
+            let message = format!(
+                "{} {} devices are named '{}'. No device was switched.\n\nCandidate IDs:\n{}\n\nRename one of the devices in Windows Sound settings, or set exact-first-on-ambiguous = true to use the first one.",
+                duplicates.len(),
+                device_kind,
+                target_device_name,
+                ids.join("\n")
+            );
+            show_notification("SoundSwitch - Duplicate Device Names", &message, true);
+            message.into()
+        }
+    }
+}
+
+// Helper function to find and set the audio output device; returns it with its match score
+fn find_and_set_output_device(
+    backend: &dyn AudioBackend,
+    target_device_name: &str,
+    available_devices: &[AudioDevice],
+    config: &Config,
+) -> Result<(AudioDevice, f64), Box<dyn Error>> {
+    match resolve_match(target_device_name, available_devices, config, false) {
+        Ok((device, score)) => {
+            backend.set_default_output_device(&device.id)?;
+            Ok((device.clone(), score))
+        }
+        Err(e) => Err(match_failure("output", target_device_name, e, config)),
+    }
+}
+
+// Helper function to find an output device and set it as default for a single role
+// (Console also covers Multimedia, see set_default_device_for_role)
+fn find_and_set_output_role(
+    backend: &dyn AudioBackend,
+    target_device_name: &str,
+    available_devices: &[AudioDevice],
+    config: &Config,
+    role: AudioRole,
+) -> Result<(AudioDevice, f64), Box<dyn Error>> {
+    match resolve_match(target_device_name, available_devices, config, false) {
+        Ok((device, score)) => {
+            backend.set_default_device_for_role(&device.id, role)?;
+            Ok((device.clone(), score))
+        }
+        Err(e) => Err(match_failure("output", target_device_name, e, config)),
+    }
+}
+
+// Helper function to find and set the audio input device
+fn find_and_set_input_device(
+    backend: &dyn AudioBackend,
+    target_device_name: &str,
+    available_devices: &[AudioDevice],
+    config: &Config,
+) -> Result<(AudioDevice, f64), Box<dyn Error>> {
+    match resolve_match(target_device_name, available_devices, config, config.prefer_communications_input) {
+        Ok((device, score)) => {
+            backend.set_default_input_device(&device.id)?;
+            Ok((device.clone(), score))
+        }
+        Err(e) => Err(match_failure("input", target_device_name, e, config)),
+    }
+}
+
+// Helper function to find an input device and set it as default for a single role
+fn find_and_set_input_role(
+    backend: &dyn AudioBackend,
+    target_device_name: &str,
+    available_devices: &[AudioDevice],
+    config: &Config,
+    role: AudioRole,
+) -> Result<(AudioDevice, f64), Box<dyn Error>> {
+    match resolve_match(target_device_name, available_devices, config, config.prefer_communications_input) {
+        Ok((device, score)) => {
+            backend.set_default_device_for_role(&device.id, role)?;
+            Ok((device.clone(), score))
+        }
+        Err(e) => Err(match_failure("input", target_device_name, e, config)),
+    }
+}
+
+// Runs a control pipe command with the same matching and switching as the hotkeys and
+// returns its result line: "OK ..." on success, "ERR ..." otherwise
+fn run_ipc_command(command: &IpcCommand, config: &Config) -> String {
+    let backend = SystemBackend;
+    let result = match command {
+        IpcCommand::SetOutput(target) => {
+            STICKY_RELEASE.store(true, Ordering::Relaxed);
+            backend
+                .list_output_devices()
+                .and_then(|outputs| find_and_set_output_device(&backend, target, &outputs, config))
+                .map(|(device, score)| format!("output set to '{}'{}", device.name, score_suffix(score, config)))
+        }
+        IpcCommand::SetInput(target) => {
+            STICKY_RELEASE.store(true, Ordering::Relaxed);
+            backend
+                .list_input_devices()
+                .and_then(|inputs| find_and_set_input_device(&backend, target, &inputs, config))
+                .map(|(device, score)| format!("input set to '{}'{}", device.name, score_suffix(score, config)))
+        }
+        IpcCommand::ToggleMute => toggle_output_mute()
+            .map(|(name, muted)| format!("'{}' {}", name, if muted { "muted" } else { "unmuted" }))
+            .map_err(Into::into),
+        IpcCommand::List => backend
+            .list_output_devices()
+            .and_then(|outputs| Ok((outputs, backend.list_input_devices()?)))
+            .map(|(outputs, inputs)| format_ipc_device_list(&outputs, &inputs)),
+    };
+    if result.is_ok() && matches!(command, IpcCommand::SetOutput(_) | IpcCommand::SetInput(_)) {
+        record_last_used(&backend);
+    }
+    match result {
+        Ok(message) => format!("OK {}", message),
+        // Keep the reply on one line
+        Err(e) => format!("ERR {}", e.to_string().replace(['\r', '\n'], " ")),
+    }
+}
+
+// One-line device list for the control pipe's `list` command
+fn format_ipc_device_list(outputs: &[AudioDevice], inputs: &[AudioDevice]) -> String {
+    let names = |devices: &[AudioDevice]| devices.iter().map(|d| d.name.as_str()).collect::<Vec<_>>().join(" | ");
+    format!("outputs: {}; inputs: {}", names(outputs), names(inputs))
+}
+
+// Function to validate that configured devices exist on the system
+fn validate_configured_devices(config: &Config) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>) {
+    let mut missing_output_devices = Vec::new();
+    let mut missing_input_devices = Vec::new();
+
+    // Get available devices
+    let available_output_devices = match list_output_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            error!("Failed to list output devices during validation: {}", e);
+            return (missing_output_devices, missing_input_devices, Vec::new(), Vec::new());
+        }
+    };
+
+    let available_input_devices = match list_input_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            error!("Failed to list input devices during validation: {}", e);
+            return (missing_output_devices, missing_input_devices, Vec::new(), Vec::new());
+        }
+    };
+
+    // Unplugged and disabled devices, to tell "not found" apart from "not active right now"
+    let inactive_output_devices = inactive_devices(DeviceFlow::Output);
+    let inactive_input_devices = inactive_devices(DeviceFlow::Input);
+
+    // Create lists of available device names for the notification
+    let available_output_names: Vec<String> = available_output_devices.iter().map(|d| d.name.clone()).collect();
+    let available_input_names: Vec<String> = available_input_devices.iter().map(|d| d.name.clone()).collect();
+
+    // Check each configured hotkey mapping
+    for mapping in &config.hotkeys {
+        // Check output device(s) using the unified matching logic
+        let output_names = mapping
+            .output_target()
+            .into_iter()
+            .chain(mapping.device_names.iter().flatten().map(String::as_str))
+            .chain(mapping.step_devices(DeviceFlow::Output));
+        // With fallbacks, only a list none of whose entries is connected is reported (as its primary)
+        let has_available_fallback = mapping.fallback_devices.is_some()
+            && mapping.output_priority().any(|name| find_best_match(name, &available_output_devices, config).is_some());
+        for device_name in output_names {
+            if !has_available_fallback && find_best_match(device_name, &available_output_devices, config).is_none() {
+                let context = format!("hotkey: {}", mapping.keys);
+                report_missing_device("Output", device_name, &context, &inactive_output_devices, config, &mut missing_output_devices);
+            }
+        }
+
+        // Check communications output device if specified
+        if let Some(comms_device_name) = &mapping.comms_device_name
+            && find_best_match(comms_device_name, &available_output_devices, config).is_none()
+        {
+            let context = format!("communications, hotkey: {}", mapping.keys);
+            report_missing_device("Output", comms_device_name, &context, &inactive_output_devices, config, &mut missing_output_devices);
+        }
+
+        // Check input device(s) if specified
+        for input_device_name in mapping.input_target().into_iter().chain(mapping.step_devices(DeviceFlow::Input)) {
+            if find_best_input_match(input_device_name, &available_input_devices, config).is_none() {
+                let context = format!("hotkey: {}", mapping.keys);
+                report_missing_device("Input", input_device_name, &context, &inactive_input_devices, config, &mut missing_input_devices);
+            }
+        }
+    }
+
+    // Check the devices of each profile
+    for profile in &config.profiles {
+        if let Some(device_name) = &profile.device_name
+            && find_best_match(device_name, &available_output_devices, config).is_none()
+        {
+            let context = format!("profile: {}", profile.name);
+            report_missing_device("Output", device_name, &context, &inactive_output_devices, config, &mut missing_output_devices);
+        }
+        if let Some(device_name) = &profile.input_device_name
+            && find_best_input_match(device_name, &available_input_devices, config).is_none()
+        {
+            let context = format!("profile: {}", profile.name);
+            report_missing_device("Input", device_name, &context, &inactive_input_devices, config, &mut missing_input_devices);
+        }
+    }
+
+    (missing_output_devices, missing_input_devices, available_output_names, available_input_names)
+}
+
+// How often the startup wait re-enumerates the devices
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Re-enumerates the devices until every configured device is present and the device count
+// didn't change since the previous poll, or `startup-delay-ms` has passed. Returns how long it
+// waited.
+fn wait_for_devices(backend: &dyn AudioBackend, config: &Config, poll_interval: Duration) -> Duration {
+    let started = Instant::now();
+    let timeout = Duration::from_millis(config.startup_delay_ms);
+    let mut previous_count = None;
+    loop {
+        let outputs = backend.list_output_devices().unwrap_or_default();
+        let inputs = backend.list_input_devices().unwrap_or_default();
+        let count = outputs.len() + inputs.len();
+        let settled = previous_count == Some(count) && configured_devices_present(config, &outputs, &inputs);
+        if settled || started.elapsed() >= timeout {
+            if !settled {
+                info!("Stopped waiting for audio devices after {} ms; {} device(s) present", timeout.as_millis(), count); // Log info
+            }
+            return started.elapsed();
+        }
+        debug!("Waiting for audio devices at startup: {} present", count);
+        previous_count = Some(count);
+        thread::sleep(poll_interval);
+    }
+}
+
+// Whether every hotkey's output (or one of its fallbacks), cycle entries and input resolve
+// against the given devices
+fn configured_devices_present(config: &Config, outputs: &[AudioDevice], inputs: &[AudioDevice]) -> bool {
+    config.hotkeys.iter().all(|mapping| {
+        let output_ok = mapping.output_target().is_none()
+            || mapping.output_priority().any(|name| find_best_match(name, outputs, config).is_some());
+        let cycle_ok = mapping.device_names.iter().flatten().all(|name| find_best_match(name, outputs, config).is_some());
+        let input_ok = mapping.input_target().is_none_or(|name| find_best_input_match(name, inputs, config).is_some());
+        output_ok && cycle_ok && input_ok
+    })
+}
+
+// Unplugged and disabled devices of one flow; empty if they can't be listed
+fn inactive_devices(flow: DeviceFlow) -> Vec<AudioDevice> {
+    match list_devices_including_inactive(flow) {
+        Ok(devices) => devices.into_iter().filter(|d| d.state != DeviceState::Active).collect(),
+        Err(e) => {
+            warn!("Failed to list inactive {:?} devices during validation: {}", flow, e); // Log warning
+            Vec::new()
+        }
+    }
+}
+
+// Records a configured device that isn't active. A device that exists but is unplugged or
+// disabled is reported as such, so it isn't mistaken for a typo in the config.
+fn report_missing_device(
+    kind: &str,
+    name: &str,
+    context: &str,
+    inactive_devices: &[AudioDevice],
+    config: &Config,
+    missing: &mut Vec<String>,
+) {
+    match find_best_match(name, inactive_devices, config) {
+        Some((device, _)) => {
+            let entry = format!("{} ({}) - exists but is {}", name, context, device.state);
+            warn!("{} device exists but is {}: {} ({})", kind, device.state, name, context); // Log warning
+            missing.push(entry);
+        }
+        None => {
+            let entry = format!("{} ({})", name, context);
+            warn!("{} device not found: {}", kind, entry); // Log warning
+            missing.push(entry);
+        }
+    }
+}
+
+// Function to show a Windows notification for missing devices
+fn show_missing_devices_notification(
+    missing_output: &[String], 
+    missing_input: &[String], 
+    available_output: &[String], 
+    available_input: &[String],
+    notice: MissingDevicesNotice,
+) {
+    if missing_output.is_empty() && missing_input.is_empty() {
+        return; // Nothing to show
+    }
+    match notice {
+        MissingDevicesNotice::Modal => {}
+        MissingDevicesNotice::Toast => {
+            show_toast("SoundSwitch - Missing Audio Devices", &missing_devices_summary(missing_output, missing_input));
+            return;
+        }
+        MissingDevicesNotice::Off => return, // Already logged by the caller
+    }
+
+    let mut message = String::from("SoundSwitch has started but some configured devices were not found:\n\n");
+
+    if !missing_output.is_empty() {
+        message.push_str(&format!("Missing Output Device{} ({}):\n", 
+            if missing_output.len() > 1 { "s" } else { "" },
+            missing_output.len()));
+        for device in missing_output {
+            message.push_str(&format!("  • {}\n", device));
+        }
+        message.push('\n');
+    }
+
+    if !missing_input.is_empty() {
+        message.push_str(&format!("Missing Input Device{} ({}):\n", 
+            if missing_input.len() > 1 { "s" } else { "" },
+            missing_input.len()));
+        for device in missing_input {
+            message.push_str(&format!("  • {}\n", device));
+        }
+        message.push('\n');
+    }
+
+    message.push_str("The application will continue to run, but these hotkeys will not work until the devices are available.\n\n");
+
+    // Add available devices list to help with configuration
+    if !missing_output.is_empty() && !available_output.is_empty() {
+        message.push_str(&format!("Available Output Devices ({}):\n", available_output.len()));
+        for device in available_output {
+            message.push_str(&format!("  • {}\n", device));
+        }
+        message.push('\n');
+    }
+
+    if !missing_input.is_empty() && !available_input.is_empty() {
+        message.push_str(&format!("Available Input Devices ({}):\n", available_input.len()));
+        for device in available_input {
+            message.push_str(&format!("  • {}\n", device));
+        }
+        message.push('\n');
+    }
+
+    message.push_str("Possible solutions:\n");
+    message.push_str("• Check that the devices are connected and enabled in Windows Sound settings\n");
+    message.push_str("• Verify the device names in your config.toml file match the available devices above\n");
+    message.push_str("• Consider enabling fuzzy matching in your configuration");
+
+    // Show Windows MessageBox
+    use windows::Win32::UI::WindowsAndMessaging::{MB_ICONWARNING, MB_OK, MessageBoxW};
+    use windows::core::HSTRING;
+
+    let title = HSTRING::from("SoundSwitch - Missing Audio Devices");
+    let content = HSTRING::from(message);
+
+    unsafe {
+        MessageBoxW(
+            None,
+            &content,
+            &title,
+            MB_OK | MB_ICONWARNING,
+        );
+    }
+}
+
+// Short form of the missing devices report for a toast, which only shows a few lines
+fn missing_devices_summary(missing_output: &[String], missing_input: &[String]) -> String {
+    let mut lines = Vec::new();
+    if !missing_output.is_empty() {
+        lines.push(format!("Output: {}", missing_output.join(", ")));
+    }
+    if !missing_input.is_empty() {
+        lines.push(format!("Input: {}", missing_input.join(", ")));
+    }
+    if lines.len() < 2 {
+        lines.push("Hotkeys using them won't work until they're connected.".to_string());
+    }
+    lines.join("\n")
+}
+
+// Lists each hotkey that couldn't be registered with the reason, one per line
+fn format_hotkey_failures(failures: &[(String, String)]) -> String {
+    let lines: Vec<String> = failures
+        .iter()
+        .map(|(keys, error)| format!("'{}': {}", keys, error))
+        .collect();
+    format!(
+        "{} hotkey(s) could not be registered and will not work:\n{}",
+        failures.len(),
+        lines.join("\n")
+    )
+}
+
+// Shows a message box on a separate thread so the caller (e.g. the main event loop) is not blocked
+fn show_notification(title: &str, message: &str, is_error: bool) {
+    use windows::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_ICONINFORMATION, MB_OK, MessageBoxW};
+    use windows::core::HSTRING;
+
+    let title = HSTRING::from(title);
+    let content = HSTRING::from(message);
+    let icon = if is_error { MB_ICONERROR } else { MB_ICONINFORMATION };
+
+    thread::spawn(move || unsafe {
+        MessageBoxW(None, &content, &title, MB_OK | icon);
+    });
+}
+
+// Opens the config file with the program associated with its extension, or Notepad if there is
+// none. If there is no config file (none was found, or it was deleted since), opens the folder
+// it belongs in instead. Failures are logged and shown.
+fn open_config_in_editor(config_path: Option<&Path>) {
+    use windows::Win32::UI::Shell::ShellExecuteW;
+    use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+    use windows::core::{HSTRING, w};
+
+    let target = match config_path {
+        Some(path) if path.is_file() => path.to_path_buf(),
+        Some(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        None => match std::env::current_exe() {
+            Ok(exe) => exe.parent().map(Path::to_path_buf).unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to locate the config folder: {}", e); // Log error
+                return;
+            }
+        },
+    };
+    info!("Opening {}", target.display()); // Log info
+
+    // Values above 32 mean success; anything else is an SE_ERR_* code (e.g. no associated program)
+    let result = unsafe { ShellExecuteW(None, w!("open"), &HSTRING::from(target.as_os_str()), None, None, SW_SHOWNORMAL) };
+    if result.0 as usize > 32 {
+        return;
+    }
+    warn!("No program is associated with {} (code {}); using Notepad", target.display(), result.0 as usize); // Log warning
+    if let Err(e) = Command::new("notepad.exe").arg(&target).spawn() {
+        error!("Failed to open {}: {}", target.display(), e); // Log error
+        show_notification("SoundSwitch - Edit Config", &format!("Failed to open {}: {}", target.display(), e), true);
+    }
+}
+
+// "SoundSwitch", followed by the hotkey's label (or keys) when a hotkey caused the notification
+fn notification_title(label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("SoundSwitch - {}", label),
+        None => "SoundSwitch".to_string(),
+    }
+}
+
+// Whether the current local time falls in the configured quiet hours
+fn is_quiet_now(config: &Config) -> bool {
+    is_quiet_at(config, local_minute_of_day())
+}
+
+fn is_quiet_at(config: &Config, minute_of_day: u32) -> bool {
+    config.quiet_hours.is_some_and(|quiet| quiet.contains(minute_of_day))
+}
+
+// Confirms a switch with the configured sound and, if enabled, a toast listing the new devices.
+// An outcome without any switched device plays the failure sound instead. During quiet hours
+// neither sound nor toast is given.
+fn announce_switch(outcome: &SwitchOutcome, label: Option<&str>, config: &Config) {
+    if is_quiet_now(config) {
+        debug!("Quiet hours: no sound or toast for this switch");
+        return;
+    }
+    let Some(body) = outcome.notification_body(config) else {
+        play_switch_sound(&config.failure_sound, true);
+        return;
+    };
+    play_switch_sound(&config.switch_sound, false);
+    if config.show_switch_notifications {
+        show_toast(&notification_title(label), &body);
+    }
+}
+
+// Both kinds of sound play asynchronously, so the listener is not held up
+fn play_switch_sound(sound: &SwitchSound, failure: bool) {
+    use windows::Win32::Media::Audio::{PlaySoundW, SND_ASYNC, SND_FILENAME, SND_NODEFAULT};
+    use windows::Win32::System::Diagnostics::Debug::MessageBeep;
+    use windows::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_OK};
+    use windows::core::HSTRING;
+
+    match sound {
+        SwitchSound::None => {}
+        SwitchSound::Beep => {
+            let kind = if failure { MB_ICONERROR } else { MB_OK };
+            if let Err(e) = unsafe { MessageBeep(kind) } {
+                warn!("Failed to play switch beep: {}", e); // Log warning
+            }
+        }
+        SwitchSound::File(path) => {
+            let played = unsafe {
+                PlaySoundW(&HSTRING::from(path.as_os_str()), None, SND_ASYNC | SND_FILENAME | SND_NODEFAULT)
+            };
+            if !played.as_bool() {
+                warn!("Failed to play switch sound {}", path.display()); // Log warning
+            }
+        }
+    }
+}
+
+// AppUserModelID toasts are shown under. Unpackaged apps need a registered Start menu shortcut to
+// use their own ID, so borrow Windows PowerShell's, which is present on every install
+const TOAST_APP_ID: &str = "{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\\WindowsPowerShell\\v1.0\\powershell.exe";
+
+// Shows a toast on a separate thread so the hotkey listener is not blocked. Toasts are best
+// effort: a failure (e.g. notifications disabled by policy) is only logged
+fn show_toast(title: &str, message: &str) {
+    let title = escape_xml(title);
+    let lines: String = message.lines().map(|line| format!("<text>{}</text>", escape_xml(line))).collect();
+    let xml = format!(
+        "<toast><visual><binding template=\"ToastGeneric\"><text>{}</text>{}</binding></visual></toast>",
+        title, lines
+    );
+
+    thread::spawn(move || {
+        if let Err(e) = show_toast_xml(&xml) {
+            warn!("Failed to show toast notification: {}", e); // Log warning
+        }
+    });
+}
+
+fn show_toast_xml(xml: &str) -> windows::core::Result<()> {
+    use windows::Data::Xml::Dom::XmlDocument;
+    use windows::UI::Notifications::{ToastNotification, ToastNotificationManager};
+    use windows::core::HSTRING;
+
+    let _com = ComGuard::new()?;
+    let document = XmlDocument::new()?;
+    document.LoadXml(&HSTRING::from(xml))?;
+    let toast = ToastNotification::CreateToastNotification(&document)?;
+    ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(TOAST_APP_ID))?.Show(&toast)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Shared reload path: re-parses the config file and, on success, restarts the hotkey
+// listener with the new configuration. On failure the running listener is left untouched.
+fn reload_config(
+    config: &mut Config,
+    listener: &mut HotkeyListener,
+    error_sender: &crossbeam_channel::Sender<AppMessage>,
+    dedup_logger: &DedupLogger,
+) {
+    info!("Reloading configuration..."); // Log info
+    match load_config() {
+        Ok(new_config) => {
+            let hotkey_count = new_config.hotkeys.len();
+            let (missing_output, missing_input, _, _) = validate_configured_devices(&new_config);
+            if !missing_output.is_empty() || !missing_input.is_empty() {
+                warn!(
+                    "Missing devices after reload - Output: {:?}, Input: {:?}",
+                    missing_output, missing_input
+                ); // Log warning
+            }
+
+            listener.stop();
+            *listener = HotkeyListener::spawn(new_config.clone(), error_sender.clone());
+            // A reload resets a sticky-default toggled from the tray to the configured value
+            STICKY_DEFAULT.store(new_config.sticky_default, Ordering::Relaxed);
+            dedup_logger.set_window(new_config.log_dedup_window_ms);
+            *config = new_config;
+
+            info!("Configuration reloaded with {} hotkeys.", hotkey_count); // Log info
+            show_notification(
+                "SoundSwitch - Configuration Reloaded",
+                &format!("Configuration reloaded successfully.\n\n{} hotkey(s) configured.", hotkey_count),
+                false,
+            );
+        }
+        Err(e) => {
+            error!("Failed to reload configuration: {}", e); // Log error
+            show_notification(
+                "SoundSwitch - Configuration Error",
+                &format!("Failed to reload configuration. The previous configuration is still active.\n\n{}", e),
+                true,
+            );
+        }
+    }
+}
+
+// Sends a message from a tray callback to the main loop
+fn send_tray_message(sender: &crossbeam_channel::Sender<AppMessage>, message: AppMessage) {
+    // Only fails once the main loop has exited, when nobody needs the message anymore
+    let _ = sender.send(message);
+}
+
+// Opens the tray context menu at the cursor, as if the icon had been right-clicked.
+// tray-item doesn't expose its window, so it is looked up by class and title among this process's windows.
+fn show_tray_menu() -> Result<(), Box<dyn Error>> {
+    use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+    use windows::Win32::UI::WindowsAndMessaging::{FindWindowExW, GetWindowThreadProcessId, PostMessageW, WM_RBUTTONUP, WM_USER};
+    use windows::core::w;
+
+    let mut previous: Option<HWND> = None;
+    loop {
+        let hwnd = unsafe { FindWindowExW(None, previous, w!("my_window"), w!("rust_systray_window")) }
+            .map_err(|_| "Tray window not found")?;
+        let mut process_id = 0;
+        unsafe { GetWindowThreadProcessId(hwnd, Some(&mut process_id)) };
+        if process_id == std::process::id() {
+            // The tray's window procedure pops up the menu on its notification message (WM_USER + 1)
+            unsafe { PostMessageW(Some(hwnd), WM_USER + 1, WPARAM(0), LPARAM(WM_RBUTTONUP as isize)) }?;
+            return Ok(());
+        }
+        previous = Some(hwnd);
+    }
+}
+
+// How long device changes must settle before the tray menu is rebuilt
+const TRAY_REBUILD_DELAY: Duration = Duration::from_millis(500);
+
+// Prefix of the output device that is currently the default in the tray menu
+const DEFAULT_DEVICE_MARKER: &str = "\u{2713} ";
+
+// Longest device name shown in the tray tooltip; Windows cuts tooltips off at 127 characters
+const TOOLTIP_NAME_MAX_CHARS: usize = 40;
+
+// Shortens a device name to at most `max_chars` characters, ending it with "..." if cut
+fn truncate_device_name(name: &str, max_chars: usize) -> String {
+    if name.chars().count() <= max_chars {
+        return name.to_string();
+    }
+    let kept: String = name.chars().take(max_chars.saturating_sub(3)).collect();
+    format!("{}...", kept.trim_end())
+}
+
+// The tray tooltip for the given default devices, e.g. "Out: Speakers | In: Blue Yeti"
+fn tray_tooltip(output: Option<&str>, input: Option<&str>) -> String {
+    let name = |device: Option<&str>| {
+        device.map_or("none".to_string(), |n| truncate_device_name(n, TOOLTIP_NAME_MAX_CHARS))
+    };
+    format!("Out: {} | In: {}", name(output), name(input))
+}
+
+// Which tray icon the default output device gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IconCategory {
+    Headphones,
+    Speakers,
+    Default,
+}
+
+// Classifies by form factor. Only an unknown form factor falls back to words in the name, and
+// anything else (e.g. a monitor or S/PDIF output) gets the default icon.
+fn icon_category(device: &AudioDevice) -> IconCategory {
+    match device.form_factor {
+        FormFactor::Headphones | FormFactor::Headset | FormFactor::Handset => IconCategory::Headphones,
+        FormFactor::Speakers => IconCategory::Speakers,
+        FormFactor::Unknown => {
+            let name = device.name.to_lowercase();
+            if ["headphone", "headset", "earphone", "earbud"].iter().any(|word| name.contains(word)) {
+                IconCategory::Headphones
+            } else if name.contains("speaker") {
+                IconCategory::Speakers
+            } else {
+                IconCategory::Default
+            }
+        }
+        _ => IconCategory::Default,
+    }
+}
+
+// Resolves a [tray-icons] value: a built-in icon resource, else an .ico file. Falls back to the
+// default icon (with a warning) if the file can't be loaded.
+fn load_tray_icon(icon: &str) -> tray_item::IconSource {
+    use windows::Win32::UI::WindowsAndMessaging::{IMAGE_ICON, LR_DEFAULTSIZE, LR_LOADFROMFILE, LoadImageW};
+    use windows::core::HSTRING;
+
+    for resource in ["default-icon", "headphones-icon", "speakers-icon"] {
+        if icon.eq_ignore_ascii_case(resource) {
+            return tray_item::IconSource::Resource(resource);
+        }
+    }
+    match unsafe { LoadImageW(None, &HSTRING::from(icon), IMAGE_ICON, 0, 0, LR_LOADFROMFILE | LR_DEFAULTSIZE) } {
+        Ok(handle) => tray_item::IconSource::RawIcon(handle.0 as _),
+        Err(e) => {
+            warn!("Failed to load tray icon '{}', using the default icon: {}", icon, e); // Log warning
+            tray_item::IconSource::Resource("default-icon")
+        }
+    }
+}
+
+// The tray icon plus its output device items, so the default marker can be moved in place
+struct TrayMenu {
+    tray: TrayItem,
+    device_items: Vec<(u32, AudioDevice)>, // Menu item ID and device, in menu order
+    sticky_item: u32,
+    enabled_item: u32,
+    icons: Vec<(IconCategory, tray_item::IconSource)>, // Loaded once from [tray-icons]
+    shown_icon: Option<IconCategory>,
+}
+
+impl TrayMenu {
+    // Marks the current default output device and unmarks all others, and shows its icon
+    fn mark_default(&mut self) {
+        let default = get_default_output_device(AudioRole::Console).ok();
+        let default_id = default.as_ref().map(|d| d.id.as_str());
+        for (i, (item_id, device)) in self.device_items.iter().enumerate() {
+            let marker = if default_id == Some(device.id.as_str()) { DEFAULT_DEVICE_MARKER } else { "" };
+            let label = format!("{}{}. {}", marker, i + 1, device.name);
+            if let Err(e) = self.tray.inner_mut().set_menu_item_label(&label, *item_id) {
+                warn!("Failed to update tray menu item for '{}': {}", device.name, e); // Log warning
+            }
+        }
+        self.show_icon(default.as_ref().map_or(IconCategory::Default, icon_category));
+        self.update_tooltip(default.as_ref());
+    }
+
+    // Shows the default output (as passed in) and the current default input in the tooltip
+    fn update_tooltip(&mut self, output: Option<&AudioDevice>) {
+        let input = get_default_input_device(AudioRole::Console).ok();
+        let tooltip = tray_tooltip(output.map(|d| d.name.as_str()), input.as_ref().map(|d| d.name.as_str()));
+        if let Err(e) = self.tray.inner_mut().set_tooltip(&tooltip) {
+            warn!("Failed to update the tray tooltip: {}", e); // Log warning
+        }
+    }
+
+    // Switches the tray icon, unless it already shows this category
+    fn show_icon(&mut self, category: IconCategory) {
+        if self.shown_icon == Some(category) {
+            return;
+        }
+        let Some((_, icon)) = self.icons.iter().find(|(c, _)| *c == category) else {
+            return;
+        };
+        match self.tray.set_icon(icon.clone()) {
+            Ok(()) => self.shown_icon = Some(category),
+            Err(e) => warn!("Failed to set the {:?} tray icon: {}", category, e), // Log warning
+        }
+    }
+
+    // Checks the sticky-default item while it is on
+    fn mark_sticky(&mut self) {
+        let marker = if STICKY_DEFAULT.load(Ordering::Relaxed) { DEFAULT_DEVICE_MARKER } else { "" };
+        let label = format!("{}{}", marker, STICKY_MENU_LABEL);
+        if let Err(e) = self.tray.inner_mut().set_menu_item_label(&label, self.sticky_item) {
+            warn!("Failed to update the sticky tray menu item: {}", e); // Log warning
+        }
+    }
+
+    // Checks the "Enabled" item while hotkeys are enabled
+    fn mark_enabled(&mut self) {
+        let marker = if HOTKEYS_ENABLED.load(Ordering::Relaxed) { DEFAULT_DEVICE_MARKER } else { "" };
+        let label = format!("{}{}", marker, ENABLED_MENU_LABEL);
+        if let Err(e) = self.tray.inner_mut().set_menu_item_label(&label, self.enabled_item) {
+            warn!("Failed to update the enabled tray menu item: {}", e); // Log warning
+        }
+    }
+}
+
+const STICKY_MENU_LABEL: &str = "Keep default devices (sticky)";
+const ENABLED_MENU_LABEL: &str = "Enabled";
+
+// Creates the tray icon and its menu; menu callbacks send to the main loop through the tray sender
+fn create_tray(
+    tray_sender: &crossbeam_channel::Sender<AppMessage>,
+    profiles: &[Profile],
+    tray_icons: &TrayIcons,
+) -> Result<TrayMenu, Box<dyn Error>> {
+    // Use a simple placeholder icon name for now.
+    // For a real icon, you'd load it from a file (e.g., .ico on Windows)
+    // using `tray.set_icon(Icon::from_path("path/to/icon.ico")?)`
+    let mut tray = TrayItem::new(
+        "SoundSwitch",
+        tray_item::IconSource::Resource("default-icon"),
+    )
+    .map_err(|e| format!("Failed to create tray icon: {}", e))?;
+    info!("Tray icon created."); // Log info
+
+    // Add a numbered output device list (sorted, so the numbers match --set-output-index)
+    let mut device_items = Vec::new();
+    match list_output_devices() {
+        Ok(mut devices) => {
+            sort_for_indexing(&mut devices);
+            tray.add_label("Output devices (numbers may change when devices are added/removed)")
+                .map_err(|e| format!("Failed to add output device label: {}", e))?;
+            for (i, device) in devices.into_iter().enumerate() {
+                let device_sender = tray_sender.clone();
+                let label = format!("{}. {}", i + 1, device.name);
+                let selected = device.clone();
+                let item_id = tray
+                    .inner_mut()
+                    .add_menu_item_with_id(&label, move || {
+                        info!("Output device menu item selected: {}", selected.name); // Log info
+                        send_tray_message(
+                            &device_sender,
+                            AppMessage::SetOutputDevice { id: selected.id.clone(), name: selected.name.clone() },
+                        );
+                    })
+                    .map_err(|e| format!("Failed to add output device menu item: {}", e))?;
+                device_items.push((item_id, device));
+            }
+            info!("Output device menu items added."); // Log info
+        }
+        Err(e) => warn!("Could not list output devices for the tray menu: {}", e), // Log warning
+    }
+
+    // Add one item per profile (tray-item has no submenus, so they follow a label)
+    if !profiles.is_empty() {
+        tray.add_label("Profiles")
+            .map_err(|e| format!("Failed to add profiles label: {}", e))?;
+        for profile in profiles {
+            let profile_sender = tray_sender.clone();
+            let name = profile.name.clone();
+            tray.add_menu_item(&profile.name, move || {
+                info!("Profile menu item selected: {}", name); // Log info
+                send_tray_message(&profile_sender, AppMessage::ActivateProfile(name.clone()));
+            })
+            .map_err(|e| format!("Failed to add profile menu item: {}", e))?;
+        }
+    }
+
+    // Add the sticky-default toggle
+    let sticky_sender = tray_sender.clone();
+    let sticky_item = tray
+        .inner_mut()
+        .add_menu_item_with_id(STICKY_MENU_LABEL, move || {
+            info!("Sticky menu item selected."); // Log info
+            send_tray_message(&sticky_sender, AppMessage::ToggleStickyDefault);
+        })
+        .map_err(|e| format!("Failed to add sticky menu item: {}", e))?;
+
+    // Add Enabled menu item (checked while hotkeys are enabled)
+    let enabled_sender = tray_sender.clone();
+    let enabled_item = tray
+        .inner_mut()
+        .add_menu_item_with_id(ENABLED_MENU_LABEL, move || {
+            info!("Enabled menu item selected."); // Log info
+            send_tray_message(&enabled_sender, AppMessage::ToggleEnabled);
+        })
+        .map_err(|e| format!("Failed to add enabled menu item: {}", e))?;
+
+    // Add Test configured devices menu item
+    let test_sender = tray_sender.clone();
+    tray.add_menu_item("Test configured devices", move || {
+        info!("Test configured devices menu item selected."); // Log info
+        send_tray_message(&test_sender, AppMessage::TestConfig);
+    })
+    .map_err(|e| format!("Failed to add 'Test configured devices' menu item: {}", e))?;
+
+    // Add Edit config menu item
+    let edit_sender = tray_sender.clone();
+    tray.add_menu_item("Edit config", move || {
+        info!("Edit config menu item selected."); // Log info
+        send_tray_message(&edit_sender, AppMessage::EditConfig);
+    })
+    .map_err(|e| format!("Failed to add 'Edit config' menu item: {}", e))?;
+
+    // Add Reload config menu item
+    let reload_sender = tray_sender.clone();
+    tray.add_menu_item("Reload config", move || {
+        info!("Reload config menu item selected."); // Log info
+        send_tray_message(&reload_sender, AppMessage::ReloadConfig);
+    })
+    .map_err(|e| format!("Failed to add 'Reload config' menu item: {}", e))?;
+    info!("'Reload config' menu item added."); // Log info
+
+    // Add Restart hotkeys menu item
+    let restart_sender = tray_sender.clone();
+    tray.add_menu_item("Restart hotkeys", move || {
+        info!("Restart hotkeys menu item selected."); // Log info
+        send_tray_message(&restart_sender, AppMessage::RestartHotkeys);
+    })
+    .map_err(|e| format!("Failed to add 'Restart hotkeys' menu item: {}", e))?;
+
+    // Add Quit menu item
+    // Use the shared tray sender (as quit_sender) for the Quit message
+    let quit_sender = tray_sender.clone();
+    tray.add_menu_item("Quit", move || {
+        info!("Quit menu item selected."); // Log info
+        // Send a Quit message to the main loop to initiate shutdown
+        send_tray_message(&quit_sender, AppMessage::Quit);
+    })
+    .map_err(|e| format!("Failed to add 'Quit' menu item: {}", e))?;
+    info!("'Quit' menu item added."); // Log info
+
+    let icons = vec![
+        (IconCategory::Headphones, load_tray_icon(&tray_icons.headphones)),
+        (IconCategory::Speakers, load_tray_icon(&tray_icons.speakers)),
+        (IconCategory::Default, load_tray_icon(&tray_icons.default)),
+    ];
+    let mut menu = TrayMenu { tray, device_items, sticky_item, enabled_item, icons, shown_icon: None };
+    menu.mark_default();
+    menu.mark_sticky();
+    menu.mark_enabled();
+    Ok(menu)
+}
+
+// Lets Ctrl+C, Ctrl+Break and closing the console window shut SoundSwitch down cleanly
+unsafe extern "system" fn console_ctrl_handler(ctrl_type: u32) -> BOOL {
+    use windows::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT, CTRL_CLOSE_EVENT};
+
+    match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT => {
+            CONSOLE_QUIT.store(true, Ordering::Relaxed);
+            true.into()
+        }
+        _ => false.into(),
+    }
+}
+
+// `no_tray_arg` is set by --no-tray, which works like `no-tray = true` in the config
+fn run_tray_app(
+    loaded_config: Result<Config, Box<dyn Error>>,
+    no_tray_arg: bool,
+    dedup_logger: &DedupLogger,
+) -> Result<(), Box<dyn Error>> {
+    info!("Starting SoundSwitch with Tray Icon..."); // Log info
+
+    // 1. Configuration (loaded by main before the logger, needed for the hotkey thread)
+    let mut config = match loaded_config {
+        Ok(cfg) => {
+            info!("Configuration loaded successfully."); // Log info
+            if cfg.hotkeys.is_empty() {
+                warn!("No hotkeys defined in the configuration."); // Log warning
+            }
+            cfg // Return the loaded config
+        }
+        Err(e) => {
+            // Print the specific config error and return it to exit run_tray_app
+            error!("!!! Fatal: Error loading configuration: {} !!!", e); // Log error
+            return Err(e); // Propagate the error
+        }
+    };
+    // If we reach here, config loaded successfully.
+    STICKY_DEFAULT.store(config.sticky_default, Ordering::Relaxed);
+    // Hotkeys stay disabled across restarts if they were turned off from the tray
+    if !load_hotkeys_enabled() {
+        info!("Hotkeys are disabled (turned off from the tray menu before the last exit)"); // Log info
+        HOTKEYS_ENABLED.store(false, Ordering::Relaxed);
+    }
+
+    // At login the drivers may still be coming up; give the devices time to appear
+    if config.startup_delay_ms > 0 {
+        let waited = wait_for_devices(&SystemBackend, &config, STARTUP_POLL_INTERVAL);
+        info!("Waited {} ms for audio devices at startup", waited.as_millis()); // Log info
+    }
+
+    // Re-apply the devices from the last switch before the previous exit
+    if config.restore_on_start
+        && let Some(last_used) = load_last_used()
+    {
+        let restored = restore_last_used(&last_used, &SystemBackend);
+        info!("Restored {} last used default device role(s)", restored); // Log info
+    }
+
+    // 1.5. Validate configured devices and show notification if any are missing
+    info!("Validating configured devices..."); // Log info
+    let (missing_output, missing_input, available_output, available_input) = validate_configured_devices(&config);
+    if !missing_output.is_empty() || !missing_input.is_empty() {
+        warn!(
+            "Missing devices found - Output: {:?}, Input: {:?}",
+            missing_output, missing_input
+        ); // Log warning
+        show_missing_devices_notification(
+            &missing_output,
+            &missing_input,
+            &available_output,
+            &available_input,
+            config.notify_missing_devices,
+        );
+    } else {
+        info!("All configured devices found."); // Log info
+    }
+    warn_about_dynamic_names(&config);
+
+    // Opt-in check for a corrupted or tampered copy of the switching module
+    if config.verify_module_integrity
+        && let Err(problems) = verify_module_integrity()
+    {
+        for problem in &problems {
+            warn!("Module integrity check failed: {}", problem); // Log warning
+        }
+        show_notification(
+            "SoundSwitch - Module Integrity Check Failed",
+            &format!(
+                "The bundled AudioDeviceCmdlets module doesn't match this build:\n\n{}\n\n\
+                Switches that fall back to PowerShell will likely fail or do nothing. Reinstall SoundSwitch or copy the \
+                'modules' folder from the release again.",
+                problems.join("\n")
+            ),
+            true,
+        );
+    }
+
+    // Only the PowerShell fallback needs the module, so a missing one isn't fatal
+    if config.use_powershell_fallback {
+        match locate_audio_device_module() {
+            Ok(AudioDeviceModule::Bundled(_)) => {}
+            Ok(AudioDeviceModule::Installed) => {
+                info!("Bundled module not found; the PowerShell fallback uses the installed AudioDeviceCmdlets"); // Log info
+            }
+            Err(message) => {
+                warn!("{}", message); // Log warning
+                show_notification(
+                    "SoundSwitch - PowerShell Module Missing",
+                    &format!(
+                        "{}\n\nSwitching still works through the Windows audio API; only the PowerShell fallback \
+                        for devices it can't switch is unavailable.",
+                        message
+                    ),
+                    true,
+                );
+            }
+        }
+    }
+
+    // 2. Setup communication channels (Restore)
+    let (error_sender, error_receiver) = crossbeam_channel::unbounded::<AppMessage>();
+    let tray_sender = error_sender.clone();
+
+    // Optional control pipe for external scripts; commands run on this thread like tray actions
+    if config.enable_ipc {
+        let ipc_sender = tray_sender.clone();
+        spawn_ipc_server(move |request| send_tray_message(&ipc_sender, AppMessage::Ipc(request)));
+    }
+
+    // 3. Spawn Hotkey Listener Thread (Restore)
+    let mut listener = HotkeyListener::spawn(config.clone(), error_sender.clone());
+
+    // Ctrl+C in the launching terminal is the way to stop a headless instance
+    if let Err(e) = unsafe { SetConsoleCtrlHandler(Some(console_ctrl_handler), true) } {
+        warn!("Failed to install the console Ctrl+C handler: {}", e); // Log warning
+    }
+
+    // 4. Setup Tray Icon (Restore)
+    // Without a tray (no shell, session 0, RDP quirks) keep running headless unless require-tray is set
+    let no_tray = no_tray_arg || config.no_tray;
+    let created = (!no_tray).then(|| create_tray(&tray_sender, &config.profiles, &config.tray_icons));
+    let mut tray = match created {
+        None => {
+            // main() already attached to the launching terminal, if any, so Ctrl+C reaches us
+            info!("Running without a tray icon (no-tray); stop with Ctrl+C or by ending the process"); // Log info
+            None
+        }
+        Some(Ok(tray)) => Some(tray),
+        Some(Err(e)) if config.require_tray => {
+            error!("!!! Fatal: {} !!!", e); // Log error
+            listener.stop();
+            return Err(e);
+        }
+        Some(Err(e)) => {
+            error!("{}. Continuing without a tray icon.", e); // Log error
+            show_notification(
+                "SoundSwitch - Tray Unavailable",
+                &format!(
+                    "The system tray icon could not be created:\n\n{}\n\n\
+                    SoundSwitch keeps running in the background and your hotkeys still work. \
+                    Without the tray there is no Quit menu; end the process to stop it.\n\n\
+                    Set require-tray = true to exit instead.",
+                    e
+                ),
+                true,
+            );
+            None
+        }
+    };
+
+    // 5. Main Event Loop (Handling Tray Events and Messages from hotkey thread)
+    info!("Main thread entering event loop (polling for messages)..."); // Log info
+    let mut tray_rebuild_due: Option<Instant> = None;
+    loop {
+        if CONSOLE_QUIT.load(Ordering::Relaxed) {
+            info!("Console close requested. Initiating shutdown..."); // Log info
+            break;
+        }
+
+        // Rebuild the tray menu after a config reload or once device changes have settled.
+        // tray-item can't remove menu items, so the whole tray is recreated.
+        if tray_rebuild_due.is_some_and(|due| Instant::now() >= due) {
+            tray_rebuild_due = None;
+            drop(tray.take()); // Remove the old icon before adding the new one
+            tray = match create_tray(&tray_sender, &config.profiles, &config.tray_icons) {
+                Ok(menu) => {
+                    info!("Tray menu rebuilt."); // Log info
+                    Some(menu)
+                }
+                Err(e) => {
+                    error!("Failed to rebuild the tray menu: {}. Continuing without a tray icon.", e); // Log error
+                    None
+                }
+            };
+        }
+
+        // Check for messages from the hotkey thread or quit callback
+        match error_receiver.try_recv() {
+            Ok(AppMessage::HotkeyError(err)) => {
+                error!("Error received from hotkey thread: {}", err); // Log error
+                // Otherwise a hotkey that silently does nothing is easy to miss
+                show_notification("SoundSwitch hotkey error", &err, true);
+            }
+            Ok(AppMessage::ReloadConfig) => {
+                reload_config(&mut config, &mut listener, &error_sender, dedup_logger);
+                tray_rebuild_due = tray.is_some().then(Instant::now);
+            }
+            Ok(AppMessage::RestartHotkeys) => restart_hotkeys(&config, &mut listener, &error_sender),
+            Ok(AppMessage::DefaultOutputChanged) => {
+                if let Some(menu) = &mut tray {
+                    menu.mark_default();
+                }
+            }
+            Ok(AppMessage::DefaultInputChanged) => {
+                if let Some(menu) = &mut tray {
+                    menu.update_tooltip(get_default_output_device(AudioRole::Console).ok().as_ref());
+                }
+            }
+            Ok(AppMessage::DevicesChanged) => {
+                // Devices often change in bursts (e.g. a headset's endpoints), so wait for it to settle
+                if tray.is_some() {
+                    tray_rebuild_due = Some(Instant::now() + TRAY_REBUILD_DELAY);
+                }
+            }
+            Ok(AppMessage::TestConfig) => {
+                // Runs on its own thread: it waits for confirmation and takes a while
+                let test_config = config.clone();
+                thread::spawn(move || {
+                    let outcome = ensure_com_initialized().map_err(Into::into).and_then(|()| run_device_test(&test_config));
+                    match outcome {
+                        Ok(Some(report)) => show_notification("SoundSwitch - Device Test Results", &report, false),
+                        Ok(None) => {}
+                        Err(e) => {
+                            error!("Device test failed: {}", e); // Log error
+                            show_notification("SoundSwitch - Device Test Failed", &e.to_string(), true);
+                        }
+                    }
+                });
+            }
+            Ok(AppMessage::EditConfig) => open_config_in_editor(config.source_path.as_deref()),
+            Ok(AppMessage::ShowMenu) => {
+                if tray.is_none() {
+                    warn!("show-menu hotkey pressed, but there is no tray icon (running headless)"); // Log warning
+                } else if let Err(e) = show_tray_menu() {
+                    error!("Failed to open the tray menu: {}", e); // Log error
+                }
+            }
+            Ok(AppMessage::ToggleEnabled) => {
+                let enabled = !HOTKEYS_ENABLED.fetch_xor(true, Ordering::Relaxed);
+                info!("Hotkeys {} (tray menu)", if enabled { "enabled" } else { "disabled" }); // Log info
+                record_hotkeys_enabled(enabled);
+                if let Some(menu) = &mut tray {
+                    menu.mark_enabled();
+                }
+            }
+            Ok(AppMessage::ToggleStickyDefault) => {
+                let enabled = !STICKY_DEFAULT.fetch_xor(true, Ordering::Relaxed);
+                info!("Sticky default devices turned {} (tray menu)", if enabled { "on" } else { "off" }); // Log info
+                if let Some(menu) = &mut tray {
+                    menu.mark_sticky();
+                }
+            }
+            Ok(AppMessage::SetOutputDevice { id, name }) => {
+                STICKY_RELEASE.store(true, Ordering::Relaxed);
+                match SystemBackend.set_default_output_device(&id) {
+                    Ok(()) => {
+                        info!("Successfully set output device to {} (tray menu)", name); // Log info
+                        record_last_used(&SystemBackend);
+                    }
+                    Err(e) => error!("Failed to set output device '{}' from tray menu: {}", name, e), // Log error
+                }
+            }
+            Ok(AppMessage::ActivateProfile(name)) => {
+                info!("Activating profile '{}' (tray menu)", name); // Log info
+                STICKY_RELEASE.store(true, Ordering::Relaxed);
+                let backend = SystemBackend;
+                match (backend.list_output_devices(), backend.list_input_devices()) {
+                    (Ok(outputs), Ok(inputs)) => {
+                        let outcome = switch_to_profile(&name, &backend, &outputs, &inputs, &config);
+                        announce_switch(&outcome, None, &config);
+                        if outcome.switched_any() {
+                            record_last_used(&backend);
+                        }
+                    }
+                    (Err(e), _) | (_, Err(e)) => error!("Failed to list devices for profile '{}': {}", name, e), // Log error
+                }
+            }
+            Ok(AppMessage::Ipc(request)) => {
+                let result = run_ipc_command(&request.command, &config);
+                info!("Control pipe result: {}", result); // Log info
+                let _ = request.reply.send(result);
+            }
+            Ok(AppMessage::Quit) => {
+                info!("Quit message received. Initiating shutdown..."); // Log info
+                break; // Exit the main loop to start shutdown
+            }
+            // No message. The channel can't disconnect while this thread holds senders of its own;
+            // a listener thread that died is caught below
+            Err(_) => {}
+        }
+
+        // The listener thread only exits when stopped, unless something went wrong (e.g. a panic)
+        let keep_running = recover_exited_listener(&mut listener, config.exit_on_channel_disconnect, || {
+            HotkeyListener::spawn(config.clone(), error_sender.clone())
+        });
+        if !keep_running {
+            break; // The shutdown sequence below joins the finished thread
+        }
+
+        // Add a small sleep to prevent the loop from spinning excessively
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // 6. Shutdown Sequence (Restore original logic)
+    info!("Starting shutdown sequence..."); // Log info
+
+    // Signal the hotkey thread to stop and wait for it to finish
+    listener.stop();
+    remove_status_file();
+
+    info!("SoundSwitch application finished."); // Log info
+    log::logger().flush(); // Write out pending "(repeated N times)" lines
+    // println!("--- EXITING run_tray_app (Ok) ---"); // Removed debug print
+    Ok(())
+}
+
+fn main() {
+    // Release builds use the GUI subsystem and start without a console. Attach to the console of
+    // the launching terminal, if any, so the command-line queries below print there and Ctrl+C
+    // reaches a headless instance
+    let attached = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) }.is_ok();
+    // The main thread runs the tray and the command-line queries. The device functions expect
+    // their caller to have initialized COM, so it stays initialized for the whole process.
+    if let Err(e) = ensure_com_initialized() {
+        eprintln!("Warning: failed to initialize COM: {}", e);
+    }
+    // Command-line queries run before the logger so they don't truncate a running instance's log
+    if std::env::args().any(|arg| arg == "--device-history") {
+        print_device_history();
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--diagnostics") {
+        match write_diagnostics() {
+            Ok(path) => println!(
+                "Diagnostics written to {}\nIt contains your config and device names/IDs; review it before sharing.",
+                path.display()
+            ),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--list-devices") {
+        let allocated = !attached && open_console();
+        // Include unplugged and disabled devices so they can be configured ahead of time
+        let outcome = list_devices_including_inactive(DeviceFlow::Output)
+            .and_then(|outputs| Ok((outputs, list_devices_including_inactive(DeviceFlow::Input)?)))
+            .map(|(outputs, inputs)| print!("{}", format_device_table(&outputs, &inputs)));
+        if let Err(e) = &outcome {
+            eprintln!("Error: {}", e);
+        }
+        if allocated {
+            // A console opened just for this closes with the process, so keep it up until Enter
+            println!("\nPress Enter to close.");
+            let _ = std::io::stdin().read_line(&mut String::new());
+        }
+        if outcome.is_err() {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(index_arg) = flag_value("--set-output-index") {
+        if let Err(e) = set_output_by_index(index_arg) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--test-config") {
+        let outcome = load_config().and_then(|config| run_device_test(&config));
+        match outcome {
+            Ok(Some(report)) => println!("\n{}", report),
+            Ok(None) => println!("Device test cancelled."),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--check") {
+        match check_config() {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if std::env::args().any(|arg| arg == "--list-hotkeys") {
+        if let Err(e) = list_hotkeys() {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--status") {
+        // Always exits 0; anything that couldn't be read is null in the output
+        match serde_json::to_string_pretty(&status_report()) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--snapshots") {
+        match list_snapshots().and_then(|snapshots| Ok(serde_json::to_string_pretty(&snapshots)?)) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(name_arg) = flag_value("--restore-snapshot") {
+        let outcome = name_arg
+            .ok_or_else(|| "--restore-snapshot requires a snapshot name".into())
+            .and_then(|name| load_snapshot(&name).and_then(|snapshot| restore_snapshot(&snapshot)));
+        if let Err(e) = outcome {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        println!("Snapshot restored.");
+        return;
+    }
+    if let Some(name_arg) = flag_value("--test-mic") {
+        if let Err(e) = test_microphone(name_arg) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(name_arg) = flag_value("--exists") {
+        match query_device_exists(name_arg) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // Identical messages repeated within the configured window are collapsed (see log_dedup); the
+    // logger is kept so the window can follow the config once it loads and on reload.
+    // The log file is opened once the config has chosen its level and size limit; messages
+    // logged while loading it are buffered and replayed (see log_file)
+    let dedup_logger: &'static DedupLogger =
+        Box::leak(Box::new(DedupLogger::new(Box::new(DeferredLogger), DEFAULT_LOG_DEDUP_WINDOW_MS)));
+    log::set_logger(dedup_logger).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+    let config = load_config();
+    let logging = config.as_ref().map(|c| c.logging.clone()).unwrap_or_default();
+    let level = logging.level_filter();
+    let file_logger = WriteLogger::new(
+        level.unwrap_or(LevelFilter::Info),
+        ConfigBuilder::new().build(),
+        RotatingFile::create(LOG_FILE_NAME, logging.max_size_bytes()).unwrap(), // Create log file
+    );
+    install_log_sink(file_logger);
+    if let Ok(config) = &config {
+        dedup_logger.set_window(config.log_dedup_window_ms);
+    }
+    log::set_max_level(level.unwrap_or(LevelFilter::Info));
+    if level.is_none() {
+        warn!("Unknown log level '{}' in [logging], using info", logging.level); // Log warning
+    }
+    // Use run_tray_app instead of run_app
+    let no_tray_arg = std::env::args().any(|arg| arg == "--no-tray");
+    if let Err(e) = run_tray_app(config, no_tray_arg, dedup_logger) {
+        // Using eprintln might not be visible if the console is hidden.
+        // Consider logging to a file or using a message box for errors in release.
+        eprintln!("Application exited with error: {}", e);
+        // For now, just print to stderr, which might go nowhere in release.
+        // A message box could be used here for critical errors.
+        // Example (requires enabling UI features in windows-rs):
+        use windows::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_OK, MessageBoxW};
+        use windows::core::w;
+        unsafe {
+            MessageBoxW(
+                None,
+                w!("Application exited with error."),
+                w!("SoundSwitch Error"),
+                MB_OK | MB_ICONERROR,
+            );
+        }
+        std::process::exit(1);
+    }
+}
+
+// Handles `--list-hotkeys`: prints how each mapping was parsed and whether it registered.
+// Uses the running instance's registration results if there is one, otherwise tests registration here.
+fn list_hotkeys() -> Result<(), Box<dyn Error>> {
+    let registrations = match read_status() {
+        Ok(status) => {
+            println!("Hotkeys of the running instance (PID {}, updated {}):", status.pid, status.updated);
+            status.hotkeys
+        }
+        Err(_) => {
+            println!("SoundSwitch is not running; testing registration of the configured hotkeys:");
+            let config = load_config()?;
+            let manager = GlobalHotKeyManager::new()?;
+            probe_hotkeys(&manager, &config)
+        }
+    };
+
+    for registration in &registrations {
+        print_hotkey_registration(registration);
+    }
+    let registered = registrations.iter().filter(|r| r.registered).count();
+    println!("{} of {} hotkey(s) registered.", registered, registrations.len());
+    Ok(())
+}
+
+fn print_hotkey_registration(registration: &HotkeyRegistration) {
+    println!(
+        "  [{}] '{}' -> {}",
+        if registration.registered { "OK" } else { "FAILED" },
+        registration.keys,
+        registration.parsed.as_deref().unwrap_or("(not parsed)")
+    );
+    if let Some(action) = &registration.action {
+        println!("        action: {:?}", action);
+    }
+    if let Some(device) = &registration.device_name {
+        println!("        output: {}", device);
+    }
+    if let Some(device) = &registration.input_device_name {
+        println!("        input:  {}", device);
+    }
+    if let Some(error) = &registration.error {
+        println!("        error:  {}", error);
+    }
+}
+
+// Opens a console window for a query started without a terminal, e.g. from Explorer. Returns
+// whether one was opened.
+fn open_console() -> bool {
+    use windows::Win32::System::Console::AllocConsole;
+
+    unsafe { AllocConsole().is_ok() }
+}
+
+// Formats the `--list-devices` table: type, state, friendly name and endpoint ID of every device
+fn format_device_table(outputs: &[AudioDevice], inputs: &[AudioDevice]) -> String {
+    let rows: Vec<(&str, &AudioDevice)> = outputs
+        .iter()
+        .map(|d| ("Output", d))
+        .chain(inputs.iter().map(|d| ("Input", d)))
+        .collect();
+    let name_width = rows.iter().map(|(_, d)| d.name.chars().count()).max().unwrap_or(0).max("Name".len());
+
+    let mut table = format!("{:<6}  {:<9}  {:<name_width$}  {}\n", "Type", "State", "Name", "ID");
+    for (kind, device) in rows {
+        table.push_str(&format!("{:<6}  {:<9}  {:<name_width$}  {}\n", kind, device.state, device.name, device.id));
+    }
+    table
+}
+
+// Returns Some(value) if `flag` was passed on the command line; value is the argument following it
+fn flag_value(flag: &str) -> Option<Option<String>> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next()?;
+    Some(args.next())
+}
+
+// Handles `--exists <name>`: reports whether a device matching the name (under the configured
+// matching settings) is currently present, and as which role(s). Returns Ok(false) if not present.
+fn query_device_exists(name_arg: Option<String>) -> Result<bool, Box<dyn Error>> {
+    let name = name_arg.ok_or("--exists requires a device name")?;
+    // Fall back to default matching settings if there is no usable config
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Warning: using default matching settings ({})", e);
+            toml::from_str("")?
+        }
+    };
+
+    let outputs = list_output_devices()?;
+    let inputs = list_input_devices()?;
+    let matches = [
+        ("output", find_best_match(&name, &outputs, &config)),
+        ("input", find_best_match(&name, &inputs, &config)),
+    ];
+
+    if matches.iter().all(|(_, device)| device.is_none()) {
+        println!("Device '{}' is not present.", name);
+        return Ok(false);
+    }
+    println!("Device '{}' is present:", name);
+    for (role, device) in matches {
+        if let Some((device, _)) = device {
+            println!("  {}: {} [{}]", role, device.name, device.id);
+        }
+    }
+    Ok(true)
+}
+
+// How long `--test-mic` listens
+const MIC_TEST_DURATION_MS: u32 = 3000;
+// Peaks below this count as silence: a dead or virtual device reads 0, a quiet room a little more
+const MIC_SILENCE_THRESHOLD: f32 = 0.01;
+
+// Handles `--test-mic <name>`: resolves the name like an input hotkey and prints the peak level
+// the device picks up while the user speaks into it
+fn test_microphone(name_arg: Option<String>) -> Result<(), Box<dyn Error>> {
+    let name = name_arg.ok_or("--test-mic requires a device name")?;
+    // Fall back to default matching settings if there is no usable config
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Warning: using default matching settings ({})", e);
+            toml::from_str("")?
+        }
+    };
+
+    let inputs = list_input_devices()?;
+    let (device, score) =
+        find_best_input_match(&name, &inputs, &config).ok_or_else(|| format!("No input device matches '{}'", name))?;
+    println!("Testing '{}' [{}]{}", device.name, device.id, score_suffix(score, &config));
+    println!("Speak into the microphone for {} seconds...", MIC_TEST_DURATION_MS / 1000);
+    let peak = peak_input_level(&device.id, MIC_TEST_DURATION_MS)?;
+    println!("{}", describe_mic_level(peak));
+    Ok(())
+}
+
+// The --test-mic result line: the peak as a percentage with a bar, and a hint if it was silent
+fn describe_mic_level(peak: f32) -> String {
+    let peak = peak.clamp(0.0, 1.0);
+    let bar = "#".repeat((peak * 20.0).round() as usize);
+    let mut line = format!("Peak level: {:>3.0}% [{:<20}]", peak * 100.0, bar);
+    if peak < MIC_SILENCE_THRESHOLD {
+        line.push_str("\nNo sound was picked up. The device may be muted, disconnected or a virtual device.");
+    }
+    line
+}
+
+// Outcome of testing one configured mapping
+struct MappingTestResult {
+    keys: String,
+    passed: bool,
+    details: Vec<String>,
+}
+
+// Asks for confirmation before the (disruptive) device test
+fn confirm_device_test(mapping_count: usize) -> bool {
+    use windows::Win32::UI::WindowsAndMessaging::{IDOK, MB_ICONWARNING, MB_OKCANCEL, MessageBoxW};
+    use windows::core::HSTRING;
+
+    let title = HSTRING::from("SoundSwitch - Test Configured Devices");
+    let content = HSTRING::from(format!(
+        "This will test {} hotkey mapping(s) one after another: each device is made the default, \
+        output devices play a short test tone, and then the previous defaults are restored.\n\n\
+        Audio in other applications may be interrupted. Continue?",
+        mapping_count
+    ));
+    unsafe { MessageBoxW(None, &content, &title, MB_OKCANCEL | MB_ICONWARNING) == IDOK }
+}
+
+// Resolves, switches to and (for outputs) plays a tone on each device of one mapping
+fn test_mapping_devices(
+    mapping: &HotkeyMapping,
+    backend: &dyn AudioBackend,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+) -> MappingTestResult {
+    let mut result = MappingTestResult { keys: mapping.keys.to_string(), passed: true, details: Vec::new() };
+
+    if let Some(name) = mapping.output_target() {
+        let step = resolve_match(name, available_output_devices, config, false)
+            .map_err(|e| format!("no match ({:?})", e))
+            .and_then(|(device, _)| {
+                backend.set_default_output_device(&device.id).map_err(|e| format!("switch failed: {}", e))?;
+                thread::sleep(Duration::from_millis(300)); // Give Windows a moment to route audio to the new default
+                play_test_tone().map_err(|e| format!("tone failed: {}", e))?;
+                Ok(device)
+            });
+        match step {
+            Ok(device) => result.details.push(format!("output '{}': played test tone", device.name)),
+            Err(e) => {
+                result.passed = false;
+                result.details.push(format!("output '{}': {}", name, e));
+            }
+        }
+    }
+
+    if let Some(name) = mapping.input_target() {
+        let step = resolve_match(name, available_input_devices, config, config.prefer_communications_input)
+            .map_err(|e| format!("no match ({:?})", e))
+            .and_then(|(device, _)| {
+                backend.set_default_input_device(&device.id).map_err(|e| format!("switch failed: {}", e))?;
+                Ok(device)
+            });
+        match step {
+            Ok(device) => result.details.push(format!("input '{}': switched", device.name)),
+            Err(e) => {
+                result.passed = false;
+                result.details.push(format!("input '{}': {}", name, e));
+            }
+        }
+    }
+
+    result
+}
+
+// Tests every device mapping end-to-end (`--test-config` and the tray item).
+// Returns the report, or None if the user cancelled.
+fn run_device_test(config: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    let mappings: Vec<&HotkeyMapping> = config
+        .hotkeys
+        .iter()
+        .filter(|m| m.output_target().is_some() || m.input_target().is_some())
+        .collect();
+    if mappings.is_empty() {
+        return Ok(Some("No device mappings configured.".to_string()));
+    }
+    if !confirm_device_test(mappings.len()) {
+        info!("Device test cancelled."); // Log info
+        return Ok(None);
+    }
+
+    let backend = SystemBackend;
+    let outputs = backend.list_output_devices()?;
+    let inputs = backend.list_input_devices()?;
+    let original_output = get_default_output_device(AudioRole::Console).ok();
+    let original_input = get_default_input_device(AudioRole::Console).ok();
+
+    let mut results = Vec::new();
+    for (i, mapping) in mappings.iter().enumerate() {
+        info!("Device test [{}/{}]: testing '{}'", i + 1, mappings.len(), mapping.keys); // Log info
+        println!("[{}/{}] Testing '{}'...", i + 1, mappings.len(), mapping.keys);
+        let result = test_mapping_devices(mapping, &backend, &outputs, &inputs, config);
+
+        // Switch back before the next mapping
+        if let Some(original) = &original_output
+            && let Err(e) = backend.set_default_output_device(&original.id)
+        {
+            warn!("Device test: failed to switch back to '{}': {}", original.name, e); // Log warning
+        }
+        if let Some(original) = &original_input
+            && let Err(e) = backend.set_default_input_device(&original.id)
+        {
+            warn!("Device test: failed to switch back to '{}': {}", original.name, e); // Log warning
+        }
+
+        for detail in &result.details {
+            info!("Device test '{}': {}", result.keys, detail); // Log info
+        }
+        println!("  {}", if result.passed { "PASS" } else { "FAIL" });
+        results.push(result);
+    }
+
+    let passed = results.iter().filter(|r| r.passed).count();
+    let mut report = format!("{} of {} mapping(s) passed.\n", passed, results.len());
+    for result in &results {
+        report.push_str(&format!(
+            "\n{} {}\n",
+            if result.passed { "PASS" } else { "FAIL" },
+            result.keys
+        ));
+        for detail in &result.details {
+            report.push_str(&format!("  • {}\n", detail));
+        }
+    }
+    Ok(Some(report))
+}
+
+// Resolves one configured name like a hotkey press would, without switching.
+// Returns a line for the --check report and whether the name resolved.
+fn check_device_name(
+    label: &str,
+    name: &str,
+    available_devices: &[AudioDevice],
+    config: &Config,
+    prefer_communications: bool,
+) -> (String, bool) {
+    match resolve_match(name, available_devices, config, prefer_communications) {
+        Ok((device, score)) => {
+            (format!("{} '{}' -> '{}' (score {:.3})", label, name, device.name, score), true)
+        }
+        Err(e) => (format!("{} '{}' -> not found ({:?})", label, name, e), false),
+    }
+}
+
+// Resolves every device name of one mapping against the given device lists (see --check)
+fn check_mapping(
+    mapping: &HotkeyMapping,
+    available_output_devices: &[AudioDevice],
+    available_input_devices: &[AudioDevice],
+    config: &Config,
+) -> MappingTestResult {
+    let mut result = MappingTestResult { keys: mapping.keys.to_string(), passed: true, details: Vec::new() };
+    let profile = mapping.activate_profile.as_deref().and_then(|name| config.profile(name));
+    let output_names = mapping
+        .output_target()
+        .into_iter()
+        .chain(mapping.device_names.iter().flatten().map(String::as_str))
+        .chain(profile.and_then(|p| p.device_name.as_deref()))
+        .chain(mapping.step_devices(DeviceFlow::Output));
+    let input_names = mapping
+        .input_target()
+        .into_iter()
+        .chain(profile.and_then(|p| p.input_device_name.as_deref()))
+        .chain(mapping.step_devices(DeviceFlow::Input));
+    let checks = output_names
+        .map(|name| ("output", name, available_output_devices, false))
+        .chain(mapping.comms_device_name.as_deref().map(|name| ("communications", name, available_output_devices, false)))
+        .chain(input_names.map(|name| ("input", name, available_input_devices, config.prefer_communications_input)));
+
+    for (label, name, devices, prefer_communications) in checks {
+        let (detail, resolved) = check_device_name(label, name, devices, config, prefer_communications);
+        result.passed &= resolved;
+        result.details.push(detail);
+    }
+    if result.details.is_empty()
+        && let Some(action) = &mapping.action
+    {
+        result.details.push(format!("action {:?}", action));
+    }
+    result
+}
+
+// Handles `--check`: validates the config and resolves every mapping against the connected
+// devices without switching anything. Returns Ok(false) if any device name did not resolve.
+fn check_config() -> Result<bool, Box<dyn Error>> {
+    let config = load_config()?;
+    let outputs = list_output_devices()?;
+    let inputs = list_input_devices()?;
+    println!("Config OK: {} hotkey mapping(s), matching with {}.", config.hotkeys.len(), match_mode_label(&config));
+
+    for mapping in &config.hotkeys {
+        let result = check_mapping(mapping, &outputs, &inputs, &config);
+        println!("\n{} {}", if result.passed { "OK  " } else { "FAIL" }, result.keys);
+        for detail in &result.details {
+            println!("  {}", detail);
+        }
+    }
+
+    let (missing_output, missing_input, _, _) = validate_configured_devices(&config);
+    if missing_output.is_empty() && missing_input.is_empty() {
+        println!("\nAll configured devices resolved.");
+        return Ok(true);
+    }
+    println!("\nUnresolved devices:");
+    for entry in missing_output.iter().chain(missing_input.iter()) {
+        println!("  {}", entry);
+    }
+    Ok(false)
+}
+
+// Output of `--status`. Fields that couldn't be read are null, so the command always succeeds.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+struct StatusReport {
+    output_device: Option<String>,
+    output_device_id: Option<String>,
+    input_device: Option<String>,
+    input_device_id: Option<String>,
+    hotkeys: usize,
+    resolved_hotkeys: usize,
+    config_path: Option<String>,
+    config_error: Option<String>,
+}
+
+// Counts the hotkeys whose devices all resolve among the given devices
+fn resolved_hotkey_count(config: &Config, outputs: &[AudioDevice], inputs: &[AudioDevice]) -> usize {
+    config.hotkeys.iter().filter(|mapping| check_mapping(mapping, outputs, inputs, config).passed).count()
+}
+
+// Handles `--status`: reports the current defaults and how the config resolves, as JSON
+fn status_report() -> StatusReport {
+    let mut report = StatusReport::default();
+    if let Ok(device) = get_default_output_device(AudioRole::Console) {
+        report.output_device = Some(device.name);
+        report.output_device_id = Some(device.id);
+    }
+    if let Ok(device) = get_default_input_device(AudioRole::Console) {
+        report.input_device = Some(device.name);
+        report.input_device_id = Some(device.id);
+    }
+    let loaded = config::find_config_path().and_then(|path| {
+        report.config_path = Some(path.display().to_string());
+        config::load_config_from(&path)
+    });
+    match loaded {
+        Ok(config) => {
+            // Devices that can't be listed count as missing
+            let outputs = list_output_devices().unwrap_or_default();
+            let inputs = list_input_devices().unwrap_or_default();
+            report.hotkeys = config.hotkeys.len();
+            report.resolved_hotkeys = resolved_hotkey_count(&config, &outputs, &inputs);
+        }
+        Err(e) => report.config_error = Some(e.to_string()),
+    }
+    report
+}
+
+// Sorts devices by name (then ID) so indices are reproducible regardless of enumeration order
+fn sort_for_indexing(devices: &mut [AudioDevice]) {
+    devices.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| a.id.cmp(&b.id)));
+}
+
+// Picks the device at a 1-based index of the sorted list
+fn device_at_index(devices: &[AudioDevice], index: usize) -> Result<&AudioDevice, String> {
+    index
+        .checked_sub(1)
+        .and_then(|i| devices.get(i))
+        .ok_or_else(|| format!("Device index {} is out of range (1-{})", index, devices.len()))
+}
+
+// Handles `--set-output-index N`: switches the output device by its position in the sorted list
+fn set_output_by_index(index_arg: Option<String>) -> Result<(), Box<dyn Error>> {
+    let index: usize = index_arg
+        .ok_or("--set-output-index requires a device number")?
+        .parse()
+        .map_err(|e| format!("Invalid device number: {}", e))?;
+
+    let mut devices = list_output_devices()?;
+    sort_for_indexing(&mut devices);
+    eprintln!("Warning: device indices can change when devices are added or removed.");
+    for (i, device) in devices.iter().enumerate() {
+        println!("  {}. {}", i + 1, device.name);
+    }
+
+    let device = device_at_index(&devices, index)?;
+    SystemBackend.set_default_output_device(&device.id)?;
+    println!("Switched output device to {}. {}", index, device.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{DeviceFormat, FuzzyMatchAlgorithm};
+    use hotkey_manager::parse_hotkey_string;
+    use std::cell::RefCell;
+
+    // Backend with a curated device list that records switch calls instead of touching the system
+    struct MockBackend {
+        output_devices: Vec<AudioDevice>,
+        input_devices: Vec<AudioDevice>,
+        output_switches: RefCell<Vec<String>>,
+        input_switches: RefCell<Vec<String>>,
+        role_switches: RefCell<Vec<(String, AudioRole)>>,
+        volume_changes: RefCell<Vec<(String, f32)>>,
+        format_changes: RefCell<Vec<(String, DeviceFormat)>>,
+        stuck_roles: Vec<(DeviceFlow, AudioRole)>,
+    }
+
+    impl MockBackend {
+        fn new() -> Self {
+            Self::with_devices(
+                vec![
+                    device("out-speakers", "Speakers (Realtek High Definition Audio)"),
+                    device("out-headset", "Headset (HyperX Cloud II Wireless)"),
+                    device("out-monitor", "DELL U2719DC (NVIDIA High Definition Audio)"),
+                ],
+                vec![
+                    device("in-realtek", "Microphone (Realtek High Definition Audio)"),
+                    device("in-hyperx", "Microphone (HyperX Cloud II Wireless)"),
+                ],
+            )
+        }
+
+        fn with_devices(output_devices: Vec<AudioDevice>, input_devices: Vec<AudioDevice>) -> Self {
+            MockBackend {
+                output_devices,
+                input_devices,
+                output_switches: RefCell::new(Vec::new()),
+                input_switches: RefCell::new(Vec::new()),
+                role_switches: RefCell::new(Vec::new()),
+                volume_changes: RefCell::new(Vec::new()),
+                format_changes: RefCell::new(Vec::new()),
+                stuck_roles: Vec::new(),
+            }
+        }
+    }
+
+    impl AudioBackend for MockBackend {
+        fn list_output_devices(&self) -> Result<Vec<AudioDevice>, Box<dyn Error>> {
+            Ok(self.output_devices.clone())
+        }
+
+        fn list_input_devices(&self) -> Result<Vec<AudioDevice>, Box<dyn Error>> {
+            Ok(self.input_devices.clone())
+        }
+
+        fn set_default_output_device(&self, device_id: &str) -> Result<(), Box<dyn Error>> {
+            self.output_switches.borrow_mut().push(device_id.to_string());
+            Ok(())
+        }
+
+        fn set_default_input_device(&self, device_id: &str) -> Result<(), Box<dyn Error>> {
+            self.input_switches.borrow_mut().push(device_id.to_string());
+            Ok(())
+        }
+
+        fn set_default_device_for_role(&self, device_id: &str, role: AudioRole) -> Result<(), Box<dyn Error>> {
+            self.role_switches.borrow_mut().push((device_id.to_string(), role));
+            Ok(())
+        }
+
+        // The last device switched to, or the first device before any switch
+        fn default_output_device(&self) -> Result<AudioDevice, Box<dyn Error>> {
+            let id = self.output_switches.borrow().last().cloned();
+            id.and_then(|id| self.output_devices.iter().find(|d| d.id == id))
+                .or(self.output_devices.first())
+                .cloned()
+                .ok_or_else(|| "no output devices".into())
+        }
+
+        fn set_volume(&self, device_id: &str, level: f32) -> Result<(), Box<dyn Error>> {
+            self.volume_changes.borrow_mut().push((device_id.to_string(), level));
+            Ok(())
+        }
+
+        // Like a device in use, the monitor refuses every format change
+        fn set_device_format(&self, device_id: &str, format: DeviceFormat) -> Result<(), Box<dyn Error>> {
+            if device_id == "out-monitor" {
+                return Err("device in use".into());
+            }
+            self.format_changes.borrow_mut().push((device_id.to_string(), format));
+            Ok(())
+        }
+
+        // Like default_output_device, except that roles in `stuck_roles` never change
+        fn default_device(&self, flow: DeviceFlow, role: AudioRole) -> Result<AudioDevice, Box<dyn Error>> {
+            let (switches, devices) = match flow {
+                DeviceFlow::Output => (&self.output_switches, &self.output_devices),
+                DeviceFlow::Input => (&self.input_switches, &self.input_devices),
+            };
+            let id = if self.stuck_roles.contains(&(flow, role)) { None } else { switches.borrow().last().cloned() };
+            id.and_then(|id| devices.iter().find(|d| d.id == id))
+                .or(devices.first())
+                .cloned()
+                .ok_or_else(|| "no devices".into())
+        }
+    }
+
+    fn device(id: &str, name: &str) -> AudioDevice {
+        device_with_form_factor(id, name, FormFactor::Unknown)
+    }
+
+    fn device_with_form_factor(id: &str, name: &str, form_factor: FormFactor) -> AudioDevice {
+        AudioDevice {
+            id: id.to_string(),
+            name: name.to_string(),
+            form_factor,
+            adapter_name: None,
+            container_id: None,
+            state: DeviceState::Active,
+        }
+    }
+
+    fn parse_config(toml_str: &str) -> Config {
+        toml::from_str(toml_str).expect("test config should parse")
+    }
+
+    // Simulates a hotkey press the same way the listener thread dispatches it
+    fn press(keys: &str, config: &Config, backend: &MockBackend) {
+        press_with_recent(keys, config, backend, &mut RecentOutputs::default());
+    }
+
+    fn press_with_recent(keys: &str, config: &Config, backend: &MockBackend, recent_outputs: &mut RecentOutputs) {
+        let mut hotkey_device_map = HotkeyDeviceMap::default();
+        for mapping in &config.hotkeys {
+            let ids: Vec<u32> = mapping
+                .keys
+                .combos()
+                .map(|combo| parse_hotkey_string(combo).expect("test hotkey should parse").id())
+                .collect();
+            hotkey_device_map.insert(&ids, mapping.clone());
+        }
+        let hotkey_id = parse_hotkey_string(keys).expect("pressed hotkey should parse").id();
+        let outputs = backend.list_output_devices().unwrap();
+        let inputs = backend.list_input_devices().unwrap();
+
+        let _ = handle_hotkey_press(hotkey_id, &hotkey_device_map, backend, &outputs, &inputs, config, recent_outputs);
+    }
+
+    #[test]
+    fn exact_match_switches_output_and_input() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (Realtek High Definition Audio)"
+            input-device-name = "Microphone (Realtek High Definition Audio)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("ctrl+alt+1", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-speakers"]);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-realtek"]);
+    }
+
+    #[test]
+    fn exact_match_miss_does_not_switch() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+            input-device-name = "Microphone"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+1", &config, &backend);
+
+        assert!(backend.output_switches.borrow().is_empty());
+        assert!(backend.input_switches.borrow().is_empty());
+    }
+
+    #[test]
+    fn skim_fuzzy_match_switches_to_matching_device() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "skim"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "HyperX"
+            input-device-name = "HyperX"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+2", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset"]);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-hyperx"]);
+    }
+
+    #[test]
+    fn levenshtein_fuzzy_match_respects_threshold() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            fuzzy-match-threshold = 0.8
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+3"
+            device-name = "DELL U2719DC (NVIDIA High Definition Audio) 2"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+4"
+            device-name = "DELL"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+3", &config, &backend);
+        press("Ctrl+Alt+4", &config, &backend);
+
+        // Only the near-identical name clears the 0.8 similarity threshold
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-monitor"]);
+    }
+
+    #[test]
+    fn unknown_hotkey_does_not_switch() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (Realtek High Definition Audio)"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+9", &config, &backend);
+
+        assert!(backend.output_switches.borrow().is_empty());
+    }
+
+    fn paired_microphone_backend() -> MockBackend {
+        MockBackend::with_devices(
+            Vec::new(),
+            vec![
+                device_with_form_factor("in-raw", "Microphone (USB Audio)", FormFactor::Microphone),
+                device_with_form_factor("in-comms", "Microphone (USB Audio)", FormFactor::Headset),
+            ],
+        )
+    }
+
+    #[test]
+    fn input_tie_prefers_communications_endpoint_when_enabled() {
+        let config = parse_config("prefer-communications-input = true");
+        let backend = paired_microphone_backend();
+
+        let chosen = find_best_input_match("Microphone (USB Audio)", &backend.input_devices, &config);
+
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("in-comms"));
+    }
+
+    #[test]
+    fn input_tie_keeps_first_endpoint_when_disabled() {
+        let config = parse_config("");
+        let backend = paired_microphone_backend();
+
+        let chosen = find_best_input_match("Microphone (USB Audio)", &backend.input_devices, &config);
+
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("in-raw"));
+    }
+
+    fn numbered_headsets() -> Vec<AudioDevice> {
+        vec![
+            device("out-headset-2", "Headset (2)"),
+            device("out-headset-3", "Headset (3)"),
+        ]
+    }
+
+    #[test]
+    fn ambiguous_fuzzy_match_is_refused_when_configured() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            on-ambiguous = "refuse"
+            "#,
+        );
+        let devices = numbered_headsets();
+
+        // Both candidates are exactly one edit away from the target
+        match resolve_match("Headset (1)", &devices, &config, false) {
+            Err(MatchError::Ambiguous(tied)) => {
+                let ids: Vec<&str> = tied.iter().map(|d| d.id.as_str()).collect();
+                assert_eq!(ids, vec!["out-headset-2", "out-headset-3"]);
+            }
+            other => panic!("expected an ambiguous match, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ambiguous_fuzzy_match_picks_first_by_default() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            "#,
+        );
+        let devices = numbered_headsets();
+
+        let chosen = resolve_match("Headset (1)", &devices, &config, false);
+
+        assert_eq!(chosen.ok().map(|(d, _)| d.id.as_str()), Some("out-headset-2"));
+    }
+
+    #[test]
+    fn ambiguity_margin_controls_refusal() {
+        let strict = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            on-ambiguous = "refuse"
+            ambiguity-margin = 0.05
+            "#,
+        );
+        let lenient = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            on-ambiguous = "refuse"
+            ambiguity-margin = 0.2
+            "#,
+        );
+        let devices = numbered_headsets();
+
+        // 1.000 vs 0.909: a clear winner under a 5% margin, ambiguous under a 20% margin
+        assert_eq!(
+            resolve_match("Headset (2)", &devices, &strict, false).ok().map(|(d, _)| d.id.as_str()),
+            Some("out-headset-2")
+        );
+        assert!(matches!(
+            resolve_match("Headset (2)", &devices, &lenient, false),
+            Err(MatchError::Ambiguous(_))
+        ));
+    }
+
+    #[test]
+    fn ignore_parentheticals_matches_exact_and_fuzzy_names() {
+        let devices = vec![
+            device("out-speakers", "Speakers (Realtek High Definition Audio)"),
+            device("out-headphones", "Headphones (3- USB Audio Device)"),
+        ];
+        let exact = parse_config("ignore-parentheticals = true");
+        let fuzzy = parse_config(
+            r#"
+            ignore-parentheticals = true
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            fuzzy-match-threshold = 0.9
+            "#,
+        );
+
+        let chosen = find_best_match("Headphones", &devices, &exact);
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("out-headphones"));
+        // The full name is still what gets reported and switched to
+        assert_eq!(chosen.map(|(d, _)| d.name.as_str()), Some("Headphones (3- USB Audio Device)"));
+
+        let chosen = find_best_match("headphones (1- USB Audio Device)", &devices, &fuzzy);
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("out-headphones"));
+
+        // Without the option the exact match fails
+        assert!(find_best_match("Headphones", &devices, &parse_config("")).is_none());
+    }
+
+    #[test]
+    fn duplicate_exact_names_are_refused_with_candidate_ids() {
+        let devices = vec![
+            device("out-first", "USB Audio"),
+            device("out-other", "Speakers"),
+            device("out-second", "USB Audio"),
+        ];
+        let config = parse_config("");
+
+        match resolve_match("USB Audio", &devices, &config, false) {
+            Err(MatchError::DuplicateExact(duplicates)) => {
+                let ids: Vec<&str> = duplicates.iter().map(|d| d.id.as_str()).collect();
+                assert_eq!(ids, vec!["out-first", "out-second"]);
+            }
+            other => panic!("expected DuplicateExact, got {:?}", other),
+        }
+        // A unique name is unaffected
+        assert_eq!(
+            find_best_match("Speakers", &devices, &config).map(|(d, _)| d.id.as_str()),
+            Some("out-other")
+        );
+    }
+
+    #[test]
+    fn duplicate_exact_names_do_not_switch() {
+        let backend = MockBackend::with_devices(
+            vec![device("out-first", "USB Audio"), device("out-second", "USB Audio")],
+            vec![],
+        );
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "USB Audio"
+            "#,
+        );
+
+        press("Ctrl+Alt+1", &config, &backend);
+        assert!(backend.output_switches.borrow().is_empty());
+    }
+
+    #[test]
+    fn exact_first_on_ambiguous_picks_the_first_duplicate() {
+        let devices = vec![device("out-first", "USB Audio"), device("out-second", "USB Audio")];
+        let config = parse_config("exact-first-on-ambiguous = true");
+
+        let chosen = find_best_match("USB Audio", &devices, &config);
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("out-first"));
+    }
+
+    #[test]
+    fn switch_notification_body_uses_role_glyphs() {
+        let outcome = SwitchOutcome {
+            output: Some(device("out-1", "Speakers (USB)")),
+            input: Some(device("in-1", "Mikrofon (Ünïcode Gerät)")),
+            ..SwitchOutcome::default()
+        };
+
+        let body = outcome.notification_body(&parse_config("")).unwrap();
+        assert_eq!(body, "\u{1F50A} Speakers (USB)\n\u{1F3A4} Mikrofon (Ünïcode Gerät)");
+
+        let body = outcome.notification_body(&parse_config("notification-glyphs = false")).unwrap();
+        assert_eq!(body, "Output: Speakers (USB)\nInput: Mikrofon (Ünïcode Gerät)");
+    }
+
+    #[test]
+    fn switch_notification_body_skips_unswitched_roles() {
+        let outcome = SwitchOutcome { input: Some(device("in-1", "Headset Mic")), ..SwitchOutcome::default() };
+        assert_eq!(outcome.notification_body(&parse_config("")).as_deref(), Some("\u{1F3A4} Headset Mic"));
+        assert!(SwitchOutcome::default().notification_body(&parse_config("")).is_none());
+    }
+
+    // Writes the given files into a fresh temporary directory and returns its path
+    fn write_config_files(test_name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("sound_switch_{}_{}", test_name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_append_hotkeys_in_order() {
+        let dir = write_config_files(
+            "include_order",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                    include = ["gaming.toml", "work.toml"]
+
+                    [[hotkeys]]
+                    keys = "Ctrl+Alt+1"
+                    device-name = "Main"
+                    "#,
+                ),
+                (
+                    "gaming.toml",
+                    r#"
+                    include = ["nested/extra.toml"]
+
+                    [[hotkeys]]
+                    keys = "Ctrl+Alt+2"
+                    device-name = "Gaming"
+                    "#,
+                ),
+                ("work.toml", "[[hotkeys]]\nkeys = \"Ctrl+Alt+4\"\ndevice-name = \"Work\"\n"),
+            ],
+        );
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(
+            dir.join("nested").join("extra.toml"),
+            "[[hotkeys]]\nkeys = \"Ctrl+Alt+3\"\ndevice-name = \"Extra\"\n",
+        )
+        .unwrap();
+
+        let config = config::load_config_from(&dir.join("config.toml")).unwrap();
+        let devices: Vec<&str> = config.hotkeys.iter().filter_map(|h| h.device_name.as_deref()).collect();
+        assert_eq!(devices, vec!["Main", "Gaming", "Extra", "Work"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn include_cycles_are_rejected() {
+        let dir = write_config_files(
+            "include_cycle",
+            &[
+                ("config.toml", "include = [\"a.toml\"]\n"),
+                ("a.toml", "include = [\"b.toml\"]\n"),
+                ("b.toml", "include = [\"config.toml\"]\n"),
+            ],
+        );
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("Include cycle"), "unexpected error: {}", error);
+        assert!(error.contains("b.toml"), "error should name the including file: {}", error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_include_names_the_including_file() {
+        let dir = write_config_files("include_missing", &[("config.toml", "include = [\"missing.toml\"]\n")]);
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("missing.toml"), "unexpected error: {}", error);
+        assert!(error.contains("config.toml"), "error should name the including file: {}", error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn index_ordering_is_deterministic() {
+        let mut first = vec![
+            device("id-b", "speakers"),
+            device("id-c", "Headset"),
+            device("id-a", "Speakers"),
+        ];
+        let mut second: Vec<AudioDevice> = first.iter().rev().cloned().collect();
+        sort_for_indexing(&mut first);
+        sort_for_indexing(&mut second);
+
+        let ids: Vec<&str> = first.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["id-c", "id-a", "id-b"]);
+        assert_eq!(ids, second.iter().map(|d| d.id.as_str()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn device_index_is_one_based_and_checked() {
+        let devices = vec![device("id-1", "First"), device("id-2", "Second")];
+        assert_eq!(device_at_index(&devices, 1).unwrap().id, "id-1");
+        assert_eq!(device_at_index(&devices, 2).unwrap().id, "id-2");
+        assert!(device_at_index(&devices, 0).is_err());
+        assert!(device_at_index(&devices, 3).is_err());
+    }
+
+    #[test]
+    fn held_switch_hotkeys_fire_once() {
+        let mut filter = KeyRepeatFilter::default();
+        let start = Instant::now();
+
+        assert!(filter.should_fire(1, HotKeyState::Pressed, false, start));
+        assert!(!filter.should_fire(1, HotKeyState::Pressed, false, start + Duration::from_millis(500)));
+        assert!(!filter.should_fire(1, HotKeyState::Pressed, false, start + Duration::from_millis(530)));
+        // Another hotkey is not affected by the held one
+        assert!(filter.should_fire(2, HotKeyState::Pressed, false, start + Duration::from_millis(540)));
+
+        // Releasing resets, so the next press fires again
+        assert!(!filter.should_fire(1, HotKeyState::Released, false, start + Duration::from_millis(600)));
+        assert!(filter.should_fire(1, HotKeyState::Pressed, false, start + Duration::from_millis(700)));
+    }
+
+    #[test]
+    fn repeatable_actions_fire_while_held() {
+        let mut filter = KeyRepeatFilter::default();
+        let start = Instant::now();
+
+        assert!(filter.should_fire(1, HotKeyState::Pressed, true, start));
+        assert!(filter.should_fire(1, HotKeyState::Pressed, true, start + Duration::from_millis(500)));
+        assert!(filter.should_fire(1, HotKeyState::Pressed, true, start + Duration::from_millis(530)));
+
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+Up"
+            action = "volume-up"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+            "#,
+        );
+        assert!(config.hotkeys[0].is_repeatable());
+        assert!(!config.hotkeys[1].is_repeatable());
+    }
+
+    #[test]
+    fn press_after_missed_release_is_not_a_repeat() {
+        let mut filter = KeyRepeatFilter::default();
+        let start = Instant::now();
+
+        assert!(filter.should_fire(1, HotKeyState::Pressed, false, start));
+        assert!(filter.should_fire(1, HotKeyState::Pressed, false, start + KEY_REPEAT_WINDOW));
+    }
+
+    #[test]
+    fn decomposed_and_composed_accents_match() {
+        // Windows reports the composed form, the config was typed with a combining accent
+        let devices = vec![device("out-cafe", "Haut-parleurs (Caf\u{e9} USB)")];
+        let target = "Haut-parleurs (Cafe\u{301} USB)";
+
+        let chosen = find_best_match(target, &devices, &parse_config(""));
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("out-cafe"));
+
+        let levenshtein = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            fuzzy-match-threshold = 1.0
+            "#,
+        );
+        let chosen = find_best_match("haut-parleurs (cafe\u{301} usb)", &devices, &levenshtein);
+        assert_eq!(chosen.map(|(d, _)| d.id.as_str()), Some("out-cafe"));
+    }
+
+    // Backend that records the order of all switch calls in one list
+    struct OrderRecordingBackend {
+        inner: MockBackend,
+        calls: RefCell<Vec<&'static str>>,
+    }
+
+    impl AudioBackend for OrderRecordingBackend {
+        fn list_output_devices(&self) -> Result<Vec<AudioDevice>, Box<dyn Error>> {
+            self.inner.list_output_devices()
+        }
+        fn list_input_devices(&self) -> Result<Vec<AudioDevice>, Box<dyn Error>> {
+            self.inner.list_input_devices()
+        }
+        fn set_default_output_device(&self, _device_id: &str) -> Result<(), Box<dyn Error>> {
+            self.calls.borrow_mut().push("output");
+            Ok(())
+        }
+        fn set_default_input_device(&self, _device_id: &str) -> Result<(), Box<dyn Error>> {
+            self.calls.borrow_mut().push("input");
+            Ok(())
+        }
+        fn set_default_device_for_role(&self, device_id: &str, role: AudioRole) -> Result<(), Box<dyn Error>> {
+            self.inner.set_default_device_for_role(device_id, role)
+        }
+        fn default_output_device(&self) -> Result<AudioDevice, Box<dyn Error>> {
+            self.inner.default_output_device()
+        }
+        fn default_device(&self, flow: DeviceFlow, role: AudioRole) -> Result<AudioDevice, Box<dyn Error>> {
+            self.inner.default_device(flow, role)
+        }
+        fn set_volume(&self, device_id: &str, level: f32) -> Result<(), Box<dyn Error>> {
+            self.inner.set_volume(device_id, level)
+        }
+        fn set_device_format(&self, device_id: &str, format: DeviceFormat) -> Result<(), Box<dyn Error>> {
+            self.inner.set_device_format(device_id, format)
+        }
+    }
+
+    #[test]
+    fn switch_order_controls_sequencing() {
+        let hotkeys = r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (Realtek High Definition Audio)"
+            input-device-name = "Microphone (Realtek High Definition Audio)"
+        "#;
+        for (order, expected) in [("", ["output", "input"]), ("switch-order = \"input-first\"", ["input", "output"])] {
+            let backend = OrderRecordingBackend { inner: MockBackend::new(), calls: RefCell::new(Vec::new()) };
+            let config = parse_config(&format!("{}\n{}", order, hotkeys));
+            let mapping = &config.hotkeys[0];
+            let outputs = backend.list_output_devices().unwrap();
+            let inputs = backend.list_input_devices().unwrap();
+
+            switch_mapping_devices(mapping, &backend, &outputs, &inputs, &config);
+            assert_eq!(*backend.calls.borrow(), expected);
+        }
+    }
+
+    #[test]
+    fn dnd_window_crossing_midnight() {
+        let config = parse_config(
+            r#"
+            disable-between = ["22:00", "07:00"]
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+0"
+            action = "restore-snapshot"
+            ignore-dnd = true
+            "#,
+        );
+        let (normal, critical) = (Some(&config.hotkeys[0]), Some(&config.hotkeys[1]));
+        let at = |h: u32, m: u32| h * 60 + m;
+
+        assert!(blocked_by_dnd(1, normal, &config, at(22, 0)));
+        assert!(blocked_by_dnd(1, normal, &config, at(23, 59)));
+        assert!(blocked_by_dnd(1, normal, &config, at(0, 0)));
+        assert!(blocked_by_dnd(1, normal, &config, at(6, 59)));
+        assert!(!blocked_by_dnd(1, normal, &config, at(7, 0)));
+        assert!(!blocked_by_dnd(1, normal, &config, at(21, 59)));
+        // ignore-dnd mappings always run
+        assert!(!blocked_by_dnd(2, critical, &config, at(23, 0)));
+    }
+
+    #[test]
+    fn dnd_window_within_one_day() {
+        let config = parse_config(r#"disable-between = ["09:30", "17:00"]"#);
+        assert!(!blocked_by_dnd(1, None, &config, 9 * 60 + 29));
+        assert!(blocked_by_dnd(1, None, &config, 9 * 60 + 30));
+        assert!(blocked_by_dnd(1, None, &config, 16 * 60 + 59));
+        assert!(!blocked_by_dnd(1, None, &config, 17 * 60));
+        // No window configured
+        assert!(!blocked_by_dnd(1, None, &parse_config(""), 12 * 60));
+    }
+
+    #[test]
+    fn invalid_dnd_window_is_rejected() {
+        for bad in [r#"["22:00"]"#, r#"["25:00", "07:00"]"#, r#"["22:00", "7am"]"#] {
+            let result: Result<Config, _> = toml::from_str(&format!("disable-between = {}", bad));
+            assert!(result.is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn device_ids_decode_exactly_or_sanitized() {
+        let valid: Vec<u16> = "{0.0.0.00000000}.{a1b2}".encode_utf16().collect();
+        assert_eq!(audio_device::decode_device_id(&valid), ("{0.0.0.00000000}.{a1b2}".to_string(), true));
+
+        // An unpaired surrogate can't be decoded; the device keeps a sanitized, non-empty ID
+        let invalid = [0x007B, 0xD800, 0x0041, 0x007D];
+        let (id, exact) = audio_device::decode_device_id(&invalid);
+        assert!(!exact);
+        assert_eq!(id, "{\u{FFFD}A}");
+    }
+
+    #[test]
+    fn toggle_recent_flips_between_last_two_outputs() {
+        let backend = MockBackend::new();
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (Realtek High Definition Audio)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+T"
+            action = "toggle-recent"
+            "#,
+        );
+        let mut recent = RecentOutputs::default();
+
+        // Not enough history yet: nothing is switched
+        press_with_recent("Ctrl+Alt+T", &config, &backend, &mut recent);
+        assert!(backend.output_switches.borrow().is_empty());
+
+        press_with_recent("Ctrl+Alt+1", &config, &backend, &mut recent);
+        press_with_recent("Ctrl+Alt+2", &config, &backend, &mut recent);
+        press_with_recent("Ctrl+Alt+T", &config, &backend, &mut recent);
+        press_with_recent("Ctrl+Alt+T", &config, &backend, &mut recent);
+
+        assert_eq!(
+            *backend.output_switches.borrow(),
+            vec!["out-speakers", "out-headset", "out-speakers", "out-headset"]
+        );
+    }
+
+    #[test]
+    fn recent_outputs_keep_two_distinct_devices() {
+        let mut recent = RecentOutputs::default();
+        let (a, b, c) = (device("a", "A"), device("b", "B"), device("c", "C"));
+
+        recent.record(&a);
+        recent.record(&a);
+        assert!(recent.toggle_target(Some("a")).is_none());
+
+        recent.record(&b);
+        recent.record(&c);
+        assert_eq!(recent.toggle_target(Some("c")).map(|d| d.id.as_str()), Some("b"));
+        assert_eq!(recent.toggle_target(Some("b")).map(|d| d.id.as_str()), Some("c"));
+        // Default changed elsewhere: go to the most recent one
+        assert_eq!(recent.toggle_target(Some("a")).map(|d| d.id.as_str()), Some("c"));
+    }
+
+    #[test]
+    fn execution_policy_errors_are_recognized() {
+        let blocked = "Import-Module : File C:\\SoundSwitch\\modules\\AudioDeviceCmdlets\\AudioDeviceCmdlets.psm1 cannot be \
+            loaded because running scripts is disabled on this system. For more information, see \
+            about_Execution_Policies at https:/go.microsoft.com/fwlink/?LinkID=135170.\n\
+            + CategoryInfo          : SecurityError: (:) [Import-Module], PSSecurityException";
+        assert!(audio_device::is_execution_policy_error(blocked));
+
+        let other = "Set-AudioDevice : No AudioDevice with that ID";
+        assert!(!audio_device::is_execution_policy_error(other));
+    }
+
+    #[test]
+    fn comms_device_routes_roles_independently() {
+        let backend = MockBackend::new();
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+G"
+            device-name = "Speakers (Realtek High Definition Audio)"
+            comms-device-name = "Headset (HyperX Cloud II Wireless)"
+            "#,
+        );
+
+        press("Ctrl+Alt+G", &config, &backend);
+
+        // No all-roles switch, which would overwrite the communications default
+        assert!(backend.output_switches.borrow().is_empty());
+        assert_eq!(
+            *backend.role_switches.borrow(),
+            vec![
+                ("out-speakers".to_string(), AudioRole::Console),
+                ("out-headset".to_string(), AudioRole::Communications),
+            ]
+        );
+    }
+
+    #[test]
+    fn dynamic_name_parts_are_detected() {
+        assert_eq!(dynamic_name_hints("Speakers (2- USB Audio Device)"), vec!["a numbered prefix like '2- '"]);
+        assert_eq!(dynamic_name_hints("12- Headset"), vec!["a numbered prefix like '2- '"]);
+        assert_eq!(dynamic_name_hints("Line Out (Driver v6.0.1.8)"), vec!["a version number in parentheses"]);
+        assert!(dynamic_name_hints("Speakers (Realtek High Definition Audio)").is_empty());
+        assert!(dynamic_name_hints("Headset (HyperX Cloud II Wireless)").is_empty());
+        // A dotted number outside parentheses is part of the product name
+        assert!(dynamic_name_hints("Speakers 5.1 (USB Audio)").is_empty());
+    }
+
+    #[test]
+    fn set_both_roles_switches_and_verifies_every_role() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+B"
+            action = "set-both-roles"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            input-device-name = "Microphone (HyperX Cloud II Wireless)"
+            "#,
+        );
+        let backend = MockBackend::new();
+        press("Ctrl+Alt+B", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset".to_string()]);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-hyperx".to_string()]);
+        let headset = &backend.output_devices[1];
+        assert!(unconfirmed_roles(&backend, DeviceFlow::Output, headset).is_empty());
+    }
+
+    #[test]
+    fn unconfirmed_roles_reports_roles_that_did_not_take() {
+        let mut backend = MockBackend::new();
+        backend.stuck_roles.push((DeviceFlow::Output, AudioRole::Communications));
+        backend.set_default_output_device("out-headset").unwrap();
+
+        let headset = backend.output_devices[1].clone();
+        assert_eq!(
+            unconfirmed_roles(&backend, DeviceFlow::Output, &headset),
+            vec![AudioRole::Communications]
+        );
+    }
+
+    fn device_in_container(id: &str, name: &str, container_id: &str) -> AudioDevice {
+        AudioDevice { container_id: Some(container_id.to_string()), ..device(id, name) }
+    }
+
+    #[test]
+    fn auto_pair_input_follows_output_container() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+            auto-pair-input = true
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Headset Earphone (USB Headset)"
+            "#,
+        );
+        let headset = "A1B2C3D4-0000-1111-2222-333344445555";
+        let backend = MockBackend::with_devices(
+            vec![
+                device_in_container("out-speakers", "Speakers (Realtek)", SYSTEM_CONTAINER_ID),
+                device_in_container("out-headset", "Headset Earphone (USB Headset)", headset),
+            ],
+            vec![
+                device_in_container("in-realtek", "Microphone (Realtek)", SYSTEM_CONTAINER_ID),
+                device_in_container("in-headset", "Headset Microphone (USB Headset)", headset),
+            ],
+        );
+
+        press("Ctrl+Alt+2", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset".to_string()]);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-headset".to_string()]);
+    }
+
+    #[test]
+    fn system_container_is_not_paired() {
+        let speakers = device_in_container("out-speakers", "Speakers (Realtek)", SYSTEM_CONTAINER_ID);
+        let inputs = vec![device_in_container("in-realtek", "Microphone (Realtek)", SYSTEM_CONTAINER_ID)];
+        assert!(paired_input_device(&speakers, &inputs).is_none());
+        assert!(paired_input_device(&device("out-virtual", "Virtual Cable"), &inputs).is_none());
+    }
+
+    #[test]
+    fn device_names_cycle_from_the_current_default() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+C"
+            device-names = ["Headset (HyperX Cloud II Wireless)", "DELL U2719DC (NVIDIA High Definition Audio)"]
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        // The current default (speakers) isn't in the list, so the cycle starts at the first entry
+        press("Ctrl+Alt+C", &config, &backend);
+        press("Ctrl+Alt+C", &config, &backend);
+        press("Ctrl+Alt+C", &config, &backend);
+
+        assert_eq!(
+            *backend.output_switches.borrow(),
+            vec!["out-headset".to_string(), "out-monitor".to_string(), "out-headset".to_string()]
+        );
+    }
+
+    #[test]
+    fn device_names_cycle_skips_disconnected_entries() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+C"
+            device-names = ["Headset (HyperX Cloud II Wireless)", "Unplugged Speakers", "Speakers (Realtek High Definition Audio)"]
+            "#,
+        );
+        let backend = MockBackend::new();
+        backend.set_default_output_device("out-headset").unwrap();
+
+        press("Ctrl+Alt+C", &config, &backend);
+
+        assert_eq!(backend.output_switches.borrow().last().map(String::as_str), Some("out-speakers"));
+    }
+
+    #[test]
+    fn role_communications_only_sets_the_communications_role() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+V"
+            input-device-name = "Microphone (HyperX Cloud II Wireless)"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            role = "communications"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+V", &config, &backend);
+
+        assert!(backend.output_switches.borrow().is_empty());
+        assert!(backend.input_switches.borrow().is_empty());
+        assert_eq!(
+            *backend.role_switches.borrow(),
+            vec![
+                ("out-headset".to_string(), AudioRole::Communications),
+                ("in-hyperx".to_string(), AudioRole::Communications),
+            ]
+        );
+    }
+
+    #[test]
+    fn refreshed_device_list_includes_replugged_device() {
+        let backend = MockBackend::new();
+        let mut outputs = vec![device("out-speakers", "Speakers (Realtek High Definition Audio)")];
+
+        refresh_device_list(DeviceFlow::Output, &backend, &mut outputs);
+
+        assert!(outputs.iter().any(|d| d.id == "out-headset"));
+    }
+
+    #[test]
+    fn mapping_volume_is_clamped_and_applied_after_switching() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (Realtek High Definition Audio)"
+            volume = 0.35
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            volume = 1.5
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+1", &config, &backend);
+        press("Ctrl+Alt+2", &config, &backend);
+
+        assert_eq!(
+            *backend.volume_changes.borrow(),
+            vec![("out-speakers".to_string(), 0.35), ("out-headset".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn json_and_yaml_configs_are_parsed_by_extension() {
+        let dir = write_config_files(
+            "config_formats",
+            &[
+                (
+                    "config.json",
+                    r#"{ "fuzzy-match": false, "include": ["extra.yaml"], "hotkeys": [{ "keys": "Ctrl+Alt+1", "device-name": "Speakers" }] }"#,
+                ),
+                ("extra.yaml", "hotkeys:\n  - keys: Ctrl+Alt+2\n    device-name: Headset\n"),
+            ],
+        );
+
+        let config = config::load_config_from(&dir.join("config.json")).unwrap();
+
+        assert!(!config.fuzzy_match);
+        let keys: Vec<&str> = config.hotkeys.iter().map(|m| m.keys.as_str()).collect();
+        assert_eq!(keys, vec!["Ctrl+Alt+1", "Ctrl+Alt+2"]);
+    }
+
+    #[test]
+    fn out_of_range_fuzzy_threshold_is_rejected() {
+        let dir = write_config_files("fuzzy_threshold", &[("config.toml", "fuzzy-match-threshold = 1.5\n")]);
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("fuzzy-match-threshold"), "unexpected error: {}", error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fuzzy_algorithm_and_threshold_parse() {
+        let dir = write_config_files(
+            "fuzzy_algorithm",
+            &[("config.toml", "fuzzy-match-algorithm = \"levenshtein\"\nfuzzy-match-threshold = 0.75\n")],
+        );
+
+        let config = config::load_config_from(&dir.join("config.toml")).unwrap();
+        assert_eq!(config.fuzzy_match_algorithm, FuzzyMatchAlgorithm::Levenshtein);
+        assert_eq!(config.fuzzy_match_threshold, 0.75);
+
+        let defaults = parse_config("");
+        assert_eq!(defaults.fuzzy_match_algorithm, FuzzyMatchAlgorithm::Skim);
+        assert_eq!(defaults.fuzzy_match_threshold, 0.6);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_mapping_reports_resolution_and_failures() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (USB)"
+            input-device-name = "Microphone (USB)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            input-device-name = "Headset"
+            "#,
+        );
+        let outputs = vec![device("out-1", "Speakers (USB)")];
+        let inputs = vec![device("in-1", "Microphone (USB)")];
+
+        let result = check_mapping(&config.hotkeys[0], &outputs, &inputs, &config);
+        assert!(result.passed);
+        assert_eq!(
+            result.details,
+            vec![
+                "output 'Speakers (USB)' -> 'Speakers (USB)' (score 1.000)".to_string(),
+                "input 'Microphone (USB)' -> 'Microphone (USB)' (score 1.000)".to_string(),
+            ]
+        );
+
+        let result = check_mapping(&config.hotkeys[1], &outputs, &inputs, &config);
+        assert!(!result.passed);
+        assert_eq!(result.details, vec!["input 'Headset' -> not found (NoMatch)".to_string()]);
+    }
+
+    #[test]
+    fn toggle_back_returns_to_previous_output() {
+        let backend = MockBackend::new();
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers (Realtek High Definition Audio)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+H"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            toggle-back = true
+            "#,
+        );
+        let mut recent = RecentOutputs::default();
+
+        press_with_recent("Ctrl+Alt+1", &config, &backend, &mut recent);
+        press_with_recent("Ctrl+Alt+H", &config, &backend, &mut recent);
+        press_with_recent("Ctrl+Alt+H", &config, &backend, &mut recent);
+        press_with_recent("Ctrl+Alt+H", &config, &backend, &mut recent);
+
+        assert_eq!(
+            *backend.output_switches.borrow(),
+            vec!["out-speakers", "out-headset", "out-speakers", "out-headset"]
+        );
+    }
+
+    #[test]
+    fn toggle_back_leaves_default_when_previous_device_is_gone() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+H"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            toggle-back = true
+            "#,
+        );
+        let mut recent = RecentOutputs::default();
+        recent.remember_toggle_back(
+            parse_hotkey_string("Ctrl+Alt+H").unwrap().id(),
+            device("out-unplugged", "USB Speakers"),
+        );
+        let backend = MockBackend::new();
+
+        press_with_recent("Ctrl+Alt+H", &config, &backend, &mut recent);
+        assert!(backend.output_switches.borrow().is_empty());
+
+        // The memory was used up, so the next press switches again
+        press_with_recent("Ctrl+Alt+H", &config, &backend, &mut recent);
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset"]);
+    }
+
+    #[test]
+    fn log_level_parses_case_insensitively() {
+        let config = parse_config("[logging]\nlevel = \"DEBUG\"\nmax-size-mb = 2\n");
+        assert_eq!(config.logging.level_filter(), Some(LevelFilter::Debug));
+        assert_eq!(config.logging.max_size_bytes(), 2 * 1024 * 1024);
+
+        let config = parse_config("[logging]\nlevel = \"chatty\"\n");
+        assert_eq!(config.logging.level_filter(), None);
+        assert_eq!(parse_config("").logging.level_filter(), Some(LevelFilter::Info));
+    }
+
+    #[test]
+    fn input_only_mapping_leaves_output_alone() {
+        let backend = MockBackend::new();
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+F6"
+            input-device-name = "Microphone (HyperX Cloud II Wireless)"
+            "#,
+        );
+
+        press("Ctrl+Alt+F6", &config, &backend);
+
+        assert!(backend.output_switches.borrow().is_empty());
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-hyperx"]);
+    }
+
+    #[test]
+    fn mapping_without_devices_or_action_is_rejected() {
+        let dir = write_config_files("no_devices", &[("config.toml", "[[hotkeys]]\nkeys = \"Ctrl+Alt+9\"\n")]);
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("'Ctrl+Alt+9'"), "error should name the hotkey: {}", error);
+
+        // An input device alone is enough
+        let input_only = "[[hotkeys]]\nkeys = \"Ctrl+Alt+9\"\ninput-device-name = \"Microphone\"\n";
+        std::fs::write(dir.join("config.toml"), input_only).unwrap();
+        assert!(config::load_config_from(&dir.join("config.toml")).is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn activate_profile_switches_output_and_input() {
+        let backend = MockBackend::new();
+        let config = parse_config(
+            r#"
+            [[profiles]]
+            name = "gaming"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            input-device-name = "Microphone (HyperX Cloud II Wireless)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+G"
+            activate-profile = "gaming"
+            "#,
+        );
+
+        press("Ctrl+Alt+G", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset"]);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-hyperx"]);
+    }
+
+    #[test]
+    fn hotkey_for_undefined_profile_is_rejected() {
+        let dir = write_config_files(
+            "undefined_profile",
+            &[("config.toml", "[[hotkeys]]\nkeys = \"Ctrl+Alt+G\"\nactivate-profile = \"gaming\"\n")],
+        );
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("'gaming'"), "error should name the profile: {}", error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn device_id_picks_between_duplicate_names() {
+        let backend = MockBackend::with_devices(
+            vec![
+                device("{0.0.0.00000000}.{aaaa}", "Speakers"),
+                device("{0.0.0.00000000}.{bbbb}", "Speakers"),
+            ],
+            vec![],
+        );
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+            device-id = "{0.0.0.00000000}.{BBBB}"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            id = "{0.0.0.00000000}.{cccc}"
+            "#,
+        );
+
+        press("Ctrl+Alt+1", &config, &backend);
+        // An ID that isn't present does not fall back to name matching
+        press("Ctrl+Alt+2", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["{0.0.0.00000000}.{bbbb}"]);
+    }
+
+    #[test]
+    fn device_table_aligns_names() {
+        let table = format_device_table(
+            &[device("{0.0.0.00000000}.{a}", "Speakers (Realtek High Definition Audio)")],
+            &[AudioDevice { state: DeviceState::Unplugged, ..device("{0.0.1.00000000}.{b}", "Mic") }],
+        );
+
+        assert_eq!(
+            table,
+            "Type    State      Name                                      ID\n\
+             Output  active     Speakers (Realtek High Definition Audio)  {0.0.0.00000000}.{a}\n\
+             Input   unplugged  Mic                                       {0.0.1.00000000}.{b}\n"
+        );
+    }
+
+    #[test]
+    fn rapid_presses_are_debounced_per_hotkey() {
+        let mut filter = KeyRepeatFilter::with_debounce(Duration::from_millis(250));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(filter.should_fire(1, HotKeyState::Pressed, false, at(0)));
+        assert!(!filter.should_fire(1, HotKeyState::Released, false, at(50)));
+        assert!(!filter.should_fire(1, HotKeyState::Pressed, false, at(100)));
+        // Other hotkeys have their own window
+        assert!(filter.should_fire(2, HotKeyState::Pressed, false, at(120)));
+        // Ignored presses don't extend the window
+        assert!(!filter.should_fire(1, HotKeyState::Released, false, at(150)));
+        assert!(filter.should_fire(1, HotKeyState::Pressed, false, at(260)));
+
+        // Repeatable actions are never debounced
+        assert!(filter.should_fire(3, HotKeyState::Pressed, true, at(300)));
+        assert!(filter.should_fire(3, HotKeyState::Pressed, true, at(310)));
+    }
+
+    #[test]
+    fn switch_sounds_parse_and_resolve_relative_to_config() {
+        let dir = write_config_files(
+            "switch_sounds",
+            &[("config.toml", "switch-sound = \"sounds/ok.wav\"\nfailure-sound = \"Beep\"\n")],
+        );
+
+        let config = config::load_config_from(&dir.join("config.toml")).unwrap();
+        assert_eq!(config.switch_sound, SwitchSound::File(dir.join("sounds/ok.wav")));
+        assert_eq!(config.failure_sound, SwitchSound::Beep);
+        assert_eq!(parse_config("").switch_sound, SwitchSound::None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sticky_watchdog_enforces_sticky_mappings_or_sticky_default() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+            sticky = true
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Headset"
+            "#,
+        );
+        let outcome = SwitchOutcome { output: Some(device("out-1", "Speakers")), ..SwitchOutcome::default() };
+        let mut sticky = StickyWatchdog::default();
+
+        sticky.update(Some(&config.hotkeys[0]), Some(&outcome), &config);
+        assert!(sticky.enforces(false));
+        sticky.update(Some(&config.hotkeys[1]), Some(&outcome), &config);
+        assert!(!sticky.enforces(false));
+
+        // With sticky-default on, every switch is enforced
+        assert!(sticky.enforces(true));
+        // A press that switched nothing forgets the devices
+        sticky.update(Some(&config.hotkeys[1]), None, &config);
+        assert!(!sticky.enforces(true));
+    }
+
+    #[test]
+    fn hotkey_failures_are_listed_per_mapping() {
+        let message = format_hotkey_failures(&[
+            ("Ctrl+Alt+Q".to_string(), "Unsupported key code: Q?".to_string()),
+            ("A".to_string(), "'A' needs a modifier".to_string()),
+        ]);
+        assert!(message.starts_with("2 hotkey(s) could not be registered"));
+        assert!(message.contains("\n'Ctrl+Alt+Q': Unsupported key code: Q?"));
+        assert!(message.contains("\n'A': 'A' needs a modifier"));
+    }
+
+    #[test]
+    fn inactive_devices_are_reported_apart_from_missing_ones() {
+        let config = parse_config("");
+        let inactive = [AudioDevice { state: DeviceState::Unplugged, ..device("{0.0.0.00000000}.{h}", "Headset") }];
+        let mut missing = Vec::new();
+
+        report_missing_device("Output", "Headset", "hotkey: Ctrl+Alt+1", &inactive, &config, &mut missing);
+        report_missing_device("Output", "Speakers", "hotkey: Ctrl+Alt+2", &inactive, &config, &mut missing);
+
+        assert_eq!(
+            missing,
+            vec![
+                "Headset (hotkey: Ctrl+Alt+1) - exists but is unplugged".to_string(),
+                "Speakers (hotkey: Ctrl+Alt+2)".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_version_defaults_to_current_and_rejects_newer() {
+        assert_eq!(parse_config("").version, 1);
+
+        let dir = write_config_files("config_version_newer", &[("config.toml", "version = 99\n")]);
+        let err = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(err.contains("Config version 99 is newer than this build supports"), "{}", err);
+    }
+
+    #[test]
+    fn ipc_device_list_names_outputs_and_inputs() {
+        let reply = format_ipc_device_list(&[device("a", "Speakers"), device("b", "Headset")], &[device("c", "Mic")]);
+        assert_eq!(reply, "outputs: Speakers | Headset; inputs: Mic");
+    }
+
+    #[test]
+    fn missing_module_is_detected_and_explained() {
+        use crate::audio_device::{is_module_not_found_error, missing_module_message};
+
+        assert!(is_module_not_found_error(
+            "Import-Module : The specified module 'AudioDeviceCmdlets' was not loaded because no valid module file was found in any module directory."
+        ));
+        assert!(!is_module_not_found_error("Set-AudioDevice : No AudioDevice with that ID"));
+
+        let message = missing_module_message(std::path::Path::new(r"C:\SoundSwitch\modules\AudioDeviceCmdlets\AudioDeviceCmdlets.psd1"));
+        assert!(message.contains(r"C:\SoundSwitch\modules\AudioDeviceCmdlets\AudioDeviceCmdlets.psd1"));
+        assert!(message.contains("Install-Module -Name AudioDeviceCmdlets"));
+    }
+
+    #[test]
+    fn launch_settings_parse_and_are_validated() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+G"
+            device-name = "Headset"
+            launch = 'C:\Games\game.exe'
+            launch-args = ["-fullscreen"]
+            "#,
+        );
+        assert_eq!(config.hotkeys[0].launch.as_deref(), Some(r"C:\Games\game.exe"));
+        assert_eq!(config.hotkeys[0].launch_args, Some(vec!["-fullscreen".to_string()]));
+
+        let args_only = write_config_files(
+            "launch_args_only",
+            &[("config.toml", "[[hotkeys]]\nkeys = \"Ctrl+Alt+G\"\ndevice-name = \"Headset\"\nlaunch-args = [\"-x\"]\n")],
+        );
+        let err = config::load_config_from(&args_only.join("config.toml")).unwrap_err().to_string();
+        assert!(err.contains("'launch-args' without 'launch'"), "{}", err);
+
+        let volume = write_config_files(
+            "launch_on_volume",
+            &[("config.toml", "[[hotkeys]]\nkeys = \"Ctrl+Alt+Up\"\naction = \"volume-up\"\nlaunch = \"notepad.exe\"\n")],
+        );
+        let err = config::load_config_from(&volume.join("config.toml")).unwrap_err().to_string();
+        assert!(err.contains("action that doesn't switch devices"), "{}", err);
+
+        let outcome = SwitchOutcome { input: Some(device("a", "Mic")), ..Default::default() };
+        assert!(outcome.switched_any());
+        assert!(!SwitchOutcome::default().switched_any());
+    }
+
+    #[test]
+    fn regex_algorithm_picks_the_first_matching_device() {
+        let config = parse_config("fuzzy-match = true\nfuzzy-match-algorithm = \"regex\"\nignore-parentheticals = true");
+        let devices = [
+            device("a", "Headphones (Realtek Audio)"),
+            device("b", "Speakers (Realtek(R) Audio)"),
+            device("c", "Realtek Digital Output"),
+        ];
+
+        // Groups survive ignore-parentheticals, which only applies to the device names
+        assert_eq!(find_best_match("^(Speakers|Realtek Digital)", &devices, &config).map(|(d, _)| d.id.as_str()), Some("b"));
+        assert_eq!(find_best_match("Realtek.*Output", &devices, &config).map(|(d, _)| d.id.as_str()), Some("c"));
+        assert!(find_best_match("^Monitor", &devices, &config).is_none());
+        // An invalid pattern matches nothing instead of panicking, also when it comes from the cache
+        assert!(find_best_match("Speakers (", &devices, &config).is_none());
+        assert!(find_best_match("Speakers (", &devices, &config).is_none());
+    }
+
+    #[test]
+    fn transient_failures_are_retried_a_bounded_number_of_times() {
+        use crate::audio_device::retry_transient;
+
+        let mut calls = 0;
+        let result = retry_transient("Flaky", 3, Duration::ZERO, || {
+            calls += 1;
+            if calls < 3 { Err("driver reset") } else { Ok(calls) }
+        });
+        assert_eq!(result, Ok(3));
+
+        let mut calls = 0;
+        let result: Result<(), &str> = retry_transient("Broken", 3, Duration::ZERO, || {
+            calls += 1;
+            Err("still broken")
+        });
+        assert_eq!(result, Err("still broken"));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn last_used_devices_round_trip_through_json() {
+        let backend = MockBackend::new();
+        backend.set_default_output_device("out-headset").unwrap();
+        backend.set_default_input_device("in-hyperx").unwrap();
+
+        let captured = state::capture_last_used(&backend);
+        assert_eq!(captured.output.len(), 3);
+        assert!(captured.output.iter().all(|r| r.device_id == "out-headset"));
+        assert!(captured.input.iter().all(|r| r.device_id == "in-hyperx"));
+
+        let json = serde_json::to_string(&captured).unwrap();
+        assert!(json.contains("\"device-id\""));
+        let parsed: state::LastUsedDevices = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, captured);
+        assert!(serde_json::from_str::<state::LastUsedDevices>("{ not json").is_err());
+    }
+
+    #[test]
+    fn restore_last_used_skips_devices_that_are_gone() {
+        let backend = MockBackend::new();
+        let role = |role, id: &str| state::RoleDevice { role, device_id: id.to_string() };
+        let last_used = state::LastUsedDevices {
+            output: vec![
+                role(AudioRole::Console, "out-headset"),
+                role(AudioRole::Multimedia, "out-headset"),
+                role(AudioRole::Communications, "out-unplugged"),
+            ],
+            input: vec![role(AudioRole::Communications, "in-hyperx")],
+        };
+
+        assert_eq!(restore_last_used(&last_used, &backend), 2);
+        assert_eq!(
+            *backend.role_switches.borrow(),
+            vec![
+                ("out-headset".to_string(), AudioRole::Console),
+                ("in-hyperx".to_string(), AudioRole::Communications),
+            ]
+        );
+    }
+
+    #[test]
+    fn hotkey_label_falls_back_to_keys() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+G"
+            label = "Gaming Setup"
+            device-name = "Headset"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+S"
+            device-name = "Speakers"
+        "#,
+        );
+        assert_eq!(config.hotkeys[0].display_name(), "Gaming Setup");
+        assert_eq!(config.hotkeys[1].display_name(), "Ctrl+Alt+S");
+        assert_eq!(notification_title(Some(config.hotkeys[0].display_name())), "SoundSwitch - Gaming Setup");
+        assert_eq!(notification_title(None), "SoundSwitch");
+
+        let empty = write_config_files(
+            "empty_label",
+            &[("config.toml", "[[hotkeys]]\nkeys = \"Ctrl+Alt+G\"\nlabel = \" \"\ndevice-name = \"Headset\"\n")],
+        );
+        let err = config::load_config_from(&empty.join("config.toml")).unwrap_err().to_string();
+        assert!(err.contains("empty 'label'"), "{}", err);
+    }
+
+    #[test]
+    fn missing_devices_toast_summary_and_mode() {
+        let outputs = vec!["Headset".to_string(), "Monitor".to_string()];
+        let inputs = vec!["Microphone".to_string()];
+        assert_eq!(missing_devices_summary(&outputs, &inputs), "Output: Headset, Monitor\nInput: Microphone");
+        assert_eq!(
+            missing_devices_summary(&[], &inputs),
+            "Input: Microphone\nHotkeys using them won't work until they're connected."
+        );
+
+        assert_eq!(parse_config("").notify_missing_devices, MissingDevicesNotice::Modal);
+        assert_eq!(
+            parse_config("notify-missing-devices = \"toast\"").notify_missing_devices,
+            MissingDevicesNotice::Toast
+        );
+        assert_eq!(parse_config("notify-missing-devices = \"off\"").notify_missing_devices, MissingDevicesNotice::Off);
+    }
+
+    #[test]
+    fn glob_matches_whole_name_and_misses_return_none() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "glob"
+        "#,
+        );
+        let devices = vec![
+            device("out-speakers", "Speakers (Realtek High Definition Audio)"),
+            device("out-monitor", "DELL U2719DC Monitor (NVIDIA High Definition Audio)"),
+            device("out-dock", "Monitor (Dock Audio)"),
+        ];
+
+        assert_eq!(find_best_match("*monitor*", &devices, &config).map(|(d, _)| d.id.as_str()), Some("out-monitor"));
+        assert_eq!(find_best_match("Monitor*", &devices, &config).map(|(d, _)| d.id.as_str()), Some("out-dock"));
+        assert_eq!(find_best_match("Speakers (?ealtek*", &devices, &config).map(|(d, _)| d.id.as_str()), Some("out-speakers"));
+        // Without wildcards the whole name has to match
+        assert!(find_best_match("Monitor", &devices, &config).is_none());
+        assert!(find_best_match("*Headset*", &devices, &config).is_none());
+    }
+
+    #[test]
+    fn removed_default_is_recognized_even_after_windows_replaced_it() {
+        let start = Instant::now();
+        let mut history = DefaultHistory::default();
+        history.record("out-speakers", start);
+        history.record("out-headset", start);
+        assert!(history.was_default("out-headset", start));
+        assert!(!history.was_default("out-speakers", start + Duration::from_secs(5)));
+
+        // Windows picks the speakers as soon as the headset goes away
+        history.record("out-speakers", start + Duration::from_secs(10));
+        let removal = start + Duration::from_secs(11);
+        assert!(history.was_default("out-headset", removal));
+        assert!(!history.was_default("out-headset", removal + REPLACED_DEFAULT_WINDOW));
+        assert!(!history.was_default("out-monitor", removal));
+        assert_eq!(history.before("out-headset").collect::<Vec<_>>(), Vec::<&str>::new());
+        assert_eq!(history.before("out-speakers").collect::<Vec<_>>(), vec!["out-headset"]);
+    }
+
+    #[test]
+    fn removed_default_falls_back_per_policy() {
+        let now = Instant::now();
+        let backend = MockBackend::new();
+        // The headset was removed, so the refreshed list no longer has it
+        let outputs: Vec<AudioDevice> = backend.output_devices.iter().filter(|d| d.id != "out-headset").cloned().collect();
+        let mut defaults = DefaultTracker::default();
+        defaults.flow(DeviceFlow::Output).record("out-monitor", now);
+        defaults.flow(DeviceFlow::Output).record("out-speakers", now);
+        defaults.flow(DeviceFlow::Output).record("out-headset", now);
+
+        let mut config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "DELL U2719DC (NVIDIA High Definition Audio)"
+        "#,
+        );
+        let removed = |config: &Config| {
+            handle_default_removed("out-headset", &defaults, &backend, &outputs, &backend.input_devices, config, now)
+                .map(|d| d.id)
+        };
+
+        assert_eq!(removed(&config), None);
+        config.on_device_removed = DeviceRemovedPolicy::FallbackToPrevious;
+        assert_eq!(removed(&config).as_deref(), Some("out-speakers"));
+        config.on_device_removed = DeviceRemovedPolicy::SwitchToConfigured;
+        assert_eq!(removed(&config).as_deref(), Some("out-monitor"));
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-speakers", "out-monitor"]);
+
+        // Removing a device that wasn't the default changes nothing
+        assert_eq!(
+            handle_default_removed("out-monitor", &defaults, &backend, &outputs, &backend.input_devices, &config, now),
+            None
+        );
+        assert_eq!(
+            parse_config("on-device-removed = \"fallback-to-previous\"").on_device_removed,
+            DeviceRemovedPolicy::FallbackToPrevious
+        );
+    }
+
+    #[test]
+    fn icon_category_uses_form_factor_before_name() {
+        let headset = device_with_form_factor("a", "Speakers (USB Headset)", FormFactor::Headset);
+        let speakers = device_with_form_factor("b", "Headphones", FormFactor::Speakers);
+        let monitor = device_with_form_factor("c", "DELL U2720Q", FormFactor::DigitalAudioDisplay);
+        let spdif = device_with_form_factor("d", "Headphone S/PDIF Out", FormFactor::Spdif);
+        assert_eq!(icon_category(&headset), IconCategory::Headphones);
+        assert_eq!(icon_category(&speakers), IconCategory::Speakers);
+        assert_eq!(icon_category(&monitor), IconCategory::Default);
+        assert_eq!(icon_category(&spdif), IconCategory::Default);
+    }
+
+    #[test]
+    fn icon_category_falls_back_to_name_for_unknown_form_factor() {
+        let earbuds = device_with_form_factor("a", "Galaxy Earbuds", FormFactor::Unknown);
+        let speaker = device_with_form_factor("b", "Bluetooth Speaker", FormFactor::Unknown);
+        let other = device_with_form_factor("c", "Virtual Cable", FormFactor::Unknown);
+        assert_eq!(icon_category(&earbuds), IconCategory::Headphones);
+        assert_eq!(icon_category(&speaker), IconCategory::Speakers);
+        assert_eq!(icon_category(&other), IconCategory::Default);
+    }
+
+    #[test]
+    fn tray_icons_default_to_built_in_icons() {
+        let config = parse_config("hotkeys = []");
+        assert_eq!(config.tray_icons.headphones, "headphones-icon");
+        assert_eq!(config.tray_icons.speakers, "speakers-icon");
+        assert_eq!(config.tray_icons.default, "default-icon");
+
+        let config = parse_config(
+            r#"
+            hotkeys = []
+            [tray-icons]
+            speakers = 'C:\Icons\speakers.ico'
+            "#,
+        );
+        assert_eq!(config.tray_icons.speakers, "C:\\Icons\\speakers.ico");
+        assert_eq!(config.tray_icons.headphones, "headphones-icon");
+    }
+
+    #[test]
+    fn actions_run_in_order_and_continue_after_a_failure() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            actions = [
+                { type = "set-output", device = "Nonexistent Device" },
+                { type = "set-input", device = "Microphone (HyperX Cloud II Wireless)" },
+                { type = "set-output", device = "Headset (HyperX Cloud II Wireless)" },
+                { type = "set-volume", level = 0.4 },
+            ]
+            "#,
+        );
+        let backend = MockBackend::new();
+        press("Ctrl+Alt+1", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset".to_string()]);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-hyperx".to_string()]);
+        // The volume step applies to the output the earlier step switched to
+        assert_eq!(*backend.volume_changes.borrow(), vec![("out-headset".to_string(), 0.4)]);
+    }
+
+    #[test]
+    fn flat_fields_desugar_to_a_single_step() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            actions = [{ type = "toggle-mute" }, { type = "run", program = "notepad.exe", args = ["a.txt"] }]
+            "#,
+        );
+        assert_eq!(config.hotkeys[0].steps(), &[Action::Fields]);
+        assert_eq!(
+            config.hotkeys[1].steps(),
+            &[
+                Action::ToggleMute,
+                Action::Run { program: "notepad.exe".to_string(), args: vec!["a.txt".to_string()] },
+            ]
+        );
+    }
+
+    #[test]
+    fn actions_are_validated() {
+        let cases = [
+            (
+                "actions_with_device",
+                "[[hotkeys]]\nkeys = \"Ctrl+Alt+1\"\ndevice-name = \"Speakers\"\nactions = [{ type = \"toggle-mute\" }]\n",
+                "can't be combined",
+            ),
+            (
+                "actions_volume_range",
+                "[[hotkeys]]\nkeys = \"Ctrl+Alt+1\"\nactions = [{ type = \"set-volume\", level = 1.5 }]\n",
+                "action 1 ('set-volume')",
+            ),
+            (
+                "actions_empty_program",
+                "[[hotkeys]]\nkeys = \"Ctrl+Alt+1\"\nactions = [{ type = \"toggle-mute\" }, { type = \"run\", program = \"\" }]\n",
+                "action 2 ('run') has an empty 'program'",
+            ),
+        ];
+        for (test_name, content, expected) in cases {
+            let dir = write_config_files(test_name, &[("config.toml", content)]);
+            let err = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", test_name, err);
+        }
+    }
+
+    #[test]
+    fn fallback_devices_are_tried_in_order() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "USB DAC"
+            fallback-devices = ["Nonexistent Device", "Headset (HyperX Cloud II Wireless)", "Speakers (Realtek High Definition Audio)"]
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Speakers (Realtek High Definition Audio)"
+            fallback-devices = ["Headset (HyperX Cloud II Wireless)"]
+            "#,
+        );
+        let backend = MockBackend::new();
+        press("Ctrl+Alt+1", &config, &backend);
+        press("Ctrl+Alt+2", &config, &backend);
+        assert_eq!(
+            *backend.output_switches.borrow(),
+            vec!["out-headset".to_string(), "out-speakers".to_string()]
+        );
+
+        // Nothing in the list is connected: nothing is switched
+        let backend = MockBackend::with_devices(vec![device("out-other", "Monitor")], Vec::new());
+        press("Ctrl+Alt+1", &config, &backend);
+        assert!(backend.output_switches.borrow().is_empty());
+    }
+
+    #[test]
+    fn fallback_devices_need_a_primary_device() {
+        let cases = [
+            ("fallback_without_primary", "[[hotkeys]]\nkeys = \"Ctrl+Alt+1\"\nfallback-devices = [\"Speakers\"]\n", "needs 'device-name'"),
+            ("fallback_empty", "[[hotkeys]]\nkeys = \"Ctrl+Alt+1\"\ndevice-name = \"DAC\"\nfallback-devices = []\n", "empty 'fallback-devices'"),
+        ];
+        for (test_name, content, expected) in cases {
+            let dir = write_config_files(test_name, &[("config.toml", content)]);
+            let err = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", test_name, err);
+        }
+    }
+
+    #[test]
+    fn devices_are_equal_by_endpoint_id() {
+        let active = AudioDevice { adapter_name: Some("Realtek(R) Audio".to_string()), ..device("a", "Speakers") };
+        let unplugged = AudioDevice { state: DeviceState::Unplugged, adapter_name: None, ..device("a", "Speakers (renamed)") };
+        assert_eq!(active, unplugged);
+        assert_ne!(active, device("b", "Speakers"));
+    }
+
+    #[test]
+    fn state_file_keeps_enabled_flag_next_to_last_used_devices() {
+        // Files written before the flag existed load with hotkeys enabled
+        let old = r#"{ "output": [{ "role": "console", "device-id": "out-headset" }], "input": [] }"#;
+        let parsed: state::AppState = serde_json::from_str(old).unwrap();
+        assert!(parsed.hotkeys_enabled);
+        assert_eq!(parsed.last_used.output[0].device_id, "out-headset");
+
+        let disabled = state::AppState { hotkeys_enabled: false, ..parsed };
+        let json = serde_json::to_string(&disabled).unwrap();
+        assert!(json.contains("\"hotkeys-enabled\":false"), "{}", json);
+        assert_eq!(serde_json::from_str::<state::AppState>(&json).unwrap(), disabled);
+    }
+
+    #[test]
+    fn config_expands_paths_but_not_device_names() {
+        let dir = write_config_files(
+            "expand_paths",
+            &[(
+                "config.toml",
+                "[[hotkeys]]\nkeys = \"Ctrl+Alt+1\"\ndevice-name = \"%USERPROFILE%\"\nlaunch = \"%USERPROFILE%\\\\tool.exe\"\n",
+            )],
+        );
+        let config = config::load_config_from(&dir.join("config.toml")).unwrap();
+        let profile = std::env::var("USERPROFILE").unwrap();
+        assert_eq!(config.hotkeys[0].device_name.as_deref(), Some("%USERPROFILE%"));
+        assert_eq!(config.hotkeys[0].launch, Some(format!("{}\\tool.exe", profile)));
+    }
+
+    #[test]
+    fn device_names_cycle_reads_the_current_role() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = false
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+C"
+            device-names = ["Headset (HyperX Cloud II Wireless)", "DELL U2719DC (NVIDIA High Definition Audio)"]
+            current-role = "communications"
+            "#,
+        );
+        let mut backend = MockBackend::new();
+        // The communications default stays on the speakers whatever gets switched
+        backend.stuck_roles.push((DeviceFlow::Output, AudioRole::Communications));
+
+        press("Ctrl+Alt+C", &config, &backend);
+        press("Ctrl+Alt+C", &config, &backend);
+
+        let defaults = RoleDefaults::read(&backend, DeviceFlow::Output);
+        assert!(defaults.differ());
+        assert_eq!(defaults.get(AudioRole::Console).map(|d| d.id.as_str()), Some("out-headset"));
+        assert_eq!(defaults.get(AudioRole::Communications).map(|d| d.id.as_str()), Some("out-speakers"));
+        assert_eq!(
+            *backend.output_switches.borrow(),
+            vec!["out-headset".to_string(), "out-headset".to_string()]
+        );
+    }
+
+    #[test]
+    fn current_role_defaults_to_the_role_a_mapping_switches() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Headset"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "Headset"
+            role = "communications"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+3"
+            device-name = "Headset"
+            role = "communications"
+            current-role = "multimedia"
+            "#,
+        );
+
+        let roles: Vec<AudioRole> = config.hotkeys.iter().map(|m| m.current_role()).collect();
+        assert_eq!(roles, vec![AudioRole::Console, AudioRole::Communications, AudioRole::Multimedia]);
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected_when_configured() {
+        let content = r#"
+            on-duplicate-hotkey = "error"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Headset"
+
+            [[hotkeys]]
+            keys = "alt+ctrl+1"
+            device-name = "Speakers"
+            "#;
+        let dir = write_config_files("duplicate_keys", &[("config.toml", content)]);
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("Headset") && error.contains("Speakers"), "unexpected error: {}", error);
+
+        // "warn" (the default) only logs it
+        std::fs::write(dir.join("config.toml"), content.replace("\"error\"", "\"warn\"")).unwrap();
+        assert_eq!(config::load_config_from(&dir.join("config.toml")).unwrap().hotkeys.len(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn next_and_prev_output_step_through_all_devices() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+Right"
+            action = "next-output"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+Left"
+            action = "prev-output"
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+Right", &config, &backend);
+        press("Ctrl+Alt+Right", &config, &backend);
+        press("Ctrl+Alt+Right", &config, &backend); // Wraps around to the first device
+        press("Ctrl+Alt+Left", &config, &backend); // And back to the last one
+
+        assert_eq!(
+            *backend.output_switches.borrow(),
+            vec!["out-headset", "out-monitor", "out-speakers", "out-monitor"]
+        );
+    }
+
+    #[test]
+    fn next_input_skips_a_single_device() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+Right"
+            action = "next-input"
+            "#,
+        );
+        let mut backend = MockBackend::new();
+
+        press("Ctrl+Alt+Right", &config, &backend);
+        assert_eq!(*backend.input_switches.borrow(), vec!["in-hyperx"]);
+
+        backend.input_devices.truncate(1);
+        backend.input_switches.borrow_mut().clear();
+        press("Ctrl+Alt+Right", &config, &backend);
+        assert!(backend.input_switches.borrow().is_empty());
+    }
+
+    #[test]
+    fn fuzzy_switch_notification_shows_the_match_score() {
+        let config = parse_config(
+            r#"
+            fuzzy-match = true
+            fuzzy-match-algorithm = "levenshtein"
+            fuzzy-match-threshold = 0.5
+            notification-glyphs = false
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        let (device, score) =
+            find_and_set_output_device(&backend, "Speakers (Realtek HD Audio)", &backend.output_devices, &config).unwrap();
+        assert_eq!(device.id, "out-speakers");
+        assert!(score > 0.5 && score < 1.0, "unexpected score {}", score);
+
+        let mut outcome = SwitchOutcome::default();
+        outcome.record_score(&device, score);
+        outcome.output = Some(device);
+        let body = outcome.notification_body(&config).unwrap();
+        assert_eq!(body, format!("Output: Speakers (Realtek High Definition Audio) (score {:.2})", score));
+
+        // Exact matches always score 1.0, which isn't worth showing
+        let exact = parse_config("fuzzy-match = false\nnotification-glyphs = false");
+        assert_eq!(score_suffix(1.0, &exact), "");
+        assert_eq!(
+            find_best_match("Speakers (Realtek High Definition Audio)", &backend.output_devices, &exact).map(|(_, s)| s),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn no_tray_is_off_by_default() {
+        assert!(!parse_config("").no_tray);
+        assert!(parse_config("no-tray = true").no_tray);
+    }
+
+    #[test]
+    fn switch_retries_default_to_two() {
+        assert_eq!(parse_config("").switch_retries, 2);
+        assert_eq!(parse_config("switch-retries = 0").switch_retries, 0);
+    }
+
+    #[test]
+    fn status_report_counts_hotkeys_whose_devices_resolve() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Speakers"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+10"
+            device-name = "Headset"
+            input-device-name = "Webcam"
+        "#,
+        );
+        let outputs = vec![device("out-1", "Speakers"), device("out-2", "Headset")];
+        assert_eq!(resolved_hotkey_count(&config, &outputs, &[]), 1);
+        assert_eq!(resolved_hotkey_count(&config, &outputs, &[device("in-1", "Webcam")]), 2);
+
+        let report = StatusReport { hotkeys: 2, resolved_hotkeys: 1, ..StatusReport::default() };
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["resolved-hotkeys"], 1);
+        assert!(json["output-device"].is_null());
+    }
+
+    #[test]
+    fn every_combo_of_a_keys_list_triggers_the_same_mapping() {
+        let backend = MockBackend::new();
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = ["Ctrl+Alt+H", "F13"]
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            toggle-back = true
+            "#,
+        );
+        assert_eq!(config.hotkeys[0].display_name(), "Ctrl+Alt+H, F13");
+        let mut recent = RecentOutputs::default();
+
+        press_with_recent("F13", &config, &backend, &mut recent);
+        // Toggle-back is shared by the combinations, so the other one returns
+        press_with_recent("Ctrl+Alt+H", &config, &backend, &mut recent);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset", "out-speakers"]);
+    }
+
+    #[test]
+    fn duplicate_combos_inside_a_keys_list_are_reported() {
+        let content = r#"
+            on-duplicate-hotkey = "error"
+
+            [[hotkeys]]
+            keys = ["Ctrl+Alt+1", "F13"]
+            device-name = "Headset"
+
+            [[hotkeys]]
+            keys = ["F14", "f13"]
+            device-name = "Speakers"
+            "#;
+        let dir = write_config_files("duplicate_combos", &[("config.toml", content)]);
+
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("'F13'") && error.contains("'f13'"), "unexpected error: {}", error);
+
+        std::fs::write(dir.join("config.toml"), content.replace("[\"F14\", \"f13\"]", "[]")).unwrap();
+        let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+        assert!(error.contains("empty 'keys' list"), "unexpected error: {}", error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn mic_level_shows_percentage_and_flags_silence() {
+        assert_eq!(describe_mic_level(0.5), "Peak level:  50% [##########          ]");
+        assert_eq!(describe_mic_level(1.2), "Peak level: 100% [####################]");
+        assert!(describe_mic_level(0.0).contains("No sound was picked up"));
+        assert!(!describe_mic_level(0.05).contains("No sound"));
+    }
+
+    #[test]
+    fn startup_wait_ends_once_configured_devices_are_present() {
+        let backend = MockBackend::new();
+        let mut config = parse_config(
+            r#"
+            startup-delay-ms = 60000
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            input-device-name = "Microphone (HyperX Cloud II Wireless)"
+            "#,
+        );
+        // Returns after the second poll confirms the count, long before the timeout
+        assert!(wait_for_devices(&backend, &config, Duration::ZERO) < Duration::from_secs(1));
+
+        config.hotkeys[0].device_name = Some("USB Speakers".to_string());
+        assert!(!configured_devices_present(&config, &backend.output_devices, &backend.input_devices));
+        config.startup_delay_ms = 20;
+        assert!(wait_for_devices(&backend, &config, Duration::from_millis(5)) >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn mapping_format_is_applied_and_a_refusal_keeps_the_switch() {
+        let config = parse_config(
+            r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            format = { sample-rate = 96000, bits = 24 }
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            device-name = "DELL U2719DC (NVIDIA High Definition Audio)"
+            format = { sample_rate = 48000, bits = 16 }
+            "#,
+        );
+        let backend = MockBackend::new();
+
+        press("Ctrl+Alt+1", &config, &backend);
+        press("Ctrl+Alt+2", &config, &backend);
+
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset", "out-monitor"]);
+        assert_eq!(
+            *backend.format_changes.borrow(),
+            vec![("out-headset".to_string(), DeviceFormat { sample_rate: 96000, bits: 24 })]
+        );
+
+        let wave = audio_device::pcm_format(DeviceFormat { sample_rate: 96000, bits: 24 }, 2, 0x3);
+        let (block_align, bytes_per_second) = (wave.Format.nBlockAlign, wave.Format.nAvgBytesPerSec);
+        assert_eq!((block_align, bytes_per_second), (6, 576_000));
+    }
+
+    #[test]
+    fn loaded_config_remembers_its_file_for_edit_config() {
+        let content = r#"
+            [[hotkeys]]
+            keys = "Ctrl+Alt+E"
+            action = "edit-config"
+            "#;
+        let dir = write_config_files("edit_config", &[("config.toml", content)]);
+
+        let config = config::load_config_from(&dir.join("config.toml")).unwrap();
+        assert_eq!(config.source_path.as_deref(), Some(dir.join("config.toml").as_path()));
+        assert_eq!(config.hotkeys[0].action, Some(HotkeyAction::EditConfig));
+        // A config that wasn't loaded from a file has none; the folder is opened instead
+        assert!(parse_config("").source_path.is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn quiet_hours_cross_midnight() {
+        let config = parse_config(
+            r#"
+            [quiet-hours]
+            start = "22:30"
+            end = "07:00"
+            "#,
+        );
+        let at = |h: u32, m: u32| h * 60 + m;
+
+        assert!(!is_quiet_at(&config, at(22, 29)));
+        assert!(is_quiet_at(&config, at(22, 30)));
+        assert!(is_quiet_at(&config, at(23, 59)));
+        assert!(is_quiet_at(&config, at(0, 0)));
+        assert!(is_quiet_at(&config, at(6, 59)));
+        assert!(!is_quiet_at(&config, at(7, 0)));
+        assert!(!is_quiet_at(&config, at(12, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_within_one_day_and_unset() {
+        let config = parse_config(
+            r#"
+            [quiet_hours]
+            start = "13:00"
+            end = "14:00"
+            "#,
+        );
+        assert!(!is_quiet_at(&config, 12 * 60 + 59));
+        assert!(is_quiet_at(&config, 13 * 60));
+        assert!(!is_quiet_at(&config, 14 * 60));
+        assert!(!is_quiet_at(&parse_config(""), 23 * 60));
+
+        let bad: Result<Config, _> = toml::from_str("[quiet-hours]\nstart = \"22:00\"\nend = \"24:00\"");
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn tray_tooltip_names_both_defaults_and_truncates_long_names() {
+        assert_eq!(tray_tooltip(Some("Speakers"), Some("Blue Yeti")), "Out: Speakers | In: Blue Yeti");
+        assert_eq!(tray_tooltip(None, Some("Blue Yeti")), "Out: none | In: Blue Yeti");
+
+        let long = "Speakers (High Definition Audio Device With A Very Long Name)";
+        let tooltip = tray_tooltip(Some(long), Some(long));
+        let shortened = truncate_device_name(long, TOOLTIP_NAME_MAX_CHARS);
+        assert!(shortened.ends_with("..."));
+        assert!(shortened.chars().count() <= TOOLTIP_NAME_MAX_CHARS);
+        assert_eq!(tooltip, format!("Out: {} | In: {}", shortened, shortened));
+        assert!(tooltip.chars().count() < 128);
+    }
+
+    #[test]
+    fn sticky_profile_reverts_an_external_default_change() {
+        let config = parse_config(
+            r#"
+            [[profiles]]
+            name = "gaming"
+            device-name = "Headset (HyperX Cloud II Wireless)"
+            sticky = true
+
+            [[profiles]]
+            name = "music"
+            device-name = "Speakers (Realtek High Definition Audio)"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+1"
+            activate-profile = "gaming"
+
+            [[hotkeys]]
+            keys = "Ctrl+Alt+2"
+            activate-profile = "music"
+            "#,
+        );
+        let backend = MockBackend::new();
+        let (outputs, inputs) = (backend.output_devices.clone(), backend.input_devices.clone());
+        let mut sticky = StickyWatchdog::default();
+
+        // Sticky profile: another app makes the monitor the default, and the headset comes back
+        let outcome = switch_to_profile("gaming", &backend, &outputs, &inputs, &config);
+        sticky.update(Some(&config.hotkeys[0]), Some(&outcome), &config);
+        backend.set_default_output_device("out-monitor").unwrap();
+        sticky.on_default_changed(DeviceFlow::Output, &backend, &config);
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-headset", "out-monitor", "out-headset"]);
+
+        // Non-sticky profile: the external change stays
+        backend.output_switches.borrow_mut().clear();
+        let outcome = switch_to_profile("music", &backend, &outputs, &inputs, &config);
+        sticky.update(Some(&config.hotkeys[1]), Some(&outcome), &config);
+        backend.set_default_output_device("out-monitor").unwrap();
+        sticky.on_default_changed(DeviceFlow::Output, &backend, &config);
+        assert_eq!(*backend.output_switches.borrow(), vec!["out-speakers", "out-monitor"]);
+    }
+
+    #[test]
+    fn exited_listener_is_restarted_or_ends_the_app() {
+        // A listener whose thread runs until stopped, like the real one
+        let running = || {
+            let shutdown_signal = Arc::new(AtomicBool::new(false));
+            let signal = Arc::clone(&shutdown_signal);
+            let handle = thread::spawn(move || {
+                while !signal.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            });
+            HotkeyListener { shutdown_signal, handle: Some(handle) }
+        };
+        // A listener whose thread already exited on its own
+        let exited = || {
+            let handle = thread::spawn(|| {});
+            while !handle.is_finished() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            HotkeyListener { shutdown_signal: Arc::new(AtomicBool::new(false)), handle: Some(handle) }
+        };
+
+        let mut listener = running();
+        assert!(recover_exited_listener(&mut listener, true, || panic!("a running listener must not be replaced")));
+        listener.stop();
+
+        let mut listener = exited();
+        assert!(!recover_exited_listener(&mut listener, true, running));
+
+        let mut listener = exited();
+        assert!(recover_exited_listener(&mut listener, false, running));
+        assert!(!listener.has_exited());
+        assert!(listener.handle.is_some());
+        listener.stop();
+    }
+
+    #[test]
+    fn duplicate_keys_are_found_through_key_aliases() {
+        for (first, second) in [
+            ("Ctrl+Alt+A", "Ctrl+Alt+KeyA"),
+            ("Ctrl+Alt+1", "Ctrl+Alt+Digit1"),
+            ("Ctrl+Alt+Esc", "Alt+Ctrl+Escape"),
+            ("Ctrl+Alt+Num1", "Ctrl+Alt+Numpad1"),
+            ("Ctrl+Alt+Return", "Ctrl+Alt+Enter"),
+        ] {
+            let content = format!(
+                "on-duplicate-hotkey = \"error\"\n\n[[hotkeys]]\nkeys = \"{}\"\ndevice-name = \"Headset\"\n\n[[hotkeys]]\nkeys = \"{}\"\ndevice-name = \"Speakers\"\n",
+                first, second
+            );
+            let dir = write_config_files("duplicate_key_aliases", &[("config.toml", &content)]);
+
+            let error = config::load_config_from(&dir.join("config.toml")).unwrap_err().to_string();
+            assert!(error.contains("bind the same key combination"), "{} / {}: {}", first, second, error);
+
+            let _ = std::fs::remove_dir_all(&dir);
         }
-        std::process::exit(1);
     }
 }
@@ -0,0 +1,504 @@
+//! Resolving configured device names to devices: exact, fuzzy (Skim or Levenshtein), regex and
+//! glob matching, endpoint IDs, and the ambiguity rules around them.
+
+use crate::audio_device::AudioDevice;
+use crate::config::{AmbiguityPolicy, Config, FuzzyMatchAlgorithm};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use log::{debug, error, info, warn};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use unicode_normalization::UnicodeNormalization;
+
+/// A device scored by the configured matching algorithm (higher is better)
+pub struct ScoredCandidate<'a> {
+    pub device: &'a AudioDevice,
+    pub score: f64,
+}
+
+/// Removes every parenthesized group, including nested ones, and collapses the leftover whitespace.
+/// "Headphones (3- USB Audio (Rev 2))" becomes "Headphones". An unmatched ')' is kept as-is.
+pub fn strip_parentheticals(name: &str) -> String {
+    let mut stripped = String::with_capacity(name.len());
+    let mut depth = 0usize;
+    for c in name.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Applies the configured name preprocessing before any comparison. Names are always NFC-normalized
+/// so composed and decomposed accents (e.g. "é" vs "e\u{301}") compare equal.
+pub fn normalize_for_matching(name: &str, config: &Config) -> String {
+    let name: String = name.nfc().collect();
+    if config.ignore_parentheticals {
+        strip_parentheticals(&name)
+    } else {
+        name
+    }
+}
+
+/// Scores every available device against the target name using the configured algorithm.
+/// Returns the candidates sorted by descending score; devices that cannot match at all
+/// (no exact match, no Skim match) are omitted.
+pub fn score_candidates<'a>(
+    target_name: &str,
+    available_devices: &'a [AudioDevice],
+    config: &Config,
+) -> Vec<ScoredCandidate<'a>> {
+    // Comparison uses normalized names; the full device name is kept for display and switching.
+    // Levenshtein compares case-insensitively, so both sides are lowercased here, once per name,
+    // instead of inside the scoring loop.
+    let lowercase = config.fuzzy_match && config.fuzzy_match_algorithm == FuzzyMatchAlgorithm::Levenshtein;
+    let prepare = |name: &str| {
+        let name = normalize_for_matching(name, config);
+        if lowercase { name.to_lowercase() } else { name }
+    };
+    // Regex and wildcard patterns are used as written: stripping parentheticals would break a
+    // regex's groups and drop the parentheses a wildcard pattern matches literally
+    let original_target = target_name;
+    let target_name = prepare(target_name);
+    let target_name = target_name.as_str();
+    let device_names: Vec<String> = available_devices.iter().map(|d| prepare(&d.name)).collect();
+    let devices = available_devices.iter().zip(device_names.iter());
+
+    let mut candidates: Vec<ScoredCandidate<'a>> = if !config.fuzzy_match {
+        // Exact match mode: every exact hit scores 1.0
+        devices
+            .filter(|(_, name)| name.as_str() == target_name)
+            .map(|(device, _)| ScoredCandidate { device, score: 1.0 })
+            .collect()
+    } else {
+        match config.fuzzy_match_algorithm {
+            FuzzyMatchAlgorithm::Skim => {
+                let matcher = SkimMatcherV2::default();
+                devices
+                    .filter_map(|(device, name)| {
+                        matcher
+                            .fuzzy_match(name, target_name)
+                            .map(|score| ScoredCandidate { device, score: score as f64 })
+                    })
+                    .collect()
+            }
+            FuzzyMatchAlgorithm::Regex => match cached_regex(original_target) {
+                // Matches keep enumeration order, so the first matching device wins
+                Some(regex) => devices
+                    .filter(|(_, name)| regex.is_match(name))
+                    .map(|(device, _)| ScoredCandidate { device, score: 1.0 })
+                    .collect(),
+                None => Vec::new(),
+            },
+            FuzzyMatchAlgorithm::Glob => {
+                // Device names are NFC-normalized, so the pattern is too
+                let pattern: String = original_target.nfc().collect();
+                match cached_regex(&wildcard_to_regex(&pattern)) {
+                    Some(regex) => devices
+                        .filter(|(_, name)| regex.is_match(name))
+                        .map(|(device, _)| ScoredCandidate { device, score: 1.0 })
+                        .collect(),
+                    None => Vec::new(),
+                }
+            }
+            FuzzyMatchAlgorithm::Levenshtein => {
+                // Both names are already lowercased (see `prepare`)
+                devices
+                    .map(|(device, name)| {
+                        // Normalized Levenshtein similarity (1.0 = identical, 0.0 = completely different)
+                        let similarity = strsim::normalized_levenshtein(name, target_name);
+                        debug!(
+                            "Levenshtein similarity: '{}' vs '{}' = {:.3}",
+                            device.name, target_name, similarity
+                        );
+                        ScoredCandidate { device, score: similarity }
+                    })
+                    .collect()
+            }
+        }
+    };
+
+    // Stable sort keeps enumeration order for equal scores
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
+
+/// Translates a wildcard pattern into an equivalent regex: `*` matches any run of characters,
+/// `?` exactly one, and everything else itself. The whole name must match, ignoring case.
+pub fn wildcard_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("(?i)^");
+    let mut literal = [0u8; 4];
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut literal))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+// Compiled `regex` patterns by their config string, kept for the session. Invalid patterns are
+// cached as None so the error is only logged once.
+static REGEX_CACHE: LazyLock<Mutex<HashMap<String, Option<Regex>>>> = LazyLock::new(Default::default);
+
+// Returns the compiled pattern, or None (after logging why) if it isn't a valid regex
+fn cached_regex(pattern: &str) -> Option<Regex> {
+    let mut cache = REGEX_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .entry(pattern.to_string())
+        .or_insert_with(|| match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                error!("Invalid device name regex '{}', it matches no device: {}", pattern, e); // Log error
+                None
+            }
+        })
+        .clone()
+}
+
+/// Describes the active matching mode for logs and error messages
+pub fn match_mode_label(config: &Config) -> String {
+    if config.fuzzy_match {
+        format!("{:?} fuzzy match", config.fuzzy_match_algorithm)
+    } else {
+        "exact match".to_string()
+    }
+}
+
+// Logs a single structured record describing how a target name was resolved:
+// target, algorithm, threshold, winner, winning score and the margin to the runner-up.
+fn log_match_decision(
+    target_name: &str,
+    candidates: &[ScoredCandidate],
+    winner: Option<&ScoredCandidate>,
+    config: &Config,
+) {
+    let threshold = if config.fuzzy_match && config.fuzzy_match_algorithm == FuzzyMatchAlgorithm::Levenshtein {
+        format!("{:.3}", config.fuzzy_match_threshold)
+    } else {
+        "n/a".to_string()
+    };
+    let (winner_name, winner_score) = match winner {
+        Some(c) => (format!("'{}'", c.device.name), format!("{:.3}", c.score)),
+        None => ("none".to_string(), "n/a".to_string()),
+    };
+    let (runner_up_score, margin) = match (candidates.first(), candidates.get(1)) {
+        (Some(best), Some(second)) => (
+            format!("{:.3}", second.score),
+            format!("{:.3}", best.score - second.score),
+        ),
+        _ => ("n/a".to_string(), "n/a".to_string()),
+    };
+    info!(
+        "Match decision: target='{}' mode='{}' threshold={} candidates={} winner={} score={} runner_up={} margin={}",
+        target_name,
+        match_mode_label(config),
+        threshold,
+        candidates.len(),
+        winner_name,
+        winner_score,
+        runner_up_score,
+        margin
+    );
+}
+
+// Candidates scoring within this fraction of the best score count as a near-tie
+const NEAR_TIE_RATIO: f64 = 0.05;
+
+/// Helper function to find the best matching device using the configured fuzzy match algorithm.
+/// Returns the device with its score (see [`score_candidates`]); exact and ID matches score 1.0.
+pub fn find_best_match<'a>(
+    target_name: &str,
+    available_devices: &'a [AudioDevice],
+    config: &Config,
+) -> Option<(&'a AudioDevice, f64)> {
+    resolve_match(target_name, available_devices, config, false).ok()
+}
+
+/// Like find_best_match, but applies the input-specific heuristics (see prefer_communications_input)
+pub fn find_best_input_match<'a>(
+    target_name: &str,
+    available_devices: &'a [AudioDevice],
+    config: &Config,
+) -> Option<(&'a AudioDevice, f64)> {
+    resolve_match(target_name, available_devices, config, config.prefer_communications_input).ok()
+}
+
+/// On a tie or near-tie with the winner, prefers a candidate whose form factor marks it as a
+/// communications endpoint (headset/handset) over a raw capture endpoint
+pub fn prefer_communications_endpoint<'a, 'b>(
+    winner: &'b ScoredCandidate<'a>,
+    candidates: &'b [ScoredCandidate<'a>],
+) -> &'b ScoredCandidate<'a> {
+    if winner.device.form_factor.is_communications() {
+        return winner;
+    }
+    let near_tie_floor = winner.score - winner.score.abs() * NEAR_TIE_RATIO;
+    match candidates
+        .iter()
+        .take_while(|c| c.score >= near_tie_floor)
+        .find(|c| c.device.form_factor.is_communications())
+    {
+        Some(preferred) => {
+            info!(
+                "Preferring communications endpoint '{}' ({:?}, score {:.3}) over '{}' ({:?}, score {:.3})",
+                preferred.device.name,
+                preferred.device.form_factor,
+                preferred.score,
+                winner.device.name,
+                winner.device.form_factor,
+                winner.score
+            );
+            preferred
+        }
+        None => winner,
+    }
+}
+
+/// Why a target name did not resolve to a device
+#[derive(Debug)]
+pub enum MatchError {
+    // No candidate matched (or the best one was below the threshold)
+    NoMatch,
+    // The top candidates scored within the ambiguity margin and on-ambiguous = "refuse"
+    Ambiguous(Vec<AudioDevice>),
+    // Exact mode found more than one device with the same name and exact-first-on-ambiguous is off
+    DuplicateExact(Vec<AudioDevice>),
+}
+
+/// Returns the leading candidates if the top two score within the configured ambiguity margin
+pub fn ambiguous_candidates(candidates: &[ScoredCandidate], config: &Config) -> Option<Vec<AudioDevice>> {
+    let (best, second) = (candidates.first()?, candidates.get(1)?);
+    let margin = best.score.abs() * config.ambiguity_margin;
+    if best.score - second.score > margin {
+        return None;
+    }
+    Some(
+        candidates
+            .iter()
+            .take_while(|c| best.score - c.score <= margin)
+            .map(|c| c.device.clone())
+            .collect(),
+    )
+}
+
+/// Endpoint ID strings look like "{0.0.0.00000000}.{GUID}", which no friendly name does
+pub fn is_endpoint_id(target: &str) -> bool {
+    target.starts_with("{0.0.")
+}
+
+/// Resolves a configured name or endpoint ID to a device and its score, applying the threshold,
+/// ambiguity and duplicate rules.
+pub fn resolve_match<'a>(
+    target_name: &str,
+    available_devices: &'a [AudioDevice],
+    config: &Config,
+    prefer_communications: bool,
+) -> Result<(&'a AudioDevice, f64), MatchError> {
+    // Endpoint IDs are unique, so they are looked up directly instead of being scored
+    if is_endpoint_id(target_name) {
+        let device = available_devices.iter().find(|d| d.id.eq_ignore_ascii_case(target_name));
+        info!(
+            "Match decision: target='{}' mode='endpoint id' winner={}",
+            target_name,
+            device.map_or("none".to_string(), |d| format!("'{}'", d.name))
+        ); // Log info
+        return device.map(|d| (d, 1.0)).ok_or(MatchError::NoMatch);
+    }
+
+    let candidates = score_candidates(target_name, available_devices, config);
+
+    // Duplicate names in exact mode would otherwise resolve to whichever device enumerated first
+    if !config.fuzzy_match && candidates.len() > 1 && !config.exact_first_on_ambiguous {
+        log_match_decision(target_name, &candidates, None, config);
+        warn!(
+            "Refusing exact match for '{}': {} devices share this name: {:?}",
+            target_name,
+            candidates.len(),
+            candidates.iter().map(|c| c.device.id.as_str()).collect::<Vec<_>>()
+        );
+        return Err(MatchError::DuplicateExact(
+            candidates.iter().map(|c| c.device.clone()).collect(),
+        ));
+    }
+
+    let winner = candidates.first().filter(|best| {
+        // Only the Levenshtein algorithm applies the configurable similarity threshold
+        if config.fuzzy_match
+            && config.fuzzy_match_algorithm == FuzzyMatchAlgorithm::Levenshtein
+            && best.score < config.fuzzy_match_threshold
+        {
+            warn!(
+                "Best candidate '{}' has similarity {:.3} below threshold {:.3}",
+                best.device.name, best.score, config.fuzzy_match_threshold
+            );
+            return false;
+        }
+        true
+    });
+
+    // Refuse to guess between near-identical fuzzy candidates if configured to
+    if winner.is_some()
+        && config.fuzzy_match
+        && config.on_ambiguous == AmbiguityPolicy::Refuse
+        && let Some(tied) = ambiguous_candidates(&candidates, config)
+    {
+        log_match_decision(target_name, &candidates, None, config);
+        warn!(
+            "Refusing ambiguous match for '{}' between {:?}",
+            target_name,
+            tied.iter().map(|d| d.name.as_str()).collect::<Vec<_>>()
+        );
+        return Err(MatchError::Ambiguous(tied));
+    }
+
+    let winner = match winner {
+        Some(best) if prefer_communications => Some(prefer_communications_endpoint(best, &candidates)),
+        other => other,
+    };
+
+    log_match_decision(target_name, &candidates, winner, config);
+    winner.map(|c| (c.device, c.score)).ok_or(MatchError::NoMatch)
+}
+
+/// " (score 0.82)" for a match made by a scoring fuzzy algorithm (Skim scores are unbounded
+/// integers, Levenshtein similarities run from 0.0 to 1.0), or an empty string in exact, regex and
+/// glob mode, where every match scores 1.0.
+pub fn score_suffix(score: f64, config: &Config) -> String {
+    if !config.fuzzy_match {
+        return String::new();
+    }
+    match config.fuzzy_match_algorithm {
+        FuzzyMatchAlgorithm::Skim => format!(" (score {:.0})", score),
+        FuzzyMatchAlgorithm::Levenshtein => format!(" (score {:.2})", score),
+        FuzzyMatchAlgorithm::Regex | FuzzyMatchAlgorithm::Glob => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_device::{DeviceState, FormFactor};
+
+    fn device(id: &str, name: &str) -> AudioDevice {
+        AudioDevice {
+            id: id.to_string(),
+            name: name.to_string(),
+            form_factor: FormFactor::Unknown,
+            adapter_name: None,
+            container_id: None,
+            state: DeviceState::Active,
+        }
+    }
+
+    fn parse_config(toml_str: &str) -> Config {
+        toml::from_str(toml_str).expect("test config should parse")
+    }
+
+    #[test]
+    fn exact_match_hits_and_misses() {
+        let devices = vec![device("a", "Speakers (Realtek Audio)"), device("b", "Headset (USB Audio)")];
+        let config = parse_config("");
+        assert_eq!(find_best_match("Headset (USB Audio)", &devices, &config).map(|(d, _)| d.id.as_str()), Some("b"));
+        assert!(find_best_match("Headset", &devices, &config).is_none());
+        // Exact mode compares case-sensitively
+        assert!(find_best_match("headset (usb audio)", &devices, &config).is_none());
+    }
+
+    #[test]
+    fn skim_picks_the_highest_score() {
+        let devices = vec![device("scattered", "High Yield Power Extender"), device("contiguous", "HyperX Cloud II")];
+        let config = parse_config("fuzzy-match = true\nfuzzy-match-algorithm = \"skim\"");
+        let candidates = score_candidates("hyperx", &devices, &config);
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates[0].score > candidates[1].score);
+        assert_eq!(find_best_match("hyperx", &devices, &config).map(|(d, _)| d.id.as_str()), Some("contiguous"));
+    }
+
+    #[test]
+    fn levenshtein_respects_the_threshold() {
+        // "speakerz" vs "speakers" is one edit in eight characters: similarity 0.875
+        let devices = vec![device("a", "Speakers")];
+        let config_with = |threshold: f64| {
+            parse_config(&format!(
+                "fuzzy-match = true\nfuzzy-match-algorithm = \"levenshtein\"\nfuzzy-match-threshold = {}",
+                threshold
+            ))
+        };
+        assert_eq!(find_best_match("Speakerz", &devices, &config_with(0.87)).map(|(d, _)| d.id.as_str()), Some("a"));
+        assert!(find_best_match("Speakerz", &devices, &config_with(0.88)).is_none());
+    }
+
+    #[test]
+    fn fuzzy_matching_ignores_case() {
+        let devices = vec![device("a", "Speakers (Realtek Audio)"), device("b", "Headset (USB Audio)")];
+        let levenshtein = parse_config("fuzzy-match = true\nfuzzy-match-algorithm = \"levenshtein\"");
+        assert_eq!(find_best_match("HEADSET (usb audio)", &devices, &levenshtein).map(|(d, _)| d.id.as_str()), Some("b"));
+
+        // Skim uses smart case: an all-lowercase name ignores case, one with capitals doesn't
+        let skim = parse_config("fuzzy-match = true\nfuzzy-match-algorithm = \"skim\"");
+        assert_eq!(find_best_match("headset (usb audio)", &devices, &skim).map(|(d, _)| d.id.as_str()), Some("b"));
+        assert!(find_best_match("HEADSET (usb audio)", &devices, &skim).is_none());
+    }
+
+    #[test]
+    fn empty_device_list_matches_nothing() {
+        for toml in ["", "fuzzy-match = true", "fuzzy-match = true\nfuzzy-match-algorithm = \"levenshtein\""] {
+            assert!(find_best_match("Speakers", &[], &parse_config(toml)).is_none(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn wildcards_translate_to_anchored_regexes() {
+        assert_eq!(wildcard_to_regex("*Monitor*"), "(?i)^.*Monitor.*$");
+        assert_eq!(wildcard_to_regex("Headset ?"), "(?i)^Headset .$");
+        // Regex metacharacters in device names are matched literally
+        assert_eq!(wildcard_to_regex("Speakers (2- USB)*"), r"(?i)^Speakers \(2\- USB\).*$");
+        assert_eq!(wildcard_to_regex("a.b+c"), r"(?i)^a\.b\+c$");
+    }
+
+    #[test]
+    fn strip_parentheticals_removes_every_group() {
+        assert_eq!(strip_parentheticals("Headphones (3- USB Audio Device)"), "Headphones");
+        assert_eq!(strip_parentheticals("Speakers (Realtek) (2)"), "Speakers");
+        assert_eq!(strip_parentheticals("Line (In) Front (Rear)"), "Line Front");
+        assert_eq!(strip_parentheticals("Plain Name"), "Plain Name");
+    }
+
+    #[test]
+    fn strip_parentheticals_handles_nested_and_unbalanced_groups() {
+        assert_eq!(strip_parentheticals("Headset (HyperX (Wireless) Cloud)"), "Headset");
+        assert_eq!(strip_parentheticals("Mic (USB (Rev (2)))"), "Mic");
+        assert_eq!(strip_parentheticals("Odd) Name"), "Odd) Name");
+        assert_eq!(strip_parentheticals("Open (Name"), "Open");
+    }
+
+    #[test]
+    fn levenshtein_scores_over_many_devices_are_unchanged() {
+        let config = parse_config("fuzzy-match = true\nfuzzy-match-algorithm = \"levenshtein\"");
+        let devices: Vec<AudioDevice> = (0..200)
+            .map(|i| device(&format!("{{0.0.0.00000000}}.{{{}}}", i), &format!("Virtual Cable {} (VB-Audio Point)", i)))
+            .chain([device("headset", "Headset (HyperX Cloud II Wireless)")])
+            .collect();
+
+        let candidates = score_candidates("HEADSET (hyperx cloud ii wireless)", &devices, &config);
+
+        assert_eq!(candidates.len(), devices.len());
+        assert_eq!(candidates[0].device.id, "headset");
+        assert_eq!(candidates[0].score, 1.0);
+        // Same scores as lowercasing both names for every comparison
+        for candidate in &candidates {
+            let expected = strsim::normalized_levenshtein(
+                &candidate.device.name.to_lowercase(),
+                "headset (hyperx cloud ii wireless)",
+            );
+            assert_eq!(candidate.score, expected, "{}", candidate.device.name);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::hidden_window::HiddenWindow;
+use log::info;
+use std::cell::Cell;
+use windows::{
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::WindowsAndMessaging::{DefWindowProcW, PBT_APMRESUMEAUTOMATIC, WM_POWERBROADCAST},
+    },
+    core::{Result, w},
+};
+
+thread_local! {
+    // Set by the window procedure on resume, cleared by ResumeWatcher::take_resumed
+    static RESUMED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A hidden window that notices when the system resumes from sleep or hibernation. Power
+/// broadcasts only reach top-level windows, so it is a [`HiddenWindow`]; the thread that creates
+/// the watcher must keep pumping messages for them to arrive.
+pub struct ResumeWatcher {
+    _window: HiddenWindow,
+}
+
+impl ResumeWatcher {
+    /// Creates the window for the calling thread.
+    pub fn create() -> Result<ResumeWatcher> {
+        let window = HiddenWindow::create(w!("SoundSwitchPower"), Some(power_proc))?;
+        RESUMED.with(|resumed| resumed.set(false));
+        Ok(ResumeWatcher { _window: window })
+    }
+
+    /// Whether the system resumed since the last call.
+    pub fn take_resumed(&self) -> bool {
+        RESUMED.with(|resumed| resumed.replace(false))
+    }
+}
+
+unsafe extern "system" fn power_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    // PBT_APMRESUMEAUTOMATIC is sent on every resume, whether or not a user is present
+    if msg == WM_POWERBROADCAST && wparam.0 == PBT_APMRESUMEAUTOMATIC as usize {
+        info!("System resumed from sleep");
+        RESUMED.with(|resumed| resumed.set(true));
+        return LRESULT(1);
+    }
+    unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+}
@@ -0,0 +1,57 @@
+use crate::hidden_window::HiddenWindow;
+use log::info;
+use std::cell::RefCell;
+use windows::{
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::WindowsAndMessaging::{DefWindowProcW, WM_ENDSESSION, WM_QUERYENDSESSION},
+    },
+    core::{Result, w},
+};
+
+thread_local! {
+    // Runs once when the session ends; set by the thread that owns the watcher window
+    static ON_SESSION_END: RefCell<Option<Box<dyn FnOnce()>>> = const { RefCell::new(None) };
+}
+
+/// A hidden window that receives the logoff/shutdown notifications for the thread that creates
+/// it. Only top-level windows get `WM_QUERYENDSESSION`/`WM_ENDSESSION`, so it is a
+/// [`HiddenWindow`]; the thread must keep pumping messages for them to arrive.
+pub struct SessionEndWatcher {
+    _window: HiddenWindow,
+}
+
+impl SessionEndWatcher {
+    /// Creates the window. `on_end` runs inside the `WM_ENDSESSION` handler, because Windows may
+    /// terminate the process as soon as the handler returns; it must finish quickly.
+    pub fn create(on_end: impl FnOnce() + 'static) -> Result<SessionEndWatcher> {
+        let window = HiddenWindow::create(w!("SoundSwitchSessionEnd"), Some(session_end_proc))?;
+        ON_SESSION_END.with(|hook| *hook.borrow_mut() = Some(Box::new(on_end)));
+        Ok(SessionEndWatcher { _window: window })
+    }
+}
+
+impl Drop for SessionEndWatcher {
+    fn drop(&mut self) {
+        // Runs before the window is destroyed
+        ON_SESSION_END.with(|hook| hook.borrow_mut().take());
+    }
+}
+
+unsafe extern "system" fn session_end_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match msg {
+        // Never veto the logoff or shutdown
+        WM_QUERYENDSESSION => LRESULT(1),
+        WM_ENDSESSION => {
+            // wparam is FALSE if another application cancelled the session end
+            if wparam.0 != 0 {
+                info!("Windows session is ending (flags 0x{:X})", lparam.0);
+                if let Some(on_end) = ON_SESSION_END.with(|hook| hook.borrow_mut().take()) {
+                    on_end();
+                }
+            }
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+    }
+}
@@ -0,0 +1,232 @@
+use crate::audio_device::{
+    AudioRole, DeviceFlow, get_default_device, get_mute, get_volume, list_input_devices,
+    list_output_devices, set_default_device_for_role, set_mute, set_volume,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::PathBuf};
+
+/// Default device, volume and mute state captured for one role.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct RoleState {
+    pub role: AudioRole,
+    pub device_id: String,
+    pub device_name: String,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+/// The complete audio state (defaults for every role of both flows) at the time of capture.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct AudioSnapshot {
+    #[serde(default)]
+    pub output: Vec<RoleState>,
+    #[serde(default)]
+    pub input: Vec<RoleState>,
+}
+
+/// A snapshot together with its name, as listed by `--snapshots`.
+#[derive(Serialize, Debug)]
+pub struct NamedSnapshot {
+    pub name: String,
+    #[serde(flatten)]
+    pub snapshot: AudioSnapshot,
+}
+
+/// Returns the `snapshots` directory next to the executable.
+fn snapshots_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let mut path = std::env::current_exe()?
+        .parent()
+        .ok_or("Failed to get parent directory of executable")?
+        .to_path_buf();
+    path.push("snapshots");
+    Ok(path)
+}
+
+/// Returns the path of the snapshot file `snapshots/<name>.toml` next to the executable.
+fn snapshot_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ');
+    if !valid {
+        return Err(format!(
+            "Invalid snapshot name '{}': use letters, digits, spaces, '-' or '_'",
+            name
+        )
+        .into());
+    }
+
+    let mut path = snapshots_dir()?;
+    path.push(format!("{}.toml", name));
+    Ok(path)
+}
+
+/// Captures the default device of every role for one flow, with its volume and mute state.
+/// Roles without a default device (e.g. no microphone connected) are skipped.
+fn capture_flow(flow: DeviceFlow) -> Vec<RoleState> {
+    let mut states = Vec::new();
+    for role in AudioRole::ALL {
+        let device = match get_default_device(flow, role) {
+            Ok(device) => device,
+            Err(e) => {
+                warn!("No default {:?} device for role {:?}: {}", flow, role, e);
+                continue;
+            }
+        };
+        let volume = get_volume(&device.id);
+        let muted = get_mute(&device.id);
+        match (volume, muted) {
+            (Ok(volume), Ok(muted)) => states.push(RoleState {
+                role,
+                device_id: device.id,
+                device_name: device.name,
+                volume,
+                muted,
+            }),
+            (Err(e), _) | (_, Err(e)) => warn!(
+                "Failed to read volume state of '{}' for role {:?}: {}",
+                device.name, role, e
+            ),
+        }
+    }
+    states
+}
+
+/// Captures the current audio state of the system.
+pub fn capture_snapshot() -> AudioSnapshot {
+    AudioSnapshot {
+        output: capture_flow(DeviceFlow::Output),
+        input: capture_flow(DeviceFlow::Input),
+    }
+}
+
+/// Restores one flow of a snapshot, appending any failures to `errors`.
+fn restore_flow(flow: DeviceFlow, states: &[RoleState], errors: &mut Vec<String>) {
+    let present = match flow {
+        DeviceFlow::Output => list_output_devices(),
+        DeviceFlow::Input => list_input_devices(),
+    };
+    let present = match present {
+        Ok(devices) => devices,
+        Err(e) => {
+            errors.push(format!("Failed to list {:?} devices: {}", flow, e));
+            return;
+        }
+    };
+
+    let console_id = states
+        .iter()
+        .find(|s| s.role == AudioRole::Console)
+        .map(|s| s.device_id.as_str());
+
+    for state in states {
+        if !present.iter().any(|d| d.id == state.device_id) {
+            errors.push(format!(
+                "{:?} device '{}' ({:?}) is no longer present",
+                flow, state.device_name, state.role
+            ));
+            continue;
+        }
+
+        // Console and multimedia are set together, so the multimedia entry only needs
+        // applying when there is no console entry to carry it.
+        if state.role == AudioRole::Multimedia && console_id.is_some() {
+            if console_id != Some(state.device_id.as_str()) {
+                warn!(
+                    "Snapshot has different console and multimedia {:?} defaults; restoring the console device for both",
+                    flow
+                );
+            }
+        } else if let Err(e) = set_default_device_for_role(&state.device_id, state.role) {
+            errors.push(format!(
+                "Failed to set '{}' as {:?} default: {}",
+                state.device_name, state.role, e
+            ));
+            continue;
+        }
+
+        if let Err(e) = set_volume(&state.device_id, state.volume) {
+            errors.push(format!("Failed to set volume of '{}': {}", state.device_name, e));
+        }
+        if let Err(e) = set_mute(&state.device_id, state.muted) {
+            errors.push(format!("Failed to set mute state of '{}': {}", state.device_name, e));
+        }
+    }
+}
+
+/// Applies a snapshot: default devices per role, then their volume and mute state.
+/// Restoring continues past individual failures, which are reported together.
+pub fn restore_snapshot(snapshot: &AudioSnapshot) -> Result<(), Box<dyn Error>> {
+    let mut errors = Vec::new();
+    restore_flow(DeviceFlow::Output, &snapshot.output, &mut errors);
+    restore_flow(DeviceFlow::Input, &snapshot.input, &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; ").into())
+    }
+}
+
+/// Captures the current audio state and writes it to the named snapshot file.
+pub fn save_snapshot(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = snapshot_path(name)?;
+    let snapshot = capture_snapshot();
+    let content = toml::to_string_pretty(&snapshot)
+        .map_err(|e| format!("Failed to serialize snapshot '{}': {}", name, e))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, content)
+        .map_err(|e| format!("Failed to write snapshot file {}: {}", path.display(), e))?;
+
+    info!(
+        "Saved snapshot '{}' ({} output roles, {} input roles) to {}",
+        name,
+        snapshot.output.len(),
+        snapshot.input.len(),
+        path.display()
+    );
+    Ok(path)
+}
+
+/// Reads the named snapshot file from disk.
+pub fn load_snapshot(name: &str) -> Result<AudioSnapshot, Box<dyn Error>> {
+    let path = snapshot_path(name)?;
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read snapshot file {}: {}", path.display(), e))?;
+    let snapshot = toml::from_str(&content)
+        .map_err(|e| format!("Failed to parse snapshot file {}: {}", path.display(), e))?;
+    Ok(snapshot)
+}
+
+/// Loads every saved snapshot, sorted by name. Unreadable files are skipped with a warning.
+pub fn list_snapshots() -> Result<Vec<NamedSnapshot>, Box<dyn Error>> {
+    let dir = snapshots_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+
+    Ok(names
+        .into_iter()
+        .filter_map(|name| match load_snapshot(&name) {
+            Ok(snapshot) => Some(NamedSnapshot { name, snapshot }),
+            Err(e) => {
+                warn!("Skipping snapshot '{}': {}", name, e);
+                None
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,169 @@
+use crate::audio_device::{AudioBackend, AudioRole, DeviceFlow};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::PathBuf};
+
+const STATE_FILE_NAME: &str = "state.json";
+
+/// The default device of one role, by endpoint ID.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct RoleDevice {
+    pub role: AudioRole,
+    pub device_id: String,
+}
+
+/// The default devices per role as of the last switch SoundSwitch made, kept in `state.json`
+/// next to the executable so they can be re-applied on the next launch.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct LastUsedDevices {
+    #[serde(default)]
+    pub output: Vec<RoleDevice>,
+    #[serde(default)]
+    pub input: Vec<RoleDevice>,
+}
+
+/// Everything kept in `state.json`. The last used devices sit at the top level, so files written
+/// before the other fields existed still load.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct AppState {
+    #[serde(flatten)]
+    pub last_used: LastUsedDevices,
+    #[serde(default = "default_hotkeys_enabled")]
+    pub hotkeys_enabled: bool,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        AppState { last_used: LastUsedDevices::default(), hotkeys_enabled: default_hotkeys_enabled() }
+    }
+}
+
+fn default_hotkeys_enabled() -> bool {
+    true
+}
+
+fn state_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut path = std::env::current_exe()?
+        .parent()
+        .ok_or("Failed to get parent directory of executable")?
+        .to_path_buf();
+    path.push(STATE_FILE_NAME);
+    Ok(path)
+}
+
+/// Reads the current default device of every role. Roles without a default are left out.
+pub fn capture_last_used(backend: &dyn AudioBackend) -> LastUsedDevices {
+    let capture = |flow: DeviceFlow| -> Vec<RoleDevice> {
+        AudioRole::ALL
+            .into_iter()
+            .filter_map(|role| {
+                let device = backend.default_device(flow, role).ok()?;
+                Some(RoleDevice { role, device_id: device.id })
+            })
+            .collect()
+    };
+    LastUsedDevices {
+        output: capture(DeviceFlow::Output),
+        input: capture(DeviceFlow::Input),
+    }
+}
+
+// Reads the state file. A missing file yields `None`; an unreadable or corrupt one is logged and
+// also yields `None`, so a bad file never blocks startup.
+fn read_state() -> Option<AppState> {
+    let path = state_path().ok()?;
+    if !path.exists() {
+        return None;
+    }
+    let parsed = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+    match parsed {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("Ignoring state file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// Reads the state file, lets `update` change it and writes it back. Failures are logged and
+// otherwise ignored; the state file is best-effort.
+fn update_state(update: impl FnOnce(&mut AppState)) {
+    let mut state = read_state().unwrap_or_default();
+    update(&mut state);
+    let result = state_path().and_then(|path| {
+        let content = serde_json::to_string_pretty(&state)?;
+        fs::write(&path, content)
+            .map_err(|e| format!("Failed to write state file {}: {}", path.display(), e).into())
+    });
+    if let Err(e) = result {
+        warn!("Failed to update state file: {}", e);
+    }
+}
+
+/// Captures the current defaults and writes them to the state file, keeping its other fields.
+pub fn record_last_used(backend: &dyn AudioBackend) {
+    let last_used = capture_last_used(backend);
+    update_state(|state| state.last_used = last_used);
+}
+
+/// The last used devices from the state file, or `None` if it is missing or corrupt.
+pub fn load_last_used() -> Option<LastUsedDevices> {
+    read_state().map(|state| state.last_used)
+}
+
+/// Whether hotkeys were enabled when SoundSwitch last ran (enabled if unknown).
+pub fn load_hotkeys_enabled() -> bool {
+    read_state().is_none_or(|state| state.hotkeys_enabled)
+}
+
+/// Saves whether hotkeys are enabled, keeping the other fields of the state file.
+pub fn record_hotkeys_enabled(enabled: bool) {
+    update_state(|state| state.hotkeys_enabled = enabled);
+}
+
+/// Re-applies the saved defaults whose devices are still present and returns how many roles
+/// were set. Devices that are gone are skipped; failed switches are logged.
+pub fn restore_last_used(state: &LastUsedDevices, backend: &dyn AudioBackend) -> usize {
+    let mut restored = 0;
+    for (flow, roles) in [(DeviceFlow::Output, &state.output), (DeviceFlow::Input, &state.input)] {
+        let present = match flow {
+            DeviceFlow::Output => backend.list_output_devices(),
+            DeviceFlow::Input => backend.list_input_devices(),
+        };
+        let present = match present {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!("Failed to list {:?} devices to restore the last used ones: {}", flow, e);
+                continue;
+            }
+        };
+        let console_id = roles
+            .iter()
+            .find(|r| r.role == AudioRole::Console)
+            .map(|r| r.device_id.as_str());
+
+        for saved in roles {
+            // Console and multimedia are set together, so the console entry carries both
+            if saved.role == AudioRole::Multimedia && console_id.is_some() {
+                continue;
+            }
+            let Some(device) = present.iter().find(|d| d.id == saved.device_id) else {
+                info!("Last used {:?} device for {:?} is no longer present", flow, saved.role);
+                continue;
+            };
+            match backend.set_default_device_for_role(&device.id, saved.role) {
+                Ok(()) => restored += 1,
+                Err(e) => warn!(
+                    "Failed to restore '{}' as {:?} {:?} default: {}",
+                    device.name, flow, saved.role, e
+                ),
+            }
+        }
+    }
+    restored
+}
@@ -0,0 +1,75 @@
+use crate::audio_device::{AudioRole, get_default_input_device, get_default_output_device};
+use crate::device_history::local_timestamp;
+use crate::hotkey_manager::HotkeyRegistration;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::PathBuf};
+
+const STATUS_FILE_NAME: &str = "sound_switch.status.json";
+
+/// Snapshot of the running app for external tooling, written as JSON next to the executable.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct AppStatus {
+    pub pid: u32,
+    pub updated: String,
+    pub output_device: Option<String>,
+    pub output_device_id: Option<String>,
+    pub input_device: Option<String>,
+    pub input_device_id: Option<String>,
+    pub hotkey_count: usize,
+    #[serde(default)]
+    pub hotkeys: Vec<HotkeyRegistration>,
+}
+
+fn status_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut path = std::env::current_exe()?
+        .parent()
+        .ok_or("Failed to get parent directory of executable")?
+        .to_path_buf();
+    path.push(STATUS_FILE_NAME);
+    Ok(path)
+}
+
+/// Reads the current default (console role) devices and writes the status file.
+/// Failures are logged and otherwise ignored; the status file is best-effort.
+pub fn write_status(hotkeys: &[HotkeyRegistration]) {
+    let output = get_default_output_device(AudioRole::Console).ok();
+    let input = get_default_input_device(AudioRole::Console).ok();
+    let status = AppStatus {
+        pid: std::process::id(),
+        updated: local_timestamp(),
+        output_device: output.as_ref().map(|d| d.name.clone()),
+        output_device_id: output.map(|d| d.id),
+        input_device: input.as_ref().map(|d| d.name.clone()),
+        input_device_id: input.map(|d| d.id),
+        hotkey_count: hotkeys.iter().filter(|h| h.registered).count(),
+        hotkeys: hotkeys.to_vec(),
+    };
+
+    let result = status_path().and_then(|path| {
+        let content = serde_json::to_string_pretty(&status)?;
+        fs::write(&path, content)
+            .map_err(|e| format!("Failed to write status file {}: {}", path.display(), e).into())
+    });
+    if let Err(e) = result {
+        warn!("Failed to update status file: {}", e);
+    }
+}
+
+/// Reads the status file written by a running instance.
+pub fn read_status() -> Result<AppStatus, Box<dyn Error>> {
+    let path = status_path()?;
+    let content = fs::read_to_string(&path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Deletes the status file on graceful shutdown so tooling can tell the app is no longer running.
+pub fn remove_status_file() {
+    if let Ok(path) = status_path()
+        && path.exists()
+        && let Err(e) = fs::remove_file(&path)
+    {
+        warn!("Failed to remove status file {}: {}", path.display(), e);
+    }
+}